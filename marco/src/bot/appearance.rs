@@ -0,0 +1,268 @@
+
+//! How Marco's personality shows up in Discord, beyond his activity
+//! status.
+//!
+//! Marco can post replies through a channel webhook, under the
+//! personality's name and an avatar for its class, and can change his
+//! own nickname in every server when he rerolls. Both are optional,
+//! and both quietly fall back to the plain bot user wherever Marco
+//! lacks the Manage Webhooks or Change Nickname permission.
+//!
+//! Avatars are configured with a JSON file, named by the
+//! `MARCO_AVATARS` environment variable:
+//!
+//! ```json
+//! {
+//!   "default": "https://example.com/marco.png",
+//!   "classes": { "cowboy": "https://example.com/cowboy.png" }
+//! }
+//! ```
+//!
+//! Classes are named in snake_case, such as `mad_scientist`.
+//! Personalities without an avatar for their class (including those
+//! based on custom characters) use `default`, or else the webhook's own
+//! avatar.
+
+use super::MarcoBot;
+use super::output;
+use crate::personality::FullPersonality;
+use crate::personality::base::BasePersonality;
+use crate::environ::{self, get_env_or};
+
+use serenity::prelude::*;
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, UserId, WebhookId};
+use serenity::model::webhook::Webhook;
+use serenity::builder::{CreateWebhook, ExecuteWebhook};
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+
+/// Name of the webhook Marco creates in each channel.
+const WEBHOOK_NAME: &str = "Marco";
+
+/// Longest nickname Discord allows.
+const MAX_NICKNAME_LEN: usize = 32;
+
+/// How long Marco waits before trying again to use webhooks in a
+/// channel where he could not, in case he has since been given
+/// permission.
+const WEBHOOK_RETRY_MINUTES: i64 = 10;
+
+/// Configuration for how Marco presents his personality.
+#[derive(Debug, Clone, Default)]
+pub struct AppearanceConfig {
+  /// Whether replies in servers are posted through a webhook.
+  pub webhook_replies: bool,
+  /// Whether Marco changes his nickname in every server on reroll.
+  pub nickname_on_reroll: bool,
+  avatars: HashMap<BasePersonality, String>,
+  default_avatar: Option<String>,
+}
+
+/// The avatar file, with classes not yet parsed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct AvatarFile {
+  default: Option<String>,
+  classes: HashMap<String, String>,
+}
+
+/// The webhooks Marco posts through, by channel.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookCache {
+  channels: HashMap<ChannelId, Webhook>,
+  /// Channels where Marco could not use a webhook, such as threads or
+  /// channels where he lacks permission, and when he last tried.
+  failures: HashMap<ChannelId, chrono::DateTime<chrono::Utc>>,
+  ids: HashSet<WebhookId>,
+}
+
+impl AppearanceConfig {
+  pub fn from_env() -> Self {
+    let mut config = Self {
+      webhook_replies: get_env_or(environ::WEBHOOK_REPLIES, false),
+      nickname_on_reroll: get_env_or(environ::NICKNAME_ON_REROLL, false),
+      ..Self::default()
+    };
+    if let Ok(path) = env::var(environ::AVATARS) {
+      let text = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read avatars from {path}: {err}"));
+      let file: AvatarFile = serde_json::from_str(&text)
+        .unwrap_or_else(|err| panic!("Failed to parse avatars in {path}: {err}"));
+      for (class, url) in file.classes {
+        let class = class.trim().to_lowercase().parse()
+          .unwrap_or_else(|_| panic!("Unknown class {class:?} in avatars in {path}"));
+        config.avatars.insert(class, url);
+      }
+      config.default_avatar = file.default;
+    }
+    config
+  }
+
+  /// The avatar URL for the personality, if one is configured.
+  pub fn avatar_for(&self, personality: &FullPersonality) -> Option<&str> {
+    personality.base_personality
+      .and_then(|class| self.avatars.get(&class))
+      .or(self.default_avatar.as_ref())
+      .map(String::as_str)
+  }
+}
+
+impl WebhookCache {
+  /// Whether the webhook is one of Marco's own.
+  pub fn is_own(&self, webhook_id: WebhookId) -> bool {
+    self.ids.contains(&webhook_id)
+  }
+
+  fn insert(&mut self, channel_id: ChannelId, webhook: Webhook) {
+    self.failures.remove(&channel_id);
+    self.ids.insert(webhook.id);
+    self.channels.insert(channel_id, webhook);
+  }
+
+  fn forget(&mut self, channel_id: ChannelId) {
+    if let Some(webhook) = self.channels.remove(&channel_id) {
+      self.ids.remove(&webhook.id);
+    }
+  }
+
+  /// Whether Marco failed to use a webhook in the channel recently
+  /// enough that he should not try again yet.
+  fn failed_recently(&self, channel_id: ChannelId, now: chrono::DateTime<chrono::Utc>) -> bool {
+    self.failures.get(&channel_id)
+      .is_some_and(|failed_at| now - *failed_at < chrono::Duration::minutes(WEBHOOK_RETRY_MINUTES))
+  }
+}
+
+/// Sends `text` through the channel's webhook, under the
+/// personality's name and avatar. Only `mentions` may be pinged.
+///
+/// Returns `None` if webhook replies are disabled or unavailable in
+/// the channel (or if the first message could not be sent), in which
+/// case the caller should send the reply normally.
+pub async fn send_via_webhook(
+  bot: &MarcoBot,
+  ctx: &Context,
+  channel_id: ChannelId,
+  guild_id: Option<GuildId>,
+  personality: &FullPersonality,
+  text: &str,
+  mentions: &[UserId],
+) -> Option<Vec<Message>> {
+  if !bot.config().appearance.webhook_replies || guild_id.is_none() {
+    return None;
+  }
+  let webhook = webhook_for(bot, ctx, channel_id).await?;
+  let avatar = bot.config().appearance.avatar_for(personality);
+  let mut sent = Vec::new();
+  for part in output::split_message(text) {
+    let mut message = ExecuteWebhook::new()
+      .content(part)
+      .username(personality.name.trim())
+      .allowed_mentions(output::allowed_mentions(mentions));
+    if let Some(avatar) = avatar {
+      message = message.avatar_url(avatar);
+    }
+    match webhook.execute(&ctx.http, true, message).await {
+      Ok(message) => sent.extend(message),
+      Err(err) => {
+        println!("Error sending through webhook in channel {}: {:?}", channel_id, err);
+        // The webhook may have been deleted; look for it again next
+        // time.
+        bot.lock_state().webhooks.forget(channel_id);
+        if sent.is_empty() {
+          return None;
+        }
+        break;
+      }
+    }
+  }
+  Some(sent)
+}
+
+/// Marco's webhook in the channel, creating it if necessary.
+async fn webhook_for(bot: &MarcoBot, ctx: &Context, channel_id: ChannelId) -> Option<Webhook> {
+  {
+    let state = bot.lock_state();
+    if let Some(webhook) = state.webhooks.channels.get(&channel_id) {
+      return Some(webhook.clone());
+    }
+    if state.webhooks.failed_recently(channel_id, chrono::Utc::now()) {
+      return None;
+    }
+  }
+  let webhook = match find_webhook(ctx, channel_id).await {
+    Ok(Some(webhook)) => Ok(webhook),
+    Ok(None) => channel_id.create_webhook(ctx, CreateWebhook::new(WEBHOOK_NAME)).await,
+    Err(err) => Err(err),
+  };
+  let mut state = bot.lock_state();
+  match webhook {
+    Ok(webhook) => {
+      state.webhooks.insert(channel_id, webhook.clone());
+      Some(webhook)
+    }
+    Err(err) => {
+      println!("Cannot use webhooks in channel {}, sending replies normally: {:?}", channel_id, err);
+      state.webhooks.failures.insert(channel_id, chrono::Utc::now());
+      None
+    }
+  }
+}
+
+/// Learns Marco's existing webhook in the channel, if there is one, so
+/// that messages he sent through it before a restart are recognized
+/// as his. Unlike [`send_via_webhook`], this never creates a webhook.
+pub async fn recognize_webhook(bot: &MarcoBot, ctx: &Context, channel_id: ChannelId, guild_id: Option<GuildId>) {
+  if !bot.config().appearance.webhook_replies || guild_id.is_none() {
+    return;
+  }
+  {
+    let state = bot.lock_state();
+    if state.webhooks.channels.contains_key(&channel_id) || state.webhooks.failed_recently(channel_id, chrono::Utc::now()) {
+      return;
+    }
+  }
+  match find_webhook(ctx, channel_id).await {
+    Ok(Some(webhook)) => bot.lock_state().webhooks.insert(channel_id, webhook),
+    Ok(None) => {}
+    Err(err) => {
+      println!("Cannot use webhooks in channel {}, sending replies normally: {:?}", channel_id, err);
+      bot.lock_state().webhooks.failures.insert(channel_id, chrono::Utc::now());
+    }
+  }
+}
+
+/// Marco's existing webhook in the channel, if any.
+async fn find_webhook(ctx: &Context, channel_id: ChannelId) -> serenity::Result<Option<Webhook>> {
+  let bot_user_id = ctx.cache.current_user().id;
+  let webhooks = channel_id.webhooks(&ctx.http).await?;
+  Ok(webhooks.into_iter().find(|webhook| {
+    webhook.name.as_deref() == Some(WEBHOOK_NAME) &&
+      webhook.token.is_some() &&
+      webhook.user.as_ref().is_some_and(|user| user.id == bot_user_id)
+  }))
+}
+
+/// Changes Marco's nickname in every server to the name of his
+/// current personality, if so configured. This happens in the
+/// background, and servers where he cannot change his nickname are
+/// skipped.
+pub fn update_nicknames(bot: &MarcoBot, ctx: &Context) {
+  if !bot.config().appearance.nickname_on_reroll {
+    return;
+  }
+  let name = bot.lock_state().personality.name.trim().chars().take(MAX_NICKNAME_LEN).collect::<String>();
+  let guild_ids = ctx.cache.guilds();
+  let http = ctx.http.clone();
+  tokio::spawn(async move {
+    for guild_id in guild_ids {
+      if let Err(err) = guild_id.edit_nickname(&http, Some(&name)).await {
+        println!("Cannot change nickname in guild {}: {:?}", guild_id, err);
+      }
+    }
+  });
+}
@@ -0,0 +1,171 @@
+
+//! Archive of Marco's past personalities.
+//!
+//! While a personality is current, Marco keeps track of how much it
+//! talked and how many reactions its messages received in each guild.
+//! When it is replaced, it is archived in every guild where it was
+//! active, so that it can be browsed with `/personalities` and brought
+//! back exactly as it was with `/revive`.
+
+use crate::personality::FullPersonality;
+
+use serde::{Serialize, Deserialize};
+use serenity::model::id::{GuildId, MessageId};
+
+use std::collections::HashMap;
+
+/// Maximum number of personalities archived per guild. The oldest
+/// are forgotten first.
+pub const ARCHIVE_CAPACITY: usize = 50;
+
+/// Every archived personality, by guild.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersonalityArchive {
+  next_id: u64,
+  guilds: HashMap<GuildId, Vec<ArchivedPersonality>>,
+}
+
+/// A past personality, along with how it fared in one guild.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedPersonality {
+  /// Identifies the personality across all guilds.
+  pub id: u64,
+  pub personality: FullPersonality,
+  /// When the personality was first rolled.
+  pub rolled_at: chrono::DateTime<chrono::Utc>,
+  /// When the personality was most recently replaced.
+  pub retired_at: chrono::DateTime<chrono::Utc>,
+  /// Number of messages the personality sent in the guild.
+  pub messages: usize,
+  /// Number of reactions the personality's messages received in the
+  /// guild.
+  pub reactions: usize,
+}
+
+/// How the current personality has fared so far.
+#[derive(Debug, Clone)]
+pub struct PersonalityActivity {
+  /// The personality's ID in the archive, if it was revived from
+  /// there.
+  archive_id: Option<u64>,
+  rolled_at: chrono::DateTime<chrono::Utc>,
+  guilds: HashMap<GuildId, GuildActivity>,
+  /// Every message the personality sent, so that reactions to them
+  /// can be counted.
+  message_guilds: HashMap<MessageId, GuildId>,
+}
+
+/// How the current personality has fared in one guild.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GuildActivity {
+  pub messages: usize,
+  pub reactions: usize,
+}
+
+impl PersonalityArchive {
+  /// Archives a personality which has just been replaced, in every
+  /// guild where it was active. A revived personality updates its
+  /// existing entries rather than creating new ones. Returns the
+  /// personality's ID, if it was archived anywhere.
+  pub fn archive(&mut self, personality: &FullPersonality, activity: &PersonalityActivity) -> Option<u64> {
+    if activity.guilds.is_empty() {
+      return activity.archive_id;
+    }
+    let id = activity.archive_id.unwrap_or_else(|| {
+      self.next_id += 1;
+      self.next_id
+    });
+    let now = chrono::Utc::now();
+    for (guild_id, guild_activity) in &activity.guilds {
+      let entries = self.guilds.entry(*guild_id).or_default();
+      if let Some(entry) = entries.iter_mut().find(|entry| entry.id == id) {
+        entry.retired_at = now;
+        entry.messages += guild_activity.messages;
+        entry.reactions += guild_activity.reactions;
+        continue;
+      }
+      entries.push(ArchivedPersonality {
+        id,
+        personality: personality.clone(),
+        rolled_at: activity.rolled_at,
+        retired_at: now,
+        messages: guild_activity.messages,
+        reactions: guild_activity.reactions,
+      });
+      if entries.len() > ARCHIVE_CAPACITY {
+        entries.remove(0);
+      }
+    }
+    Some(id)
+  }
+
+  /// The personalities archived in the guild, most recently retired
+  /// first.
+  pub fn list(&self, guild_id: GuildId) -> Vec<&ArchivedPersonality> {
+    let mut entries: Vec<_> = self.guilds.get(&guild_id).into_iter().flatten().collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.retired_at));
+    entries
+  }
+
+  pub fn get(&self, guild_id: GuildId, id: u64) -> Option<&ArchivedPersonality> {
+    self.guilds.get(&guild_id)?.iter().find(|entry| entry.id == id)
+  }
+}
+
+impl PersonalityActivity {
+  /// Activity of a personality which has just been rolled, or revived
+  /// from the archive.
+  pub fn new(archive_id: Option<u64>) -> Self {
+    Self {
+      archive_id,
+      rolled_at: chrono::Utc::now(),
+      guilds: HashMap::new(),
+      message_guilds: HashMap::new(),
+    }
+  }
+
+  /// Activity of a personality revived from the archive, which keeps
+  /// the time it was first rolled.
+  pub fn revived(archived: &ArchivedPersonality) -> Self {
+    Self {
+      rolled_at: archived.rolled_at,
+      ..Self::new(Some(archived.id))
+    }
+  }
+
+  /// Activity of the same personality after it has been archived under
+  /// `archive_id` without being replaced, so that archiving it again
+  /// later does not count anything twice.
+  pub fn after_archiving(&self, archive_id: Option<u64>) -> Self {
+    Self {
+      archive_id,
+      rolled_at: self.rolled_at,
+      guilds: HashMap::new(),
+      message_guilds: self.message_guilds.clone(),
+    }
+  }
+
+  pub fn in_guild(&self, guild_id: GuildId) -> GuildActivity {
+    self.guilds.get(&guild_id).copied().unwrap_or_default()
+  }
+
+  pub fn record_message(&mut self, guild_id: GuildId, message_id: MessageId) {
+    self.guilds.entry(guild_id).or_default().messages += 1;
+    self.message_guilds.insert(message_id, guild_id);
+  }
+
+  /// Counts a reaction to the message, if the message was sent by
+  /// this personality.
+  pub fn record_reaction(&mut self, message_id: MessageId) {
+    if let Some(guild_id) = self.message_guilds.get(&message_id) {
+      self.guilds.entry(*guild_id).or_default().reactions += 1;
+    }
+  }
+}
+
+impl Default for PersonalityActivity {
+  fn default() -> Self {
+    Self::new(None)
+  }
+}
@@ -0,0 +1,101 @@
+
+//! Backfilling chat history from Discord.
+//!
+//! Message history lives only in memory, so after a restart Marco
+//! has no idea what was being discussed. The first time he sees
+//! activity in a channel, he fetches the most recent messages from
+//! Discord to fill in the gap.
+
+use super::message::{Message, MessageImage, MessageUser};
+use super::markup;
+use crate::environ::{self, get_env_or};
+
+use serenity::prelude::*;
+use serenity::cache::Cache;
+use serenity::builder::GetMessages;
+use serenity::model::channel::Message as DiscordMessage;
+use serenity::model::id::{ChannelId, GuildId, MessageId};
+
+/// Configuration for history backfill.
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+  /// Maximum number of messages to fetch. This is further limited by
+  /// the capacity of the message history. Zero disables backfill.
+  pub max_messages: usize,
+}
+
+impl BackfillConfig {
+  pub const DEFAULT_MAX_MESSAGES: usize = 10;
+
+  pub fn from_env() -> Self {
+    Self {
+      max_messages: get_env_or(environ::BACKFILL_MESSAGES, Self::DEFAULT_MAX_MESSAGES),
+    }
+  }
+}
+
+impl Default for BackfillConfig {
+  fn default() -> Self {
+    Self { max_messages: Self::DEFAULT_MAX_MESSAGES }
+  }
+}
+
+/// Fetches up to `limit` messages sent in `channel_id` before
+/// `before`, oldest first, converted to history messages.
+///
+/// Marco's own past messages, as identified by `is_from_marco`, are
+/// attributed to an unknown past personality, since the personality
+/// that sent them is long gone.
+pub async fn fetch_recent_messages(
+  ctx: &Context,
+  channel_id: ChannelId,
+  guild_id: Option<GuildId>,
+  before: MessageId,
+  is_from_marco: impl Fn(&DiscordMessage) -> bool,
+  limit: usize,
+) -> serenity::Result<Vec<Message>> {
+  // Discord caps a single fetch at 100 messages.
+  let limit = limit.min(100) as u8;
+  if limit == 0 {
+    return Ok(Vec::new());
+  }
+  let request = GetMessages::new().before(before).limit(limit);
+  let mut discord_messages = channel_id.messages(&ctx.http, request).await?;
+  discord_messages.reverse();
+  let messages = discord_messages.iter()
+    .filter(|msg| !msg.content.is_empty() || !msg.attachments.is_empty() || !msg.embeds.is_empty())
+    .map(|msg| convert_message(&ctx.cache, guild_id, is_from_marco(msg), msg))
+    .collect();
+  Ok(messages)
+}
+
+fn convert_message(cache: &Cache, guild_id: Option<GuildId>, from_marco: bool, msg: &DiscordMessage) -> Message {
+  let user = if from_marco {
+    MessageUser::Marco {
+      identity_id: MessageUser::UNKNOWN_IDENTITY_ID,
+      identity: msg.author.display_name().to_owned(),
+    }
+  } else {
+    MessageUser::DiscordUser {
+      user_id: msg.author.id,
+      user_proper_name: msg.author.name.clone(),
+      user_nickname: cached_nick(cache, guild_id, msg),
+    }
+  };
+  Message {
+    user,
+    content: markup::normalize_inbound(cache, guild_id, &msg.content, &msg.mentions),
+    images: MessageImage::from_discord_message(msg),
+    tool_calls: Vec::new(),
+  }
+}
+
+/// The author's server nickname, if it is in the cache. Unlike the
+/// nickname lookup for live messages, this never hits the Discord
+/// API, since backfill may involve many users at once.
+fn cached_nick(cache: &Cache, guild_id: Option<GuildId>, msg: &DiscordMessage) -> String {
+  guild_id
+    .and_then(|guild_id| cache.guild(guild_id))
+    .and_then(|guild| guild.members.get(&msg.author.id).and_then(|member| member.nick.clone()))
+    .unwrap_or_else(|| msg.author.name.clone())
+}
@@ -1,30 +1,51 @@
 
+use super::appearance::{self, AppearanceConfig, WebhookCache};
+use super::archive::{PersonalityArchive, PersonalityActivity, ArchivedPersonality};
 use super::message::{self, MessageHistory};
+use super::markup::{self, ResolvedText};
+use super::moderation::{self, ModerationConfig, ModerationLog, Direction};
+use super::output;
+use super::reactions::{EmojiCandidates, ReactionConfig};
 use super::passive;
+use super::backfill::{self, BackfillConfig};
+use super::streaming::{self, StreamingConfig, StreamFailure};
+use super::dm::{self, DmPersona};
+use super::ensemble::{self, Scene};
+use super::handoff::{Handoff, HandoffConfig};
+use super::settings::{GuildSettingsMap, UserSettingsMap, ThreadParticipation, ModerationLevel};
+use super::threads::{self, ThreadConfig};
+use super::tools::{DiscordTools, ToolConfig};
 use super::commands::{BotCommand, compile_default_commands};
-use crate::personality::FullPersonality;
+use crate::personality::{FullPersonality, Character, SelectionPolicy, RecentRolls, MoodConfig, MoodState,
+                         generate_personality};
 use crate::openai::DeveloperPromptConfig;
-use crate::openai::responder::chat_completion;
-use crate::openai::relevance::relevance_completion;
-use crate::openai::reaction::emoji_reaction_completion;
+use crate::openai::prompts::PromptLibrary;
+use crate::openai::vision::VisionConfig;
+use crate::openai::backend::{ModelConfig, ModelTask, TaskModel};
+use crate::openai::responder::{ChatSpeaker, ChatReply, chat_completion, lost_for_words_reply};
+use crate::openai::classifier::{Classification, classify_completion};
+use crate::storage::{JsonStore, PendingWrite};
+use crate::environ::{self, get_env_or};
+use crate::util::{CapacityDeque, RateLimiter};
 
-use async_openai::Client;
-use async_openai::config::OpenAIConfig;
 use serenity::prelude::*;
 use serenity::model::channel::Message;
 use serenity::model::gateway::Ready;
 use serenity::model::id::{UserId, GuildId, ChannelId, MessageId};
-use serenity::model::channel::{Channel, ReactionType};
+use serenity::model::channel::{Channel, Reaction};
 use serenity::model::user::User;
 use serenity::model::application::{Command, Interaction, CommandInteraction};
 use serenity::builder::{CreateMessage, CreateCommand, CreateCommandOption,
                         CreateInteractionResponse, CreateInteractionResponseMessage};
 use serenity::gateway::ActivityData;
 use async_trait::async_trait;
+use tokio::task::JoinHandle;
 
 use std::sync::{Arc, Mutex, MutexGuard};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
 
 /// An instance of this Discord bot.
 ///
@@ -38,24 +59,83 @@ pub struct MarcoBot {
 #[derive(Debug)]
 struct MarcoBotImpl {
   state: Mutex<MarcoBotState>,
-  client: Client<OpenAIConfig>,
   commands: HashMap<String, Box<dyn BotCommand>>,
-  #[expect(dead_code)] // Currently, config is not used :)
   config: MarcoBotConfig,
 }
 
-// Currently unused
-#[derive(Debug)]
-pub struct MarcoBotConfig {}
+/// Startup configuration for the bot.
+#[derive(Debug, Clone, Default)]
+pub struct MarcoBotConfig {
+  /// Directory where persistent data (such as guild settings) is
+  /// stored.
+  pub data_dir: PathBuf,
+  pub models: ModelConfig,
+  pub vision: VisionConfig,
+  pub threads: ThreadConfig,
+  pub backfill: BackfillConfig,
+  pub streaming: StreamingConfig,
+  pub rate_limits: RateLimitConfig,
+  pub tools: ToolConfig,
+  pub moderation: ModerationConfig,
+  pub reactions: ReactionConfig,
+  /// Prompt template overrides. See [`crate::openai::prompts`].
+  pub prompts: PromptLibrary,
+  pub selection: SelectionPolicy,
+  pub mood: MoodConfig,
+  pub appearance: AppearanceConfig,
+  pub handoff: HandoffConfig,
+}
+
+/// Limits on how often Marco replies.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+  /// Maximum replies per minute in any one user's DMs. DMs are not
+  /// visible to anyone else, so they are limited more strictly than
+  /// guild channels.
+  pub dm_replies_per_minute: usize,
+}
 
 /// An instance of this Discord bot's current state.
 #[derive(Debug)]
 pub struct MarcoBotState {
-  /// Incremented each time Marco generates a new personality.
+  /// Identity of the current personality. A new identity is
+  /// allocated each time Marco generates a new personality.
   pub personality_id: usize,
   pub personality: FullPersonality,
+  /// Marco's most recent previous personalities, oldest first.
+  pub past_personalities: CapacityDeque<FullPersonality>,
+  /// How the current personality has fared so far.
+  pub activity: PersonalityActivity,
+  /// How the current personality feels right now.
+  pub mood: MoodState,
+  pub archive: JsonStore<PersonalityArchive>,
   pub messages: HashMap<ChannelId, MessageHistory>,
   pub last_reference: Option<chrono::DateTime<chrono::Utc>>,
+  /// The guild channel where the current personality most recently
+  /// replied. Handoffs to the next personality happen there.
+  pub last_channel: Option<(ChannelId, GuildId)>,
+  /// The most recent change of personality.
+  pub handoff: Option<Handoff>,
+  pub guild_settings: JsonStore<GuildSettingsMap>,
+  pub user_settings: JsonStore<UserSettingsMap>,
+  /// Personalities chosen by individual users for their DMs.
+  pub dm_personas: HashMap<UserId, DmPersona>,
+  /// Users whose DM personality is currently being generated.
+  pub pending_dm_personas: HashSet<UserId>,
+  /// Ensemble scenes currently running, by channel.
+  pub scenes: HashMap<ChannelId, Scene>,
+  pub webhooks: WebhookCache,
+  pub dm_rate_limiter: RateLimiter<UserId>,
+  pub moderation_log: ModerationLog,
+  identity_counter: usize,
+}
+
+/// The kind of channel a message was sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChannelKind {
+  Guild,
+  Thread { parent_id: Option<ChannelId> },
+  Private,
 }
 
 pub fn gateway_intents() -> GatewayIntents {
@@ -65,18 +145,83 @@ pub fn gateway_intents() -> GatewayIntents {
 impl MarcoBot {
   /// Creates a new instance of this Discord bot, with the given
   /// configuration.
-  pub fn new(config: MarcoBotConfig) -> Self {
+  ///
+  /// Fails if the bot's persistent data exists but cannot be read.
+  pub fn new(config: MarcoBotConfig) -> anyhow::Result<Self> {
     let inner = MarcoBotImpl {
-      state: Mutex::new(MarcoBotState::new()),
-      client: Client::new(),
+      state: Mutex::new(MarcoBotState::load(&config)?),
       commands: compile_default_commands(),
       config,
     };
-    Self { inner: Arc::new(inner) }
+    Ok(Self { inner: Arc::new(inner) })
+  }
+
+  pub fn config(&self) -> &MarcoBotConfig {
+    &self.inner.config
+  }
+
+  /// The model used for the given task.
+  pub fn model(&self, task: ModelTask) -> &TaskModel {
+    self.inner.config.models.get(task)
+  }
+
+  /// The prompts which apply in the given guild (or in DMs, for
+  /// `None`).
+  pub fn prompts(&self, ctx: &Context, guild_id: Option<GuildId>) -> DeveloperPromptConfig {
+    let guild_name = guild_id.and_then(|guild_id| ctx.cache.guild(guild_id).map(|guild| guild.name.clone()));
+    self.config().prompts.resolve(guild_id, guild_name.as_deref())
+  }
+
+  /// Generates a new personality, avoiding the characters and tags of
+  /// recent ones. If `base_character` is given, it is used instead of
+  /// a randomly-chosen character. Random characters may include the
+  /// custom characters of `guild_id`, the guild the roll is made on
+  /// behalf of.
+  ///
+  /// This does not change Marco's personality.
+  pub async fn roll_personality(
+    &self,
+    base_character: Option<Character>,
+    guild_id: Option<GuildId>,
+  ) -> anyhow::Result<FullPersonality> {
+    self.roll_personality_avoiding(base_character, guild_id, &[]).await
+  }
+
+  /// Like [`MarcoBot::roll_personality`], but also avoids the
+  /// characters and tags of `others`, as though they had been rolled
+  /// most recently.
+  pub async fn roll_personality_avoiding(
+    &self,
+    base_character: Option<Character>,
+    guild_id: Option<GuildId>,
+    others: &[FullPersonality],
+  ) -> anyhow::Result<FullPersonality> {
+    let (recent, custom) = {
+      let state = self.lock_state();
+      let personalities = others.iter().rev()
+        .chain(std::iter::once(&state.personality))
+        .chain(state.past_personalities.iter().rev());
+      let custom = guild_id.map(|guild_id| state.guild_settings.get().get(guild_id).characters).unwrap_or_default();
+      (RecentRolls::from_personalities(personalities), custom)
+    };
+    let model = self.model(ModelTask::Personality);
+    let prompts = self.config().prompts.global();
+    let mut personality = generate_personality(model, &prompts, &self.config().selection, &recent, &custom, base_character).await?;
+    // The personality is shared between guilds, so its catchphrase is
+    // screened as strictly as any guild might want. It is optional
+    // anyway.
+    if let Some(reason) = moderation::check(self, ModerationLevel::Strict, &personality.catchphrase).await {
+      println!("Dropping the catchphrase of {} ({reason})", personality.name);
+      personality.catchphrase.clear();
+    }
+    Ok(personality)
   }
 
-  pub fn client(&self) -> &Client<OpenAIConfig> {
-    &self.inner.client
+  /// Finds a character by name, including the custom characters of
+  /// `guild_id`.
+  pub fn find_character(&self, name: &str, guild_id: Option<GuildId>) -> Option<Character> {
+    let custom = guild_id.map(|guild_id| self.lock_state().guild_settings.get().get(guild_id).characters).unwrap_or_default();
+    Character::find(name, &custom)
   }
 
   /// Locks the mutex for the bot's state and returns the guard.
@@ -86,21 +231,20 @@ impl MarcoBot {
     self.inner.state.lock().unwrap()
   }
 
+  /// Whether Marco should reply to the message. Direct mentions are
+  /// always relevant; otherwise, this waits for the message's
+  /// classification.
   async fn is_message_relevant(
     &self,
     bot_user_id: UserId,
     msg: &Message,
+    classification: JoinHandle<Option<Classification>>,
   ) -> bool {
-    if msg.mentions.iter().any(|mention| mention.id == bot_user_id) {
+    if self.is_direct_mention(bot_user_id, msg) {
       return true;
     }
-    let relevance_checker = {
-      let state = self.lock_state();
-      let config = DeveloperPromptConfig {};
-      relevance_completion(&state.personality, &msg.content, &config)
-    };
-    match relevance_checker.ask_question(self.client()).await {
-      Ok(response) => response,
+    match classification.await {
+      Ok(classification) => classification.is_some_and(|classification| classification.is_relevant()),
       Err(err) => {
         println!("Error occurred while checking message relevance: {:?}", err);
         false
@@ -108,15 +252,53 @@ impl MarcoBot {
     }
   }
 
+  /// Whether the message was sent by Marco, either as himself or
+  /// through one of his webhooks.
+  fn is_from_marco(&self, bot_user_id: UserId, msg: &Message) -> bool {
+    msg.author.id == bot_user_id ||
+      msg.webhook_id.is_some_and(|webhook_id| self.lock_state().webhooks.is_own(webhook_id))
+  }
+
+  /// Whether the message pings Marco or replies to one of his messages.
+  fn is_direct_mention(&self, bot_user_id: UserId, msg: &Message) -> bool {
+    msg.mentions.iter().any(|mention| mention.id == bot_user_id) ||
+      msg.referenced_message.as_ref().is_some_and(|referenced| self.is_from_marco(bot_user_id, referenced))
+  }
+
+  /// If Marco has no history for the message's channel yet, fetches
+  /// the messages that preceded it from Discord.
+  async fn backfill_if_new(&self, ctx: &Context, msg: &Message, bot_user_id: UserId) -> Vec<message::Message> {
+    if self.lock_state().messages.contains_key(&msg.channel_id) {
+      return Vec::new();
+    }
+    // Leave room for the message that triggered the backfill.
+    let limit = self.config().backfill.max_messages.min(MarcoBotState::MESSAGE_HISTORY_CAPACITY - 1);
+    // Marco's webhook replies from before a restart should be
+    // recognized as his.
+    appearance::recognize_webhook(self, ctx, msg.channel_id, msg.guild_id).await;
+    let is_from_marco = |msg: &Message| self.is_from_marco(bot_user_id, msg);
+    match backfill::fetch_recent_messages(ctx, msg.channel_id, msg.guild_id, msg.id, is_from_marco, limit).await {
+      Ok(messages) => messages,
+      Err(err) => {
+        println!("Error backfilling history for channel {}: {:?}", msg.channel_id, err);
+        Vec::new()
+      }
+    }
+  }
+
   async fn register_commands(&self, ctx: &Context) {
     fn compile_command(command: &dyn BotCommand) -> CreateCommand {
       let args = command.get_command_arguments()
         .into_iter()
         .map(CreateCommandOption::from)
         .collect();
-      CreateCommand::new(command.get_command_name())
+      let create_command = CreateCommand::new(command.get_command_name())
         .description(command.get_command_desc())
-        .set_options(args)
+        .set_options(args);
+      match command.get_required_permissions() {
+        Some(permissions) => create_command.default_member_permissions(permissions),
+        None => create_command,
+      }
     }
 
     let commands: Vec<_> = self.inner.commands.values()
@@ -143,36 +325,201 @@ impl MarcoBot {
   }
 }
 
+impl MarcoBotConfig {
+  /// Loads the bot configuration from environment variables.
+  pub fn from_env() -> Self {
+    let data_dir = env::var(environ::DATA_DIR).map(PathBuf::from).unwrap_or_else(|_| PathBuf::from("data"));
+    let prompts_dir = env::var(environ::PROMPTS_DIR).map(PathBuf::from).unwrap_or_else(|_| data_dir.join("prompts"));
+    let prompts = PromptLibrary::load(&prompts_dir)
+      .unwrap_or_else(|err| panic!("Failed to load prompts from {}: {err}", prompts_dir.display()));
+    Self {
+      data_dir,
+      models: ModelConfig::from_env(),
+      vision: VisionConfig::from_env(),
+      threads: ThreadConfig::from_env(),
+      backfill: BackfillConfig::from_env(),
+      streaming: StreamingConfig::from_env(),
+      rate_limits: RateLimitConfig::from_env(),
+      tools: ToolConfig::from_env(),
+      moderation: ModerationConfig::from_env(),
+      reactions: ReactionConfig::from_env(),
+      prompts,
+      selection: SelectionPolicy::from_env(),
+      mood: MoodConfig::from_env(),
+      appearance: AppearanceConfig::from_env(),
+      handoff: HandoffConfig::from_env(),
+    }
+  }
+}
+
+impl RateLimitConfig {
+  pub fn from_env() -> Self {
+    let default = Self::default();
+    Self {
+      dm_replies_per_minute: get_env_or(environ::DM_REPLIES_PER_MINUTE, default.dm_replies_per_minute),
+    }
+  }
+}
+
+impl Default for RateLimitConfig {
+  fn default() -> Self {
+    Self {
+      dm_replies_per_minute: 4,
+    }
+  }
+}
+
 impl MarcoBotState {
   pub const MESSAGE_HISTORY_CAPACITY: usize = 7;
   pub const MESSAGE_REFER_HISTORY_CAPACITY: usize = 4;
-
+  pub const PAST_PERSONALITIES_CAPACITY: usize = 10;
+  /// Identity ID of the default personality Marco starts out with,
+  /// which is just a placeholder until the first real personality is
+  /// generated. Distinct from
+  /// [`MessageUser::UNKNOWN_IDENTITY_ID`](message::MessageUser::UNKNOWN_IDENTITY_ID).
+  const PLACEHOLDER_IDENTITY_ID: usize = 1;
+
+  /// A fresh state which is not backed by persistent storage.
   pub fn new() -> Self {
+    Self::with_rate_limits(&RateLimitConfig::default())
+  }
+
+  fn with_rate_limits(rate_limits: &RateLimitConfig) -> Self {
+    const MINUTE: Duration = Duration::from_secs(60);
     Self {
       messages: HashMap::new(),
-      personality_id: 0,
+      personality_id: Self::PLACEHOLDER_IDENTITY_ID,
       personality: FullPersonality::default(),
+      past_personalities: CapacityDeque::new(Self::PAST_PERSONALITIES_CAPACITY),
+      activity: PersonalityActivity::default(),
+      mood: MoodState::default(),
+      archive: JsonStore::default(),
       last_reference: None,
+      last_channel: None,
+      handoff: None,
+      guild_settings: JsonStore::default(),
+      user_settings: JsonStore::default(),
+      dm_personas: HashMap::new(),
+      pending_dm_personas: HashSet::new(),
+      scenes: HashMap::new(),
+      webhooks: WebhookCache::default(),
+      dm_rate_limiter: RateLimiter::new(rate_limits.dm_replies_per_minute, MINUTE),
+      moderation_log: ModerationLog::default(),
+      identity_counter: Self::PLACEHOLDER_IDENTITY_ID,
     }
   }
 
+  /// Loads the persistent parts of the state from the configured
+  /// data directory.
+  pub fn load(config: &MarcoBotConfig) -> anyhow::Result<Self> {
+    Ok(Self {
+      guild_settings: JsonStore::load(config.data_dir.join("guild_settings.json"))?,
+      user_settings: JsonStore::load(config.data_dir.join("user_settings.json"))?,
+      moderation_log: ModerationLog::new(config.data_dir.join("moderation_log.jsonl")),
+      archive: JsonStore::load(config.data_dir.join("personality_archive.json"))?,
+      mood: MoodState::new(config.mood.clone()),
+      ..Self::with_rate_limits(&config.rate_limits)
+    })
+  }
+
+  /// Allocates a new identity ID, distinct from all previous ones and
+  /// from the reserved IDs, for a newly-generated personality.
+  pub fn allocate_identity_id(&mut self) -> usize {
+    self.identity_counter += 1;
+    self.identity_counter
+  }
+
   fn make_new_message_history() -> MessageHistory {
     MessageHistory::new(Self::MESSAGE_REFER_HISTORY_CAPACITY, Self::MESSAGE_HISTORY_CAPACITY)
   }
 
+  /// The message history for the given channel, creating it if it
+  /// does not exist. A newly-created history is seeded with the
+  /// recent messages of `seed_from`, if that channel has any.
+  pub fn message_history_mut(&mut self, channel_id: ChannelId, seed_from: Option<ChannelId>) -> &mut MessageHistory {
+    if !self.messages.contains_key(&channel_id) {
+      let mut history = Self::make_new_message_history();
+      if let Some(parent_history) = seed_from.and_then(|id| self.messages.get(&id)) {
+        history.seed_from(parent_history);
+      }
+      self.messages.insert(channel_id, history);
+    }
+    self.messages.get_mut(&channel_id).unwrap()
+  }
+
   pub fn refresh_activity(&self, ctx: &Context) {
-    let activity_data = ActivityData::custom(&self.personality.name);
+    let status = format!("{} ({})", self.personality.name.trim(), self.mood.mood());
+    let activity_data = ActivityData::custom(status);
     ctx.set_activity(Some(activity_data));
   }
 
-  pub fn set_personality(&mut self, personality: FullPersonality) {
+  /// Replaces the current personality, archiving the old one. The
+  /// archive should be saved after the state lock is released.
+  pub fn set_personality(&mut self, personality: FullPersonality) -> PendingWrite {
+    self.replace_personality(personality, PersonalityActivity::default())
+  }
+
+  /// Brings back an archived personality, exactly as it was.
+  pub fn revive_personality(&mut self, archived: &ArchivedPersonality) -> PendingWrite {
+    self.replace_personality(archived.personality.clone(), PersonalityActivity::revived(archived))
+  }
+
+  /// Archives the current personality without replacing it, such as
+  /// when Marco shuts down. The archive should be saved after the
+  /// state lock is released.
+  pub fn archive_current_personality(&mut self) -> PendingWrite {
+    if self.personality_id == Self::PLACEHOLDER_IDENTITY_ID {
+      return PendingWrite::nothing();
+    }
+    let (archive_id, archive_write) = self.archive.update(|archive| archive.archive(&self.personality, &self.activity));
+    self.activity = self.activity.after_archiving(archive_id);
+    archive_write
+  }
+
+  fn replace_personality(&mut self, personality: FullPersonality, activity: PersonalityActivity) -> PendingWrite {
     println!("Setting Personality: {}", personality.tagline());
     self.last_reference = None;
-    self.personality_id = self.personality_id.wrapping_add(1);
-    self.personality = personality;
+    self.mood.reset(chrono::Utc::now());
+    let had_personality = self.personality_id != Self::PLACEHOLDER_IDENTITY_ID;
+    let old_personality_id = self.personality_id;
+    self.personality_id = self.allocate_identity_id();
+    let old_personality = std::mem::replace(&mut self.personality, personality);
+    let old_activity = std::mem::replace(&mut self.activity, activity);
+    let mut archive_write = PendingWrite::nothing();
+    if had_personality {
+      archive_write = self.archive.update(|archive| archive.archive(&old_personality, &old_activity)).1;
+      let channel = self.last_channel.take();
+      let history = channel.and_then(|(channel_id, _)| self.messages.get(&channel_id));
+      self.handoff = Some(Handoff::new(old_personality_id, old_personality.clone(), channel, history));
+      self.past_personalities.push_back(old_personality);
+    }
     for message_history in self.messages.values_mut() {
       message_history.referred_messages_mut().clear();
     }
+    archive_write
+  }
+
+  pub fn set_dm_personality(&mut self, user_id: UserId, personality: FullPersonality) {
+    println!("Setting DM Personality for {}: {}", user_id, personality.tagline());
+    let identity_id = self.allocate_identity_id();
+    self.dm_personas.insert(user_id, DmPersona { identity_id, personality });
+  }
+
+  /// The identity and personality Marco speaks as in the given
+  /// channel. This is the current personality, unless `dm_user` has
+  /// chosen their own personality for their DMs.
+  pub fn speaker(&self, dm_user: Option<UserId>) -> (usize, &FullPersonality) {
+    match dm_user.and_then(|user_id| self.dm_personas.get(&user_id)) {
+      Some(persona) => (persona.identity_id, &persona.personality),
+      None => (self.personality_id, &self.personality),
+    }
+  }
+
+  /// Counts a reply against the rate limit for `dm_user`'s DMs,
+  /// returning false if the limit has been reached. Replies outside of
+  /// DMs are not limited.
+  pub fn try_acquire_reply(&mut self, dm_user: Option<UserId>) -> bool {
+    dm_user.is_none_or(|dm_user| self.dm_rate_limiter.try_acquire(dm_user))
   }
 
   pub fn mark_latest_reference(&mut self, date: chrono::DateTime<chrono::Utc>) {
@@ -202,88 +549,319 @@ impl EventHandler for MarcoBot {
       // Ignore all messages from the bot itself
       return;
     }
-
-    if is_dm(&ctx, &msg).await {
-      // Ignore DMs
+    if msg.webhook_id.is_some_and(|webhook_id| self.lock_state().webhooks.is_own(webhook_id)) {
+      // Nor replies he sent through a webhook
       return;
     }
 
-    // Spawn up an independent task to see if the bot should react
-    // (via Discord emoji) to the message.
-    tokio::spawn(do_reaction_flow(self.clone(), ctx.clone(), msg.content.to_owned(), msg.channel_id, msg.id));
+    let channel_kind = channel_kind(&ctx, &msg).await;
+    let dm_user = (channel_kind == ChannelKind::Private).then_some(msg.author.id);
+    if let Some(dm_user) = dm_user {
+      let dm_enabled = self.lock_state().user_settings.get().get(dm_user).dm_enabled;
+      if !dm_enabled {
+        // Ignore DMs from users who have not opted in
+        return;
+      }
+      if let Err(err) = dm::ensure_dm_persona(self, dm_user).await {
+        println!("Error generating DM personality: {:?}", err);
+      }
+    }
 
-    if is_thread(&ctx, &msg).await {
-      // Ignore thread messages (except for emoji reacts)
+    // Spawn up an independent task to classify the message and react
+    // (via Discord emoji) to it if appropriate. The same
+    // classification decides below whether the message is relevant.
+    let classification = tokio::spawn(
+      do_classification_flow(self.clone(), ctx.clone(), msg.content.to_owned(), msg.guild_id, dm_user, msg.channel_id, msg.id),
+    );
+
+    let thread_participation = match (channel_kind, msg.guild_id) {
+      (ChannelKind::Thread { .. }, Some(guild_id)) => {
+        let state = self.lock_state();
+        Some(state.guild_settings.get().get(guild_id).threads)
+      }
+      _ => None,
+    };
+    let relevant = match thread_participation {
+      // Every DM is addressed to Marco.
+      _ if dm_user.is_some() => true,
+      Some(ThreadParticipation::Ignore) => {
+        // Ignore thread messages (except for emoji reacts)
+        return;
+      }
+      Some(ThreadParticipation::MentionsOnly) => self.is_direct_mention(bot_user_id, &msg),
+      Some(ThreadParticipation::All) | None => self.is_message_relevant(bot_user_id, &msg, classification).await,
+    };
+    // While a scene is running in the channel, its cast answers every
+    // message from a human instead.
+    let in_scene = ensemble::wants_scene(self, msg.channel_id, msg.author.bot);
+    let relevant = relevant || in_scene;
+
+    // Screen the message before it can reach the model. Blocked
+    // messages never enter the history. The moderation endpoint is
+    // only consulted for messages Marco is about to reply to.
+    let moderation_level = moderation::level_for(self, msg.guild_id);
+    let content = markup::normalize_inbound(&ctx.cache, msg.guild_id, &msg.content, &msg.mentions);
+    let blocked_reason = if relevant {
+      moderation::check(self, moderation_level, &content).await
+    } else {
+      moderation::check_rules(&self.config().moderation, moderation_level, &content)
+    };
+    if let Some(reason) = blocked_reason {
+      moderation::record_blocked(self, msg.guild_id, msg.channel_id, msg.author.id, Direction::Inbound, reason, &content);
+      if relevant {
+        let refusal = {
+          let mut state = self.lock_state();
+          state.try_acquire_reply(dm_user).then(|| moderation::refusal(state.speaker(dm_user).1))
+        };
+        if let Some(refusal) = refusal {
+          let mut new_message = CreateMessage::default();
+          if !msg.author.bot {
+            new_message = new_message.reference_message(&msg);
+          }
+          if let Err(why) = output::send_reply(&ctx, msg.channel_id, new_message, &refusal, &[]).await {
+            println!("Error sending message: {:?}", why);
+          }
+        }
+      }
       return;
     }
 
-    let mut responder = None;
-    {
-      let relevant = self.is_message_relevant(bot_user_id, &msg).await;
-      let nick = get_nick(&ctx, &msg.author, msg.guild_id).await;
+    let nick = get_nick(&ctx, &msg.author, msg.guild_id).await;
+    let backfilled_messages = self.backfill_if_new(&ctx, &msg, bot_user_id).await;
+    let spin_off = {
       let mut state = self.lock_state();
       let message = message::Message {
         user: message::MessageUser::DiscordUser {
           user_id: msg.author.id,
           user_proper_name: msg.author.name.clone(),
-          user_nickname: nick,
+          user_nickname: nick.clone(),
         },
-        content: msg.content.to_owned(),
+        content,
+        images: message::MessageImage::from_discord_message(&msg),
+        tool_calls: Vec::new(),
+      };
+      let seed_from = match channel_kind {
+        ChannelKind::Thread { parent_id } if self.config().threads.seed_from_parent => parent_id,
+        _ => None,
       };
-      let message_history = state.messages.entry(msg.channel_id)
-        .or_insert_with(MarcoBotState::make_new_message_history);
+      // Another message may have backfilled this channel while we
+      // were waiting on Discord, so only use ours if the history is
+      // still new.
+      let is_new_history = !state.messages.contains_key(&msg.channel_id);
+      let message_history = state.message_history_mut(msg.channel_id, seed_from);
+      if is_new_history {
+        for backfilled_message in backfilled_messages {
+          message_history.push_back(backfilled_message, false);
+        }
+      }
       message_history.push_back(message, relevant);
-      if relevant {
-        let config = DeveloperPromptConfig {};
-        state.mark_latest_reference(chrono::Utc::now());
-        // Re-borrow as immutable.
-        let message_history = state.messages.get(&msg.channel_id).unwrap();
-        responder = Some(
-          chat_completion(
-            state.personality_id,
-            &state.personality,
-            message_history.messages().iter(),
-            message_history.referred_messages().iter(),
-            &config,
-          ).with_typing_notification(&ctx, msg.channel_id),
-        );
+      relevant && !in_scene && channel_kind == ChannelKind::Guild &&
+        threads::is_long_exchange(message_history, msg.author.id, self.config().threads.auto_thread_after)
+    };
+
+    if in_scene {
+      ensemble::play_round(self, &ctx, msg.channel_id, msg.guild_id, msg.author.id).await;
+      return;
+    }
+
+    // If Marco has been going back and forth with one user for a
+    // while, move the conversation into its own thread.
+    let mut reply_channel_id = msg.channel_id;
+    if spin_off {
+      let personality_name = self.lock_state().personality.name.clone();
+      match threads::spin_off_thread(&ctx, &msg, &nick, &personality_name).await {
+        Ok(thread) => {
+          let mut state = self.lock_state();
+          state.message_history_mut(thread.id, Some(msg.channel_id));
+          reply_channel_id = thread.id;
+        }
+        Err(err) => {
+          println!("Error creating thread: {:?}", err);
+        }
       }
     }
+
+    let mut responder = None;
+    if relevant {
+      let config = self.prompts(&ctx, msg.guild_id);
+      let mut state = self.lock_state();
+      if !state.try_acquire_reply(dm_user) {
+        println!("Rate limit exceeded in channel {}; not replying", reply_channel_id);
+        return;
+      }
+      let is_shared = dm_user.is_none_or(|dm_user| !state.dm_personas.contains_key(&dm_user));
+      if is_shared {
+        // Only the shared personality counts as being spoken to.
+        let now = chrono::Utc::now();
+        state.mark_latest_reference(now);
+        if let Some(guild_id) = msg.guild_id {
+          state.last_channel = Some((reply_channel_id, guild_id));
+        }
+        if state.mood.record_ping(now) {
+          state.refresh_activity(&ctx);
+        }
+      }
+      let mood = is_shared.then(|| state.mood.mood());
+      let handoff = state.handoff.as_ref()
+        .filter(|_| is_shared)
+        .and_then(|handoff| handoff.recent_note(msg.guild_id, chrono::Utc::now()));
+      let (identity_id, personality) = state.speaker(dm_user);
+      // Remember who is speaking now, since a tool call may reroll
+      // Marco's personality before the reply is finished.
+      let speaker = message::MessageUser::Marco {
+        identity_id,
+        identity: personality.name.clone(),
+      };
+      let message_history = state.messages.get(&reply_channel_id).unwrap();
+      responder = Some((
+        chat_completion(
+          self.model(ModelTask::Chat),
+          ChatSpeaker { identity_id, personality, mood, handoff },
+          message_history.messages().iter(),
+          message_history.referred_messages().iter(),
+          &config,
+          &self.config().vision,
+        ).with_typing_notification(&ctx, reply_channel_id),
+        speaker,
+      ));
+    }
     // Note: Drop mutex here so we don't hold it over an OpenAI await boundary.
-    if let Some(responder) = responder {
-      let resp = match responder.chat(self.client()).await {
-        Ok(resp) => resp,
-        Err(e) => {
-          println!("Error from OpenAI: {:?}", e);
-          return;
+    if let Some((responder, speaker)) = responder {
+      let known_users: Vec<(UserId, String)> = {
+        let state = self.lock_state();
+        state.messages.get(&reply_channel_id)
+          .map(|history| history.participants().map(|(user_id, name)| (user_id, name.to_owned())).collect())
+          .unwrap_or_default()
+      };
+      let (personality, refusal) = {
+        let state = self.lock_state();
+        let personality = state.speaker(dm_user).1.clone();
+        let refusal = moderation::refusal(&personality);
+        (personality, refusal)
+      };
+      let render = |text: &str| {
+        // The local rules are cheap enough to check on every edit of a
+        // streamed reply.
+        if moderation::check_rules(&self.config().moderation, moderation_level, text).is_some() {
+          return ResolvedText { text: refusal.clone(), mentions: Vec::new() };
         }
+        let known_users = known_users.iter().map(|(user_id, name)| (*user_id, name.as_str()));
+        markup::resolve_outbound(&ctx.cache, msg.guild_id, &output::redact_secrets(text), known_users)
       };
-      {
-        let mut state = self.lock_state();
-        let user = message::MessageUser::Marco {
-          identity_id: state.personality_id,
-          identity: state.personality.name.clone(),
-        };
-        let messages = state.messages.entry(msg.channel_id)
-          .or_insert_with(MarcoBotState::make_new_message_history);
-        messages.push_back(message::Message {
-          user,
-          content: resp.clone(),
-        }, true);
-      }
-      let mut resp = CreateMessage::default()
-        .content(resp);
+      let mut new_message = CreateMessage::default();
       // I would love to reply to all messages, but replying to bots
       // causes an infinite loop WAY too often. This is a stop-gap.
-      if !msg.author.bot {
-        resp = resp.reference_message(&msg);
+      if !msg.author.bot && reply_channel_id == msg.channel_id {
+        new_message = new_message.reference_message(&msg);
       }
-      if let Err(why) = msg.channel_id.send_message(&ctx.http, resp).await {
-        println!("Error sending message: {:?}", why);
+
+      let tools = DiscordTools::for_message(self, &ctx, &msg, reply_channel_id, dm_user).await;
+
+      // The moderation endpoint has to see the whole reply before
+      // anyone else does, so it rules out streaming. So do webhooks,
+      // which cannot show Marco typing.
+      let endpoint_moderation = self.config().moderation.use_endpoint && moderation_level != ModerationLevel::Off;
+      let webhook_replies = self.config().appearance.webhook_replies && msg.guild_id.is_some();
+      let mut resp = None;
+      // Tools that already ran while streaming. They are not run again
+      // by the one-shot fallback.
+      let mut tools_already_run = Vec::new();
+      if self.config().streaming.enabled && !endpoint_moderation && !webhook_replies {
+        let streamed = match responder.chat_stream(self.model(ModelTask::Chat), &tools).await {
+          Ok(reply) => streaming::stream_reply(&ctx, reply_channel_id, new_message.clone(), reply, render, &self.config().streaming).await,
+          Err(err) => Err(StreamFailure { error: err.into(), tool_calls: Vec::new() }),
+        };
+        match streamed {
+          Ok((mut reply, message_ids)) => {
+            if let Some(reason) = moderation::check_rules(&self.config().moderation, moderation_level, &reply.text) {
+              moderation::record_blocked(self, msg.guild_id, reply_channel_id, msg.author.id, Direction::Outbound, reason, &reply.text);
+              reply.text = refusal.clone();
+            }
+            resp = Some((reply, message_ids));
+          }
+          Err(failure) => {
+            println!("Error streaming response, falling back to one-shot: {:?}", failure.error);
+            tools_already_run = failure.tool_calls;
+          }
+        }
       }
+      let resp = match resp {
+        Some(resp) => resp,
+        None => {
+          let chat = if tools_already_run.is_empty() {
+            responder.chat(self.model(ModelTask::Chat), &tools).await
+          } else {
+            // Asking again would run the same tools a second time, so
+            // give up on a reply, but remember what the tools did.
+            Ok(ChatReply { text: lost_for_words_reply(&personality), tool_calls: tools_already_run })
+          };
+          let mut resp = match chat {
+            Ok(resp) => resp,
+            Err(e) => {
+              println!("Error from OpenAI: {:?}", e);
+              let fallback = {
+                let state = self.lock_state();
+                lost_for_words_reply(state.speaker(dm_user).1)
+              };
+              if let Err(why) = output::send_reply(&ctx, reply_channel_id, new_message, &fallback, &[]).await {
+                println!("Error sending message: {:?}", why);
+              }
+              return;
+            }
+          };
+          if resp.text.trim().is_empty() {
+            // An empty reply would not be sent at all.
+            resp.text = lost_for_words_reply(&personality);
+          }
+          if let Some(reason) = moderation::check(self, moderation_level, &resp.text).await {
+            moderation::record_blocked(self, msg.guild_id, reply_channel_id, msg.author.id, Direction::Outbound, reason, &resp.text);
+            resp.text = refusal.clone();
+          }
+          let rendered = render(&resp.text);
+          let sent = match appearance::send_via_webhook(self, &ctx, reply_channel_id, msg.guild_id, &personality, &rendered.text, &rendered.mentions).await {
+            Some(sent) => Ok(sent),
+            None => output::send_reply(&ctx, reply_channel_id, new_message, &rendered.text, &rendered.mentions).await,
+          };
+          let message_ids = match sent {
+            Ok(sent) => sent.iter().map(|message| message.id).collect(),
+            Err(why) => {
+              println!("Error sending message: {:?}", why);
+              Vec::new()
+            }
+          };
+          (resp, message_ids)
+        }
+      };
+      let (resp, message_ids) = resp;
+
+      let mut state = self.lock_state();
+      if let (Some(guild_id), message::MessageUser::Marco { identity_id, .. }) = (msg.guild_id, &speaker) {
+        // Only count messages from the personality that is still
+        // current.
+        if *identity_id == state.personality_id {
+          for message_id in message_ids {
+            state.activity.record_message(guild_id, message_id);
+          }
+        }
+      }
+      let messages = state.message_history_mut(reply_channel_id, None);
+      messages.push_back(message::Message {
+        user: speaker,
+        content: resp.text,
+        images: Vec::new(),
+        tool_calls: resp.tool_calls,
+      }, true);
     }
   }
 
+  async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+    if reaction.user_id == Some(ctx.cache.current_user().id) {
+      // Marco's own reactions don't count.
+      return;
+    }
+    self.lock_state().activity.record_reaction(reaction.message_id);
+  }
+
   async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
     let Interaction::Command(interaction) = interaction else {
       eprintln!("Got unknown interaction {:?}... ignoring", interaction);
@@ -307,22 +885,17 @@ impl EventHandler for MarcoBot {
       let state = self.lock_state();
       state.refresh_activity(&ctx);
     }
+    appearance::update_nicknames(self, &ctx);
     self.register_commands(&ctx).await;
     passive::schedule_reroll_task(self.clone(), ctx);
   }
 }
 
-async fn is_dm(ctx: &Context, msg: &Message) -> bool {
+async fn channel_kind(ctx: &Context, msg: &Message) -> ChannelKind {
   match msg.channel(&ctx).await {
-    Ok(Channel::Private(_)) => true,
-    _ => false,
-  }
-}
-
-async fn is_thread(ctx: &Context, msg: &Message) -> bool {
-  match msg.channel(&ctx).await {
-    Ok(Channel::Guild(ch)) => ch.thread_metadata.is_some(),
-    _ => false,
+    Ok(Channel::Private(_)) => ChannelKind::Private,
+    Ok(Channel::Guild(ch)) if ch.thread_metadata.is_some() => ChannelKind::Thread { parent_id: ch.parent_id },
+    _ => ChannelKind::Guild,
   }
 }
 
@@ -331,33 +904,65 @@ async fn get_nick(ctx: &Context, user: &User, guild: Option<GuildId>) -> String
   user.nick_in(ctx, guild).await.unwrap_or_else(|| user.name.clone())
 }
 
-async fn do_reaction_flow(
+/// Classifies the message, reacting to it with an emoji if the
+/// classifier suggests one.
+async fn do_classification_flow(
   bot: MarcoBot,
   ctx: Context,
   message_content: String,
+  guild_id: Option<GuildId>,
+  dm_user: Option<UserId>,
   channel_id: ChannelId,
   message_id: MessageId,
-) {
-  async fn do_reaction_flow_impl(
-    bot: MarcoBot,
-    ctx: Context,
-    message_content: String,
-    channel_id: ChannelId,
-    message_id: MessageId,
-  ) -> anyhow::Result<()> {
-    let reaction_checker = emoji_reaction_completion(&message_content, &DeveloperPromptConfig {});
-    let emoji_response = reaction_checker.ask_question(bot.client()).await?;
-    let Some(emoji_response) = emoji_response else {
-      return Ok(()); // Nothing to react with.
+) -> Option<Classification> {
+  async fn do_classification_flow_impl(
+    bot: &MarcoBot,
+    message_content: &str,
+    dm_user: Option<UserId>,
+    emoji_candidates: &EmojiCandidates,
+    config: &DeveloperPromptConfig,
+  ) -> anyhow::Result<Classification> {
+    let emoji_list = emoji_candidates.prompt_list(message_content, bot.config().reactions.max_custom_emoji);
+    let classifier = {
+      let state = bot.lock_state();
+      let (_, personality) = state.speaker(dm_user);
+      classify_completion(
+        bot.model(ModelTask::Classifier),
+        personality,
+        message_content,
+        &emoji_list,
+        config,
+      )
     };
-    ctx.http
-      .create_reaction(channel_id, message_id, &ReactionType::Unicode(emoji_response))
-      .await?;
-    Ok(())
+    classifier.classify(bot.model(ModelTask::Classifier)).await
+  }
+  let emoji_candidates = EmojiCandidates::for_guild(&ctx, guild_id);
+  let prompts = bot.prompts(&ctx, guild_id);
+  let classification = match do_classification_flow_impl(&bot, &message_content, dm_user, &emoji_candidates, &prompts).await {
+    Ok(classification) => classification,
+    Err(err) => {
+      println!("Error while classifying message: {:?}", err);
+      return None;
+    }
+  };
+  if guild_id.is_some() {
+    // Only guild conversations affect the shared personality's mood;
+    // DMs have their own personalities.
+    let mut state = bot.lock_state();
+    if state.mood.record_sentiment(classification.sentiment, chrono::Utc::now()) {
+      state.refresh_activity(&ctx);
+    }
   }
-  if let Err(err) = do_reaction_flow_impl(bot, ctx, message_content, channel_id, message_id).await {
-    println!("Error while doing reaction flow: {:?}", err);
+  if let Some(emoji) = classification.reaction() {
+    let Some(reaction) = emoji_candidates.resolve(emoji) else {
+      println!("Ignoring reaction {:?}, which is not one of the candidate emoji", emoji);
+      return Some(classification);
+    };
+    if let Err(err) = ctx.http.create_reaction(channel_id, message_id, &reaction).await {
+      println!("Error while reacting to message: {:?}", err);
+    }
   }
+  Some(classification)
 }
 
 async fn send_invalid_command_response(ctx: &Context, interaction: CommandInteraction) -> serenity::Result<()> {
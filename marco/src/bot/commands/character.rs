@@ -0,0 +1,181 @@
+
+use super::{BotCommand, CommandOption, get_subcommand, find_option, respond, subcommand};
+use crate::bot::MarcoBot;
+use crate::bot::moderation;
+use crate::bot::output::truncate_message;
+use crate::bot::settings::ModerationLevel;
+use crate::personality::CustomCharacter;
+use crate::personality::custom::MAX_CUSTOM_CHARACTERS;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOption,
+                                   CommandDataOptionValue};
+use serenity::model::id::GuildId;
+use serenity::model::permissions::Permissions;
+use async_trait::async_trait;
+
+use std::fmt::{Debug, Write};
+
+/// "Character" command to manage the guild's custom characters.
+#[derive(Debug, Clone, Default)]
+pub struct CharacterCommand;
+
+#[async_trait]
+impl BotCommand for CharacterCommand {
+  fn get_command_name(&self) -> &str {
+    "character"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Manages the custom characters Marco can play in this server."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      subcommand("add", "Adds a custom character", vec![
+        string_option("name", "Who the character is", true),
+        string_option("class", "What kind of character it is, such as \"Detective\"", true),
+        string_option("description", "How the character talks and behaves", true),
+      ]),
+      subcommand("edit", "Changes a custom character", vec![
+        string_option("name", "Name of the character to change", true),
+        string_option("class", "New class for the character", false),
+        string_option("description", "New description for the character", false),
+      ]),
+      subcommand("remove", "Removes a custom character", vec![
+        string_option("name", "Name of the character to remove", true),
+      ]),
+      subcommand("list", "Lists the custom characters", Vec::new()),
+    ]
+  }
+
+  fn get_required_permissions(&self) -> Option<Permissions> {
+    Some(Permissions::MANAGE_GUILD)
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let Some(guild_id) = interaction.guild_id else {
+      respond(ctx, &interaction, "This command can only be used in a server.", true).await?;
+      return Ok(());
+    };
+    let Some((subcommand, options)) = get_subcommand(&interaction.data) else {
+      panic!("Expected a subcommand, per command arguments");
+    };
+    let content = match subcommand {
+      "add" => add_character(bot, guild_id, options)?,
+      "edit" => edit_character(bot, guild_id, options)?,
+      "remove" => remove_character(bot, guild_id, options)?,
+      "list" => list_characters(bot, guild_id)?,
+      _ => panic!("Unknown subcommand {subcommand:?}"),
+    };
+    respond(ctx, &interaction, &truncate_message(&content), true).await?;
+    Ok(())
+  }
+}
+
+fn add_character(bot: &MarcoBot, guild_id: GuildId, options: &[CommandDataOption]) -> anyhow::Result<String> {
+  let character = CustomCharacter {
+    name: string_value(options, "name").unwrap_or_default().trim().to_owned(),
+    class: string_value(options, "class").unwrap_or_default().trim().to_owned(),
+    description: string_value(options, "description").unwrap_or_default().trim().to_owned(),
+  };
+  // Look up the moderation level first; it needs the state lock too.
+  let level = moderation::level_for(bot, Some(guild_id));
+  let mut state = bot.lock_state();
+  let existing = state.guild_settings.get().get(guild_id).characters;
+  if existing.len() >= MAX_CUSTOM_CHARACTERS {
+    return Ok(format!("This server already has {MAX_CUSTOM_CHARACTERS} characters. Remove one first."));
+  }
+  let problems = validate(bot, level, &character, &existing);
+  if !problems.is_empty() {
+    return Ok(problems.join("\n"));
+  }
+  let name = character.name.clone();
+  let settings_write = state.guild_settings.update(|settings| settings.get_mut(guild_id).characters.push(character)).1;
+  drop(state);
+  settings_write.save()?;
+  Ok(format!("Added {name}. Use `/reroll {name}` to try them out."))
+}
+
+fn edit_character(bot: &MarcoBot, guild_id: GuildId, options: &[CommandDataOption]) -> anyhow::Result<String> {
+  let name = string_value(options, "name").unwrap_or_default();
+  let level = moderation::level_for(bot, Some(guild_id));
+  let mut state = bot.lock_state();
+  let mut others = state.guild_settings.get().get(guild_id).characters;
+  let Some(index) = others.iter().position(|character| character.matches_name(name)) else {
+    return Ok(format!("There is no character called {}.", name.trim()));
+  };
+  let mut character = others.remove(index);
+  if let Some(class) = string_value(options, "class") {
+    character.class = class.trim().to_owned();
+  }
+  if let Some(description) = string_value(options, "description") {
+    character.description = description.trim().to_owned();
+  }
+  let problems = validate(bot, level, &character, &others);
+  if !problems.is_empty() {
+    return Ok(problems.join("\n"));
+  }
+  let name = character.name.clone();
+  let settings_write = state.guild_settings.update(|settings| settings.get_mut(guild_id).characters[index] = character).1;
+  drop(state);
+  settings_write.save()?;
+  Ok(format!("Updated {name}."))
+}
+
+fn remove_character(bot: &MarcoBot, guild_id: GuildId, options: &[CommandDataOption]) -> anyhow::Result<String> {
+  let name = string_value(options, "name").unwrap_or_default();
+  let mut state = bot.lock_state();
+  let (removed, settings_write) = state.guild_settings.update(|settings| {
+    let characters = &mut settings.get_mut(guild_id).characters;
+    let index = characters.iter().position(|character| character.matches_name(name))?;
+    Some(characters.remove(index))
+  });
+  drop(state);
+  settings_write.save()?;
+  Ok(match removed {
+    Some(character) => format!("Removed {}.", character.name),
+    None => format!("There is no character called {}.", name.trim()),
+  })
+}
+
+fn list_characters(bot: &MarcoBot, guild_id: GuildId) -> anyhow::Result<String> {
+  let characters = bot.lock_state().guild_settings.get().get(guild_id).characters;
+  if characters.is_empty() {
+    return Ok(String::from("This server has no custom characters. Add one with `/character add`."));
+  }
+  let mut content = format!("Custom characters ({} of {MAX_CUSTOM_CHARACTERS}):", characters.len());
+  for character in characters {
+    write!(content, "\n- **{}** ({}): {}", character.name, character.class, character.description)?;
+  }
+  Ok(content)
+}
+
+/// Problems with the character, including text that the guild's
+/// moderation rules would block.
+fn validate(bot: &MarcoBot, level: ModerationLevel, character: &CustomCharacter, others: &[CustomCharacter]) -> Vec<String> {
+  let mut problems = character.validate(others);
+  let text = format!("{}\n{}\n{}", character.name, character.class, character.description);
+  if moderation::check_rules(&bot.config().moderation, level, &text).is_some() {
+    problems.push(String::from("The character breaks this server's moderation rules."));
+  }
+  problems
+}
+
+fn string_value<'d>(options: &'d [CommandDataOption], name: &str) -> Option<&'d str> {
+  match find_option(options, name)? {
+    CommandDataOptionValue::String(value) => Some(value),
+    _ => panic!("Expected a string, per command arguments"),
+  }
+}
+
+fn string_option(name: &str, description: &str, is_required: bool) -> CommandOption {
+  CommandOption {
+    kind: CommandOptionType::String,
+    name: String::from(name),
+    description: String::from(description),
+    is_required,
+    choices: Vec::new(),
+    options: Vec::new(),
+  }
+}
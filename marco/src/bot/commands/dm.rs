@@ -0,0 +1,114 @@
+
+use super::{BotCommand, CommandOption, get_option};
+use crate::bot::MarcoBot;
+use crate::personality::BaseCharacter;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage,
+                        EditInteractionResponse};
+use async_trait::async_trait;
+
+use std::fmt::Debug;
+
+/// "DM" command to opt in to (or out of) direct messages with Marco.
+#[derive(Debug, Clone, Default)]
+pub struct DmCommand;
+
+#[async_trait]
+impl BotCommand for DmCommand {
+  fn get_command_name(&self) -> &str {
+    "dm"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Enables or disables chatting with Marco in direct messages."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      CommandOption {
+        kind: CommandOptionType::Boolean,
+        name: String::from("enabled"),
+        description: String::from("Whether Marco should respond to your DMs"),
+        is_required: true,
+        choices: Vec::new(),
+        options: Vec::new(),
+      },
+      CommandOption {
+        kind: CommandOptionType::String,
+        name: String::from("character_name"),
+        description: String::from("Character template for Marco to use in your DMs"),
+        is_required: false,
+        choices: Vec::new(),
+        options: Vec::new(),
+      },
+    ]
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let initial_response = CreateInteractionResponseMessage::default()
+      .ephemeral(true);
+    interaction.create_response(&ctx.http, CreateInteractionResponse::Defer(initial_response)).await?;
+
+    let Some(CommandDataOptionValue::Boolean(enabled)) = get_option(&interaction.data, "enabled") else {
+      panic!("Expected a boolean, per command arguments");
+    };
+    let user_id = interaction.user.id;
+    let already_enabled = bot.lock_state().user_settings.get().get(user_id).dm_enabled;
+    if *enabled && !already_enabled && interaction.guild_id.is_none() {
+      // Requiring a server the first time ensures Marco only talks to
+      // people he shares a server with.
+      let final_response = EditInteractionResponse::default()
+        .content("Please enable DMs from a server we're both in first.");
+      interaction.edit_response(&ctx.http, final_response).await?;
+      return Ok(());
+    }
+
+    let mut character = None;
+    if let Some(data_value) = get_option(&interaction.data, "character_name") {
+      let CommandDataOptionValue::String(data_value) = data_value else {
+        panic!("Expected a string, per command arguments");
+      };
+      let Ok(character_name) = data_value.trim().to_lowercase().parse::<BaseCharacter>() else {
+        let final_response = EditInteractionResponse::default()
+          .content("I don't know who that is, sorry");
+        interaction.edit_response(&ctx.http, final_response).await?;
+        return Ok(());
+      };
+      character = Some(character_name);
+    }
+
+    let new_personality = match character {
+      Some(character) if *enabled => Some(bot.roll_personality(Some(character.into()), None).await?),
+      _ => None,
+    };
+    let (content, settings_write) = {
+      let mut state = bot.lock_state();
+      let ((), settings_write) = state.user_settings.update(|settings| {
+        let settings = settings.get_mut(user_id);
+        settings.dm_enabled = *enabled;
+        if character.is_some() {
+          settings.dm_character = character;
+        }
+      });
+      let content = if !*enabled {
+        state.dm_personas.remove(&user_id);
+        String::from("Okay, I won't respond to your DMs anymore.")
+      } else if let Some(new_personality) = new_personality {
+        let name = new_personality.name.trim().to_owned();
+        state.set_dm_personality(user_id, new_personality);
+        format!("DMs enabled! {name} will be waiting for you.")
+      } else {
+        String::from("DMs enabled! Send me a message any time.")
+      };
+      (content, settings_write)
+    };
+    settings_write.save()?;
+
+    let final_response = EditInteractionResponse::default()
+      .content(content);
+    interaction.edit_response(&ctx.http, final_response).await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,191 @@
+
+use super::{BotCommand, CommandOption, get_subcommand, find_option, respond, subcommand};
+use crate::bot::MarcoBot;
+use crate::bot::moderation;
+use crate::bot::message::{Message, MessageUser};
+use crate::bot::ensemble::{self, Scene, CastMember, MIN_CAST_SIZE, MAX_CAST_SIZE, DEFAULT_TURNS, MAX_TURNS};
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOption,
+                                   CommandDataOptionValue};
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseMessage,
+                        EditInteractionResponse};
+use async_trait::async_trait;
+
+use std::fmt::Debug;
+
+/// "Ensemble" command to start (or stop) a scene with several
+/// personalities in a channel.
+#[derive(Debug, Clone, Default)]
+pub struct EnsembleCommand;
+
+#[async_trait]
+impl BotCommand for EnsembleCommand {
+  fn get_command_name(&self) -> &str {
+    "ensemble"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Starts a scene where several of Marco's personalities talk in this channel."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      subcommand("start", "Starts a scene in this channel", vec![
+        option(CommandOptionType::String, "premise", "What the scene is about"),
+        option(CommandOptionType::Integer, "cast", "How many personalities take part (2 or 3)"),
+        option(CommandOptionType::Integer, "turns", "How many replies the scene lasts"),
+      ]),
+      subcommand("end", "Ends the scene in this channel", Vec::new()),
+    ]
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    if interaction.guild_id.is_none() {
+      respond(ctx, &interaction, "This command can only be used in a server.", true).await?;
+      return Ok(());
+    }
+    let Some((subcommand, options)) = get_subcommand(&interaction.data) else {
+      panic!("Expected a subcommand, per command arguments");
+    };
+    match subcommand {
+      "start" => start_scene(bot, ctx, &interaction, options).await,
+      "end" => end_scene(bot, ctx, &interaction).await,
+      _ => panic!("Unknown subcommand {subcommand:?}"),
+    }
+  }
+}
+
+async fn start_scene(
+  bot: &MarcoBot,
+  ctx: &Context,
+  interaction: &CommandInteraction,
+  options: &[CommandDataOption],
+) -> anyhow::Result<()> {
+  let channel_id = interaction.channel_id;
+  let guild_id = interaction.guild_id;
+  let premise = match find_option(options, "premise") {
+    None => None,
+    Some(CommandDataOptionValue::String(premise)) => Some(premise.trim().to_owned()).filter(|premise| !premise.is_empty()),
+    Some(_) => panic!("Expected a string, per command arguments"),
+  };
+  let cast_size = integer_value(options, "cast").unwrap_or(MIN_CAST_SIZE as i64);
+  let Some(cast_size) = usize::try_from(cast_size).ok().filter(|size| (MIN_CAST_SIZE..=MAX_CAST_SIZE).contains(size)) else {
+    let content = format!("A scene needs between {MIN_CAST_SIZE} and {MAX_CAST_SIZE} personalities.");
+    respond(ctx, interaction, &content, true).await?;
+    return Ok(());
+  };
+  let turns = integer_value(options, "turns").unwrap_or(DEFAULT_TURNS as i64);
+  let Some(turns) = usize::try_from(turns).ok().filter(|turns| (1..=MAX_TURNS).contains(turns)) else {
+    let content = format!("A scene can last between 1 and {MAX_TURNS} turns.");
+    respond(ctx, interaction, &content, true).await?;
+    return Ok(());
+  };
+  let running = {
+    let mut state = bot.lock_state();
+    ensemble::end_idle_scenes(&mut state);
+    state.scenes.contains_key(&channel_id)
+  };
+  if running {
+    respond(ctx, interaction, "A scene is already running here. Use `/ensemble end` to stop it.", true).await?;
+    return Ok(());
+  }
+  if let Some(premise) = &premise {
+    let level = moderation::level_for(bot, guild_id);
+    if moderation::check_rules(&bot.config().moderation, level, premise).is_some() {
+      respond(ctx, interaction, "That premise breaks this server's moderation rules.", true).await?;
+      return Ok(());
+    }
+  }
+
+  let initial_response = CreateInteractionResponseMessage::default()
+    .content("Setting the scene...");
+  interaction.create_response(&ctx.http, CreateInteractionResponse::Defer(initial_response)).await?;
+
+  let mut personalities = Vec::new();
+  for _ in 0..cast_size {
+    let personality = bot.roll_personality_avoiding(None, guild_id, &personalities).await?;
+    personalities.push(personality);
+  }
+  let content = {
+    let mut state = bot.lock_state();
+    if state.scenes.contains_key(&channel_id) {
+      // Someone else started a scene while we were rolling.
+      None
+    } else {
+      let cast = personalities.into_iter()
+        .map(|personality| CastMember { identity_id: state.allocate_identity_id(), personality })
+        .collect();
+      let scene = Scene::new(cast, turns);
+      let cast_names = scene.cast_names();
+      // Set the scene for the cast, as though the user had said it.
+      let user = &interaction.user;
+      let nickname = interaction.member.as_ref()
+        .and_then(|member| member.nick.clone())
+        .unwrap_or_else(|| user.name.clone());
+      let mut opening = format!("(Starts a scene between {})", cast_names);
+      if let Some(premise) = &premise {
+        opening.push_str(&format!(" The scene: {premise}"));
+      }
+      state.message_history_mut(channel_id, None).push_back(Message {
+        user: MessageUser::DiscordUser {
+          user_id: user.id,
+          user_proper_name: user.name.clone(),
+          user_nickname: nickname,
+        },
+        content: opening,
+        images: Vec::new(),
+        tool_calls: Vec::new(),
+      }, true);
+      state.scenes.insert(channel_id, scene);
+      let mut content = format!("A scene begins with {cast_names}, lasting {turns} turns.");
+      if let Some(premise) = &premise {
+        content.push_str(&format!("\nThe scene: {premise}"));
+      }
+      Some(content)
+    }
+  };
+  let Some(content) = content else {
+    let final_response = EditInteractionResponse::default()
+      .content("A scene is already running here. Use `/ensemble end` to stop it.");
+    interaction.edit_response(&ctx.http, final_response).await?;
+    return Ok(());
+  };
+  let final_response = EditInteractionResponse::default()
+    .content(content);
+  interaction.edit_response(&ctx.http, final_response).await?;
+
+  ensemble::play_round(bot, ctx, channel_id, guild_id, interaction.user.id).await;
+  Ok(())
+}
+
+async fn end_scene(bot: &MarcoBot, ctx: &Context, interaction: &CommandInteraction) -> anyhow::Result<()> {
+  let name = {
+    let mut state = bot.lock_state();
+    state.scenes.remove(&interaction.channel_id)
+      .map(|_| state.personality.name.trim().to_owned())
+  };
+  match name {
+    None => respond(ctx, interaction, "There is no scene running here.", true).await?,
+    Some(name) => respond(ctx, interaction, &ensemble::end_of_scene(&name), false).await?,
+  }
+  Ok(())
+}
+
+fn integer_value(options: &[CommandDataOption], name: &str) -> Option<i64> {
+  match find_option(options, name)? {
+    CommandDataOptionValue::Integer(value) => Some(*value),
+    _ => panic!("Expected an integer, per command arguments"),
+  }
+}
+
+fn option(kind: CommandOptionType, name: &str, description: &str) -> CommandOption {
+  CommandOption {
+    kind,
+    name: String::from(name),
+    description: String::from(description),
+    is_required: false,
+    choices: Vec::new(),
+    options: Vec::new(),
+  }
+}
@@ -34,6 +34,13 @@ impl BotCommand for HelpCommand {
       .description("Marco is a Discord bot written by Mercerenies. Check the link above for more details")
       .field("/help", "Displays this help message.", false)
       .field("/reroll [base]", "Roll a new personality for Marco.", false)
+      .field("/personalities [page]", "List Marco's past personalities in this server.", false)
+      .field("/revive <id>", "(Admin) Bring back one of Marco's past personalities.", false)
+      .field("/ensemble <start|end>", "Start a scene where several personalities talk in this channel, or end it early.", false)
+      .field("/dm <enabled> [character_name]", "Opt in to (or out of) chatting with Marco in DMs.", false)
+      .field("/threads <mode>", "(Admin) Choose which threads Marco participates in.", false)
+      .field("/character <add|edit|remove|list>", "(Admin) Manage custom characters for Marco to play in this server.", false)
+      .field("/moderation [level]", "(Admin) Choose how strictly Marco screens messages, and review blocked ones.", false)
       .url("https://github.com/Mercerenies/marco-bot")
       .footer(CreateEmbedFooter::new("Thank you for using Marco Bot!"));
 
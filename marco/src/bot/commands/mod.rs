@@ -1,16 +1,31 @@
 
+mod character;
+mod dm;
+mod ensemble;
 mod help;
+mod moderation;
+mod personalities;
 mod reroll;
+mod revive;
+mod threads;
 
+pub use character::CharacterCommand;
+pub use dm::DmCommand;
+pub use ensemble::EnsembleCommand;
 pub use help::HelpCommand;
+pub use moderation::ModerationCommand;
+pub use personalities::PersonalitiesCommand;
 pub use reroll::RerollCommand;
+pub use revive::ReviveCommand;
+pub use threads::ThreadsCommand;
 
 use super::MarcoBot;
 
 use serenity::prelude::*;
 use serenity::model::application::{CommandInteraction, CommandOptionType,
-                                   CommandData, CommandDataOptionValue};
-use serenity::builder::CreateCommandOption;
+                                   CommandData, CommandDataOption, CommandDataOptionValue};
+use serenity::builder::{CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage};
+use serenity::model::permissions::Permissions;
 use async_trait::async_trait;
 
 use std::collections::HashMap;
@@ -29,6 +44,12 @@ pub trait BotCommand: Debug + Send + Sync {
 
   fn get_command_arguments(&self) -> Vec<CommandOption>;
 
+  /// Permissions a member needs by default to see and use this
+  /// command. Server administrators can override this in Discord.
+  fn get_required_permissions(&self) -> Option<Permissions> {
+    None
+  }
+
   async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()>;
 }
 
@@ -38,12 +59,32 @@ pub struct CommandOption {
   pub name: String,
   pub description: String,
   pub is_required: bool,
+  /// If non-empty, the only values a user may pick for a string
+  /// option.
+  pub choices: Vec<String>,
+  /// The options of a subcommand.
+  pub options: Vec<CommandOption>,
 }
 
 impl From<CommandOption> for CreateCommandOption {
   fn from(opt: CommandOption) -> Self {
-    CreateCommandOption::new(opt.kind, opt.name, opt.description)
-      .required(opt.is_required)
+    let option = opt.choices.into_iter().fold(
+      CreateCommandOption::new(opt.kind, opt.name, opt.description).required(opt.is_required),
+      |option, choice| option.add_string_choice(choice.clone(), choice),
+    );
+    opt.options.into_iter().fold(option, |option, sub_option| option.add_sub_option(sub_option.into()))
+  }
+}
+
+/// A subcommand with the given options.
+pub fn subcommand(name: &str, description: &str, options: Vec<CommandOption>) -> CommandOption {
+  CommandOption {
+    kind: CommandOptionType::SubCommand,
+    name: String::from(name),
+    description: String::from(description),
+    is_required: false,
+    choices: Vec::new(),
+    options,
   }
 }
 
@@ -55,15 +96,43 @@ where I: IntoIterator<Item = Box<dyn BotCommand>> {
 }
 
 pub fn compile_default_commands() -> HashMap<String, Box<dyn BotCommand>> {
-  let default_commands_list: [Box<dyn BotCommand>; 2] = [
+  let default_commands_list: [Box<dyn BotCommand>; 9] = [
     Box::new(HelpCommand),
+    Box::new(DmCommand),
     Box::new(RerollCommand),
+    Box::new(PersonalitiesCommand),
+    Box::new(ReviveCommand),
+    Box::new(EnsembleCommand),
+    Box::new(CharacterCommand),
+    Box::new(ThreadsCommand),
+    Box::new(ModerationCommand),
   ];
   compile_commands_map(default_commands_list)
 }
 
 pub fn get_option<'d>(data: &'d CommandData, name: &str) -> Option<&'d CommandDataOptionValue> {
-  data.options.iter()
+  find_option(&data.options, name)
+}
+
+/// The subcommand that was invoked, along with its options.
+pub fn get_subcommand(data: &CommandData) -> Option<(&str, &[CommandDataOption])> {
+  data.options.iter().find_map(|o| match &o.value {
+    CommandDataOptionValue::SubCommand(options) => Some((o.name.as_str(), options.as_slice())),
+    _ => None,
+  })
+}
+
+pub fn find_option<'d>(options: &'d [CommandDataOption], name: &str) -> Option<&'d CommandDataOptionValue> {
+  options.iter()
     .find(|o| o.name == name)
     .map(|o| &o.value)
 }
+
+/// Responds to the interaction with a plain message, visible only to
+/// the user who invoked it if `ephemeral` is set.
+pub async fn respond(ctx: &Context, interaction: &CommandInteraction, content: &str, ephemeral: bool) -> serenity::Result<()> {
+  let message = CreateInteractionResponseMessage::default()
+    .content(content)
+    .ephemeral(ephemeral);
+  interaction.create_response(&ctx.http, CreateInteractionResponse::Message(message)).await
+}
@@ -0,0 +1,95 @@
+
+use super::{BotCommand, CommandOption, get_option, respond};
+use crate::bot::MarcoBot;
+use crate::bot::output::truncate_message;
+use crate::bot::settings::ModerationLevel;
+use crate::storage::PendingWrite;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
+use serenity::model::permissions::Permissions;
+use strum::VariantArray;
+use async_trait::async_trait;
+
+use std::fmt::{Debug, Write};
+
+/// Number of recently blocked messages shown by the command.
+const SHOWN_BLOCKED_ITEMS: usize = 5;
+
+/// "Moderation" command to set how strictly Marco screens messages,
+/// and to review recently blocked messages.
+#[derive(Debug, Clone, Default)]
+pub struct ModerationCommand;
+
+#[async_trait]
+impl BotCommand for ModerationCommand {
+  fn get_command_name(&self) -> &str {
+    "moderation"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Sets how strictly Marco screens messages, and shows recently blocked messages."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      CommandOption {
+        kind: CommandOptionType::String,
+        name: String::from("level"),
+        description: String::from("How strictly Marco should screen messages"),
+        is_required: false,
+        choices: ModerationLevel::VARIANTS.iter().map(|level| level.to_string()).collect(),
+        options: Vec::new(),
+      },
+    ]
+  }
+
+  fn get_required_permissions(&self) -> Option<Permissions> {
+    Some(Permissions::MANAGE_GUILD)
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let Some(guild_id) = interaction.guild_id else {
+      respond(ctx, &interaction, "This command can only be used in a server.", true).await?;
+      return Ok(());
+    };
+    let new_level = match get_option(&interaction.data, "level") {
+      None => None,
+      Some(CommandDataOptionValue::String(level)) => {
+        let Ok(level) = level.parse::<ModerationLevel>() else {
+          respond(ctx, &interaction, "I don't know that level, sorry", true).await?;
+          return Ok(());
+        };
+        Some(level)
+      }
+      Some(_) => panic!("Expected a string, per command arguments"),
+    };
+    let mut settings_write = PendingWrite::nothing();
+    let content = {
+      let mut state = bot.lock_state();
+      if let Some(level) = new_level {
+        settings_write = state.guild_settings.update(|settings| settings.get_mut(guild_id).moderation = level).1;
+      }
+      let level = state.guild_settings.get().get(guild_id).moderation;
+      let mut content = format!("Moderation level is `{level}`.");
+      let blocked: Vec<_> = state.moderation_log.recent(Some(guild_id)).take(SHOWN_BLOCKED_ITEMS).collect();
+      if blocked.is_empty() {
+        content.push_str("\nNothing has been blocked recently.");
+      } else {
+        content.push_str("\nRecently blocked:");
+        for item in blocked {
+          write!(
+            content,
+            "\n- <t:{}:R> {} from <@{}> in <#{}> ({}): `{}`",
+            item.time.timestamp(), item.direction, item.user_id, item.channel_id,
+            item.reason, item.excerpt.replace('`', "'"),
+          )?;
+        }
+      }
+      content
+    };
+    settings_write.save()?;
+    respond(ctx, &interaction, &truncate_message(&content), true).await?;
+    Ok(())
+  }
+}
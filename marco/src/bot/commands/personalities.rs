@@ -0,0 +1,89 @@
+
+use super::{BotCommand, CommandOption, get_option, respond};
+use crate::bot::MarcoBot;
+use crate::bot::output::truncate_message;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
+use itertools::Itertools;
+use async_trait::async_trait;
+
+use std::fmt::{Debug, Write};
+
+/// Number of archived personalities shown per page.
+const PAGE_SIZE: usize = 10;
+
+/// "Personalities" command to browse Marco's archived personalities.
+#[derive(Debug, Clone, Default)]
+pub struct PersonalitiesCommand;
+
+#[async_trait]
+impl BotCommand for PersonalitiesCommand {
+  fn get_command_name(&self) -> &str {
+    "personalities"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Lists Marco's past personalities in this server."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      CommandOption {
+        kind: CommandOptionType::Integer,
+        name: String::from("page"),
+        description: String::from("Page of the archive to show, starting from 1"),
+        is_required: false,
+        choices: Vec::new(),
+        options: Vec::new(),
+      },
+    ]
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let Some(guild_id) = interaction.guild_id else {
+      respond(ctx, &interaction, "This command can only be used in a server.", false).await?;
+      return Ok(());
+    };
+    let page = match get_option(&interaction.data, "page") {
+      None => 1,
+      Some(CommandDataOptionValue::Integer(page)) => usize::try_from(*page).unwrap_or(0).max(1),
+      Some(_) => panic!("Expected an integer, per command arguments"),
+    };
+    let content = {
+      let state = bot.lock_state();
+      let activity = state.activity.in_guild(guild_id);
+      let mut content = format!(
+        "Currently playing **{}** ({} messages, {} reactions).\n",
+        state.personality.name.trim(), activity.messages, activity.reactions,
+      );
+      let archived = state.archive.get().list(guild_id);
+      let page_count = archived.len().div_ceil(PAGE_SIZE).max(1);
+      if archived.is_empty() {
+        content.push_str("No past personalities yet.");
+      } else if page > page_count {
+        write!(content, "There are only {page_count} pages.")?;
+      } else {
+        write!(content, "Past personalities (page {page} of {page_count}):")?;
+        for entry in archived.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+          let personality = &entry.personality;
+          let tags = if personality.tags.is_empty() {
+            String::new()
+          } else {
+            format!(" [{}]", personality.tags.iter().join(", "))
+          };
+          write!(
+            content,
+            "\n`{}` **{}** - {}, like {}{}. Rolled <t:{}:d>, {} messages, {} reactions",
+            entry.id, personality.name.trim(), personality.class, personality.base_character, tags,
+            entry.rolled_at.timestamp(), entry.messages, entry.reactions,
+          )?;
+        }
+        content.push_str("\nUse `/revive <id>` to bring one back.");
+      }
+      content
+    };
+    respond(ctx, &interaction, &truncate_message(&content), false).await?;
+    Ok(())
+  }
+}
@@ -1,7 +1,8 @@
 
 use super::{BotCommand, CommandOption, get_option};
 use crate::bot::MarcoBot;
-use crate::personality::{generate_personality, generate_personality_from};
+use crate::bot::appearance;
+use crate::bot::handoff;
 
 use serenity::prelude::*;
 use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
@@ -32,6 +33,8 @@ impl BotCommand for RerollCommand {
         name: String::from("character_name"),
         description: String::from("Name of character template to use"),
         is_required: false,
+        choices: Vec::new(),
+        options: Vec::new(),
       },
     ]
   }
@@ -41,30 +44,38 @@ impl BotCommand for RerollCommand {
       .content("Rerolling...");
     interaction.create_response(&ctx.http, CreateInteractionResponse::Defer(initial_response)).await?;
 
-    let new_personality;
+    let mut character = None;
     if let Some(data_value) = get_option(&interaction.data, "character_name") {
       let CommandDataOptionValue::String(data_value) = data_value else {
         panic!("Expected a string, per command arguments");
       };
-      let Ok(character_name) = data_value.trim().to_lowercase().parse() else {
+      let Some(character_name) = bot.find_character(data_value, interaction.guild_id) else {
         let final_response = EditInteractionResponse::default()
           .content("I don't know who that is, sorry");
         interaction.edit_response(&ctx.http, final_response).await?;
         return Ok(());
       };
-      new_personality = generate_personality_from(bot.client(), character_name).await?;
-    } else {
-      new_personality = generate_personality(bot.client()).await?;
+      character = Some(character_name);
     }
+    let new_personality = bot.roll_personality(character, interaction.guild_id).await?;
     let name = new_personality.name.trim().to_owned();
-    {
+    let catchphrase = new_personality.catchphrase.clone();
+    let archive_write = {
       let mut state = bot.lock_state();
-      state.set_personality(new_personality);
-      state.refresh_activity(&ctx);
-    }
+      let archive_write = state.set_personality(new_personality);
+      state.refresh_activity(ctx);
+      archive_write
+    };
+    archive_write.save_or_log();
+    appearance::update_nicknames(bot, ctx);
+    handoff::announce(bot, ctx);
 
+    let mut content = format!("Introducing {name}!");
+    if !catchphrase.is_empty() {
+      content.push_str(&format!(" *\"{catchphrase}\"*"));
+    }
     let final_response = EditInteractionResponse::default()
-      .content(format!("Introducing {name}!"));
+      .content(content);
     interaction.edit_response(&ctx.http, final_response).await?;
 
     Ok(())
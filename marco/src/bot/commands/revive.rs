@@ -0,0 +1,80 @@
+
+use super::{BotCommand, CommandOption, get_option, respond};
+use crate::bot::MarcoBot;
+use crate::bot::appearance;
+use crate::bot::handoff;
+use crate::storage::PendingWrite;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
+use serenity::model::permissions::Permissions;
+use async_trait::async_trait;
+
+use std::fmt::Debug;
+
+/// "Revive" command to bring back an archived personality.
+#[derive(Debug, Clone, Default)]
+pub struct ReviveCommand;
+
+#[async_trait]
+impl BotCommand for ReviveCommand {
+  fn get_command_name(&self) -> &str {
+    "revive"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Brings back one of Marco's past personalities, exactly as it was."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      CommandOption {
+        kind: CommandOptionType::Integer,
+        name: String::from("id"),
+        description: String::from("ID of the personality, as shown by /personalities"),
+        is_required: true,
+        choices: Vec::new(),
+        options: Vec::new(),
+      },
+    ]
+  }
+
+  /// Reviving brings back personalities an admin may have deliberately
+  /// rerolled away from, so it is limited to admins by default.
+  fn get_required_permissions(&self) -> Option<Permissions> {
+    Some(Permissions::MANAGE_GUILD)
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let Some(guild_id) = interaction.guild_id else {
+      respond(ctx, &interaction, "This command can only be used in a server.", false).await?;
+      return Ok(());
+    };
+    let Some(CommandDataOptionValue::Integer(id)) = get_option(&interaction.data, "id") else {
+      panic!("Expected an integer, per command arguments");
+    };
+    let (revived, archive_write) = {
+      let mut state = bot.lock_state();
+      let archived = u64::try_from(*id).ok()
+        .and_then(|id| state.archive.get().get(guild_id, id))
+        .cloned();
+      let mut archive_write = PendingWrite::nothing();
+      if let Some(archived) = &archived {
+        archive_write = state.revive_personality(archived);
+        state.refresh_activity(ctx);
+      }
+      (archived, archive_write)
+    };
+    archive_write.save_or_log();
+    let content = match revived {
+      None => String::from("I don't remember that one, sorry. Try `/personalities`."),
+      Some(archived) => {
+        appearance::update_nicknames(bot, ctx);
+        handoff::announce(bot, ctx);
+        format!("{} is back!", archived.personality.name.trim())
+      }
+    };
+    respond(ctx, &interaction, &content, false).await?;
+    Ok(())
+  }
+}
@@ -0,0 +1,62 @@
+
+use super::{BotCommand, CommandOption, get_option, respond};
+use crate::bot::MarcoBot;
+use crate::bot::settings::ThreadParticipation;
+
+use serenity::prelude::*;
+use serenity::model::application::{CommandInteraction, CommandOptionType, CommandDataOptionValue};
+use serenity::model::permissions::Permissions;
+use strum::VariantArray;
+use async_trait::async_trait;
+
+use std::fmt::Debug;
+
+/// "Threads" command to choose which threads Marco participates in.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadsCommand;
+
+#[async_trait]
+impl BotCommand for ThreadsCommand {
+  fn get_command_name(&self) -> &str {
+    "threads"
+  }
+
+  fn get_command_desc(&self) -> &str {
+    "Chooses which threads Marco participates in on this server."
+  }
+
+  fn get_command_arguments(&self) -> Vec<CommandOption> {
+    vec![
+      CommandOption {
+        kind: CommandOptionType::String,
+        name: String::from("mode"),
+        description: String::from("Which threads Marco should respond in"),
+        is_required: true,
+        choices: ThreadParticipation::VARIANTS.iter().map(|mode| mode.to_string()).collect(),
+        options: Vec::new(),
+      },
+    ]
+  }
+
+  fn get_required_permissions(&self) -> Option<Permissions> {
+    Some(Permissions::MANAGE_GUILD)
+  }
+
+  async fn run_command(&self, bot: &MarcoBot, ctx: &Context, interaction: CommandInteraction) -> anyhow::Result<()> {
+    let Some(guild_id) = interaction.guild_id else {
+      respond(ctx, &interaction, "This command can only be used in a server.", true).await?;
+      return Ok(());
+    };
+    let Some(CommandDataOptionValue::String(mode)) = get_option(&interaction.data, "mode") else {
+      panic!("Expected a string, per command arguments");
+    };
+    let Ok(mode) = mode.parse::<ThreadParticipation>() else {
+      respond(ctx, &interaction, "I don't know that mode, sorry", true).await?;
+      return Ok(());
+    };
+    let settings_write = bot.lock_state().guild_settings.update(|settings| settings.get_mut(guild_id).threads = mode).1;
+    settings_write.save()?;
+    respond(ctx, &interaction, &format!("Thread participation set to `{mode}`."), true).await?;
+    Ok(())
+  }
+}
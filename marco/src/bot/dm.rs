@@ -0,0 +1,47 @@
+
+//! Opt-in direct message conversations.
+//!
+//! Users enable DMs for themselves with the `/dm` command. By
+//! default, Marco uses his current personality in DMs, but a user may
+//! choose a character, in which case Marco keeps a separate
+//! personality just for that user.
+
+use super::MarcoBot;
+use crate::personality::FullPersonality;
+
+use serenity::model::id::UserId;
+
+/// A personality used only in one user's direct messages.
+#[derive(Debug, Clone)]
+pub struct DmPersona {
+  pub identity_id: usize,
+  pub personality: FullPersonality,
+}
+
+/// Makes sure that, if the user has chosen a character for their
+/// DMs, a personality for that character exists.
+///
+/// Personalities are only kept in memory, so after a restart they
+/// are regenerated the first time the user sends a DM. If the
+/// personality is already being generated for another message, this
+/// returns immediately rather than generating a second one.
+pub async fn ensure_dm_persona(bot: &MarcoBot, user_id: UserId) -> anyhow::Result<()> {
+  let character = {
+    let mut state = bot.lock_state();
+    if state.dm_personas.contains_key(&user_id) {
+      return Ok(());
+    }
+    let Some(character) = state.user_settings.get().get(user_id).dm_character else {
+      return Ok(());
+    };
+    if !state.pending_dm_personas.insert(user_id) {
+      return Ok(());
+    }
+    character
+  };
+  let personality = bot.roll_personality(Some(character.into()), None).await;
+  let mut state = bot.lock_state();
+  state.pending_dm_personas.remove(&user_id);
+  state.set_dm_personality(user_id, personality?);
+  Ok(())
+}
@@ -0,0 +1,189 @@
+
+//! Ensemble scenes, where several personalities share a channel for
+//! a limited number of turns.
+//!
+//! Scenes are started with the `/ensemble` command. While a scene is
+//! running, every message from a human in the channel prompts each
+//! member of the cast to reply in turn, to the users and to each
+//! other. Once the turn cap is reached, or if nobody prompts the
+//! scene for a while, the scene ends and the channel goes back to
+//! Marco's current personality.
+
+use super::{MarcoBot, MarcoBotState};
+use super::output;
+use super::speech::{Speech, speak};
+use crate::personality::FullPersonality;
+
+use serenity::prelude::*;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::builder::CreateMessage;
+
+/// Smallest and largest number of personalities in a scene.
+pub const MIN_CAST_SIZE: usize = 2;
+pub const MAX_CAST_SIZE: usize = 3;
+
+/// Number of replies in a scene, unless the user asks for another
+/// number.
+pub const DEFAULT_TURNS: usize = 12;
+
+/// Hard cap on the number of replies in a scene.
+pub const MAX_TURNS: usize = 30;
+
+/// How long a scene lasts without being prompted before it ends.
+const IDLE_MINUTES: i64 = 15;
+
+/// A personality taking part in a scene.
+#[derive(Debug, Clone)]
+pub struct CastMember {
+  pub identity_id: usize,
+  pub personality: FullPersonality,
+}
+
+/// A scene running in one channel.
+#[derive(Debug, Clone)]
+pub struct Scene {
+  pub cast: Vec<CastMember>,
+  /// Replies left before the scene ends, not counting those being
+  /// written right now.
+  pub turns_left: usize,
+  /// Replies being written right now. Rounds can overlap, since
+  /// messages are handled concurrently.
+  turns_in_progress: usize,
+  /// Index into `cast` of whoever speaks next.
+  next_speaker: usize,
+  /// When the scene was started or last prompted.
+  last_active: chrono::DateTime<chrono::Utc>,
+}
+
+impl Scene {
+  pub fn new(cast: Vec<CastMember>, turns: usize) -> Self {
+    Self {
+      cast,
+      turns_left: turns.min(MAX_TURNS),
+      turns_in_progress: 0,
+      next_speaker: 0,
+      last_active: chrono::Utc::now(),
+    }
+  }
+
+  /// The names of the cast, as a list for humans to read.
+  pub fn cast_names(&self) -> String {
+    let names: Vec<_> = self.cast.iter().map(|member| member.personality.name.trim()).collect();
+    match names.split_last() {
+      None => String::new(),
+      Some((last, [])) => (*last).to_owned(),
+      Some((last, rest)) => format!("{} and {last}", rest.join(", ")),
+    }
+  }
+
+  /// Reserves the next turn, returning who speaks, or `None` if there
+  /// are no turns left. The turn must be ended with
+  /// [`Scene::end_turn`] once the reply is sent (or fails).
+  fn reserve_turn(&mut self) -> Option<CastMember> {
+    if self.turns_left == 0 || self.cast.is_empty() {
+      return None;
+    }
+    self.turns_left -= 1;
+    self.turns_in_progress += 1;
+    let member = self.cast[self.next_speaker].clone();
+    self.next_speaker = (self.next_speaker + 1) % self.cast.len();
+    Some(member)
+  }
+
+  /// Ends a turn reserved with [`Scene::reserve_turn`]. If no reply was
+  /// sent, the turn is handed back.
+  fn end_turn(&mut self, sent: bool) {
+    self.turns_in_progress = self.turns_in_progress.saturating_sub(1);
+    if !sent {
+      self.turns_left += 1;
+    }
+  }
+
+  /// Whether every turn has been used up.
+  fn is_over(&self) -> bool {
+    self.turns_left == 0 && self.turns_in_progress == 0
+  }
+
+  /// Whether the scene has gone unprompted for long enough to end.
+  fn is_idle(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+    now - self.last_active >= chrono::Duration::minutes(IDLE_MINUTES)
+  }
+}
+
+/// Lets each member of the scene in `channel_id` reply once, in
+/// turn, to a message from `prompted_by`. Ends the scene if it runs
+/// out of turns.
+pub async fn play_round(bot: &MarcoBot, ctx: &Context, channel_id: ChannelId, guild_id: Option<GuildId>, prompted_by: UserId) {
+  let cast_size = {
+    let mut state = bot.lock_state();
+    let Some(scene) = state.scenes.get_mut(&channel_id) else {
+      return;
+    };
+    scene.last_active = chrono::Utc::now();
+    scene.cast.len()
+  };
+  for _ in 0..cast_size {
+    let member = bot.lock_state().scenes.get_mut(&channel_id).and_then(Scene::reserve_turn);
+    let Some(member) = member else {
+      break;
+    };
+    let sent = speak(bot, ctx, channel_id, guild_id, prompted_by, Speech {
+      identity_id: member.identity_id,
+      personality: &member.personality,
+      mood: None,
+      instruction: None,
+      label: true,
+    }).await;
+    if let Some(scene) = bot.lock_state().scenes.get_mut(&channel_id) {
+      scene.end_turn(sent.is_some());
+    }
+    if sent.is_none() {
+      break;
+    }
+  }
+  let finished = {
+    let mut state = bot.lock_state();
+    if state.scenes.get(&channel_id).is_some_and(Scene::is_over) {
+      state.scenes.remove(&channel_id);
+      Some(state.personality.name.trim().to_owned())
+    } else {
+      None
+    }
+  };
+  if let Some(name) = finished {
+    if let Err(why) = output::send_reply(ctx, channel_id, CreateMessage::default(), &end_of_scene(&name), &[]).await {
+      println!("Error sending message: {:?}", why);
+    }
+  }
+}
+
+/// Announces that a scene is over and `name`, Marco's current
+/// personality, is back.
+pub fn end_of_scene(name: &str) -> String {
+  format!("*The scene is over. {name} is back.*")
+}
+
+/// Whether a message in the channel should go to the scene running
+/// there, rather than to Marco's current personality. Only humans can
+/// prompt a scene, so that bots cannot keep it going forever.
+pub fn wants_scene(bot: &MarcoBot, channel_id: ChannelId, author_is_bot: bool) -> bool {
+  if author_is_bot {
+    return false;
+  }
+  let mut state = bot.lock_state();
+  end_idle_scenes(&mut state);
+  state.scenes.contains_key(&channel_id)
+}
+
+/// Ends every scene which has gone unprompted for too long. The
+/// channels quietly go back to Marco's current personality.
+pub fn end_idle_scenes(state: &mut MarcoBotState) {
+  let now = chrono::Utc::now();
+  state.scenes.retain(|channel_id, scene| {
+    let idle = scene.is_idle(now);
+    if idle {
+      println!("Ending idle scene in channel {channel_id}");
+    }
+    !idle
+  });
+}
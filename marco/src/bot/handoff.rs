@@ -0,0 +1,156 @@
+
+//! Handing the conversation over from one personality to the next.
+//!
+//! Whenever Marco's personality is replaced, the new personality is
+//! told who came before it and what they were last discussing, so that
+//! it does not walk into the conversation blind. Optionally, the
+//! outgoing personality also says goodbye and the new one introduces
+//! itself, in the channel where Marco was most recently talking.
+
+use super::MarcoBot;
+use super::message::{MessageHistory, MessageUser};
+use super::output;
+use super::speech::{Speech, speak};
+use crate::personality::FullPersonality;
+use crate::environ::{self, get_env_or};
+
+use itertools::Itertools;
+use serenity::prelude::*;
+use serenity::model::id::{ChannelId, GuildId};
+
+use std::collections::HashSet;
+
+/// How long the new personality is reminded of its predecessor.
+const NOTE_MINUTES: i64 = 30;
+
+/// Longest excerpt of the predecessor's last words in the note.
+const MAX_LAST_WORDS_LEN: usize = 200;
+
+/// Configuration for handoffs between personalities.
+#[derive(Debug, Clone, Default)]
+pub struct HandoffConfig {
+  /// Whether the outgoing personality says goodbye and the new one
+  /// introduces itself.
+  pub announce: bool,
+}
+
+/// A personality that has just been replaced, and what it leaves
+/// behind for its successor.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+  pub predecessor_id: usize,
+  pub predecessor: FullPersonality,
+  /// The guild channel where the predecessor was most recently
+  /// talking, if any.
+  pub channel: Option<(ChannelId, GuildId)>,
+  /// What the successor is told about the predecessor.
+  pub note: String,
+  at: chrono::DateTime<chrono::Utc>,
+}
+
+impl HandoffConfig {
+  pub fn from_env() -> Self {
+    Self {
+      announce: get_env_or(environ::HANDOFF_MESSAGES, false),
+    }
+  }
+}
+
+impl Handoff {
+  /// The handoff from `predecessor`, who was last talking in
+  /// `channel`, with the given history.
+  pub fn new(
+    predecessor_id: usize,
+    predecessor: FullPersonality,
+    channel: Option<(ChannelId, GuildId)>,
+    history: Option<&MessageHistory>,
+  ) -> Self {
+    let mut note = format!("You have just taken over from {}, Marco's previous personality.", predecessor.tagline());
+    if let Some(history) = history {
+      let mut seen = HashSet::new();
+      let participants = history.participants()
+        .filter(|(user_id, _)| seen.insert(*user_id))
+        .map(|(_, name)| name)
+        .join(", ");
+      if !participants.is_empty() {
+        note.push_str(&format!(" They were talking with {participants}."));
+      }
+      let last_words = history.messages().iter().rev().find_map(|message| match &message.user {
+        MessageUser::Marco { identity_id, .. } if *identity_id == predecessor_id => Some(&message.content),
+        _ => None,
+      });
+      if let Some(last_words) = last_words {
+        let excerpt = output::truncate_chars(last_words.trim(), MAX_LAST_WORDS_LEN);
+        note.push_str(&format!(" Their last words were: \"{excerpt}\""));
+      }
+    }
+    Self { predecessor_id, predecessor, channel, note, at: chrono::Utc::now() }
+  }
+
+  /// The note for the successor when replying in `guild_id`, if the
+  /// handoff happened recently enough to still matter. A note which
+  /// describes a conversation is only given in the guild where that
+  /// conversation took place.
+  pub fn recent_note(&self, guild_id: Option<GuildId>, now: chrono::DateTime<chrono::Utc>) -> Option<&str> {
+    let same_place = self.channel.is_none_or(|(_, handoff_guild_id)| guild_id == Some(handoff_guild_id));
+    (same_place && now - self.at < chrono::Duration::minutes(NOTE_MINUTES)).then_some(self.note.as_str())
+  }
+}
+
+/// Has the outgoing personality say goodbye and the new one introduce
+/// itself, if so configured. This happens in the background, in the
+/// channel where Marco was most recently talking.
+pub fn announce(bot: &MarcoBot, ctx: &Context) {
+  if !bot.config().handoff.announce {
+    return;
+  }
+  let bot = bot.clone();
+  let ctx = ctx.clone();
+  tokio::spawn(async move {
+    let (handoff, successor_id, successor) = {
+      let state = bot.lock_state();
+      let Some(handoff) = state.handoff.clone() else {
+        return;
+      };
+      (handoff, state.personality_id, state.personality.clone())
+    };
+    let Some((channel_id, guild_id)) = handoff.channel else {
+      return;
+    };
+    let farewell = format!(
+      "You are about to be replaced by {}, Marco's next personality. Say a short goodbye to the chat.",
+      successor.tagline(),
+    );
+    let bot_user_id = ctx.cache.current_user().id;
+    // Marco's own name already belongs to his new personality by now.
+    speak(&bot, &ctx, channel_id, Some(guild_id), bot_user_id, Speech {
+      identity_id: handoff.predecessor_id,
+      personality: &handoff.predecessor,
+      mood: None,
+      instruction: Some(&farewell),
+      label: true,
+    }).await;
+    let mood = {
+      let state = bot.lock_state();
+      if state.personality_id != successor_id {
+        // Marco has already moved on again.
+        return;
+      }
+      state.mood.mood()
+    };
+    let introduction = format!("{} Introduce yourself to the chat in a sentence or two.", handoff.note);
+    let sent = speak(&bot, &ctx, channel_id, Some(guild_id), bot_user_id, Speech {
+      identity_id: successor_id,
+      personality: &successor,
+      mood: Some(mood),
+      instruction: Some(&introduction),
+      label: false,
+    }).await;
+    let mut state = bot.lock_state();
+    if state.personality_id == successor_id {
+      for message in sent.into_iter().flatten() {
+        state.activity.record_message(guild_id, message.id);
+      }
+    }
+  });
+}
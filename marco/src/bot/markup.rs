@@ -0,0 +1,163 @@
+
+//! Translation between raw Discord markup and human-readable text.
+//!
+//! Discord represents mentions and custom emoji as opaque tokens such
+//! as `<@123>` or `<:blob:456>`. The model has no way to interpret
+//! these, so inbound messages are rewritten to use names (`@Alice`,
+//! `#general`, `:blob:`) before they enter the
+//! [`MessageHistory`](super::message::MessageHistory), and outbound
+//! replies are rewritten back into Discord markup wherever a name can
+//! be resolved unambiguously.
+
+use serenity::cache::Cache;
+use serenity::model::id::{UserId, GuildId, ChannelId, RoleId};
+use serenity::model::user::User;
+use regex::{Regex, Captures};
+use itertools::Itertools;
+
+use std::sync::LazyLock;
+
+static USER_MENTION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<@!?(\d+)>").unwrap());
+
+static ROLE_MENTION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<@&(\d+)>").unwrap());
+
+static CHANNEL_MENTION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<#(\d+)>").unwrap());
+
+static CUSTOM_EMOJI_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<a?:(\w+):\d+>").unwrap());
+
+/// Matches `#channel-name` in outbound text. Discord channel names
+/// never contain spaces.
+static CHANNEL_NAME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"#([\w-]+)").unwrap());
+
+/// Matches `:emoji_name:` in outbound text, along with any Discord
+/// markup surrounding it (so that already-resolved emoji can be left
+/// alone).
+static EMOJI_NAME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(<a?)?:(\w+):(\d+>)?").unwrap());
+
+/// An outbound reply, rewritten into Discord markup.
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedText {
+  pub text: String,
+  /// The users the reply mentions by name. Only these users may be
+  /// pinged by it.
+  pub mentions: Vec<UserId>,
+}
+
+/// Replaces user, role, and channel mentions and custom emoji in
+/// `content` with readable names.
+///
+/// `mentions` is the list of users Discord reports as mentioned in
+/// the message, which is used as a fallback for users that are not in
+/// the cache.
+pub fn normalize_inbound(
+  cache: &Cache,
+  guild_id: Option<GuildId>,
+  content: &str,
+  mentions: &[User],
+) -> String {
+  let content = USER_MENTION_RE.replace_all(content, |caps: &Captures| {
+    let name = parse_id(&caps[1]).map(UserId::new)
+      .and_then(|user_id| user_display_name(cache, guild_id, user_id, mentions));
+    format!("@{}", name.as_deref().unwrap_or("unknown-user"))
+  });
+  let content = ROLE_MENTION_RE.replace_all(&content, |caps: &Captures| {
+    let name = guild_id.and_then(|guild_id| cache.guild(guild_id))
+      .zip(parse_id(&caps[1]).map(RoleId::new))
+      .and_then(|(guild, role_id)| guild.roles.get(&role_id).map(|role| role.name.clone()));
+    format!("@{}", name.as_deref().unwrap_or("unknown-role"))
+  });
+  let content = CHANNEL_MENTION_RE.replace_all(&content, |caps: &Captures| {
+    let name = guild_id.and_then(|guild_id| cache.guild(guild_id))
+      .zip(parse_id(&caps[1]).map(ChannelId::new))
+      .and_then(|(guild, channel_id)| {
+        guild.channels.get(&channel_id)
+          .or_else(|| guild.threads.iter().find(|thread| thread.id == channel_id))
+          .map(|channel| channel.name.clone())
+      });
+    format!("#{}", name.as_deref().unwrap_or("unknown-channel"))
+  });
+  let content = CUSTOM_EMOJI_RE.replace_all(&content, ":$1:");
+  content.into_owned()
+}
+
+/// Rewrites `@Name`, `#channel-name`, and `:emoji:` in an outbound
+/// reply into Discord markup.
+///
+/// `known_users` lists the users that may be mentioned by name,
+/// typically the recent participants of the conversation. Users are
+/// matched case-insensitively by any of their names. Names that
+/// cannot be resolved are left as plain text.
+pub fn resolve_outbound<'a, I>(
+  cache: &Cache,
+  guild_id: Option<GuildId>,
+  content: &str,
+  known_users: I,
+) -> ResolvedText
+where I: IntoIterator<Item = (UserId, &'a str)> {
+  let mut known_users: Vec<(UserId, &str)> = known_users.into_iter()
+    .filter(|(_, name)| !name.is_empty())
+    .collect();
+  // Prefer the longest match, so "@Mr Smith" does not resolve to a
+  // user named "Mr".
+  known_users.sort_by_key(|(_, name)| std::cmp::Reverse(name.chars().count()));
+  let mut content = content.to_owned();
+  let mut mentions = Vec::new();
+  if !known_users.is_empty() {
+    // One group per user, so that the user is identified by whichever
+    // group matched, with the regex's own case folding. The final
+    // group requires the name to end at a word boundary, so "@Bobby"
+    // is not read as "@Bob" followed by "by".
+    let pattern = known_users.iter().map(|(_, name)| format!("({})", regex::escape(name))).join("|");
+    let user_name_re = Regex::new(&format!(r"(?i)@(?:{pattern})(\W|$)")).unwrap();
+    content = user_name_re.replace_all(&content, |caps: &Captures| {
+      let boundary = caps.get(known_users.len() + 1).map_or("", |m| m.as_str());
+      let Some(index) = (0..known_users.len()).find(|index| caps.get(index + 1).is_some()) else {
+        return caps[0].to_owned();
+      };
+      let user_id = known_users[index].0;
+      if !mentions.contains(&user_id) {
+        mentions.push(user_id);
+      }
+      format!("<@{user_id}>{boundary}")
+    }).into_owned();
+  }
+
+  let Some(guild) = guild_id.and_then(|guild_id| cache.guild(guild_id)) else {
+    return ResolvedText { text: content, mentions };
+  };
+  let content = CHANNEL_NAME_RE.replace_all(&content, |caps: &Captures| {
+    guild.channels.values()
+      .find(|channel| channel.name == caps[1])
+      .map(|channel| format!("<#{}>", channel.id))
+      .unwrap_or_else(|| caps[0].to_owned())
+  });
+  let content = EMOJI_NAME_RE.replace_all(&content, |caps: &Captures| {
+    if caps.get(1).is_some() || caps.get(3).is_some() {
+      // Already Discord markup.
+      return caps[0].to_owned();
+    }
+    guild.emojis.values()
+      .find(|emoji| emoji.name == caps[2])
+      .map(|emoji| emoji.to_string())
+      .unwrap_or_else(|| caps[0].to_owned())
+  });
+  ResolvedText { text: content.into_owned(), mentions }
+}
+
+fn parse_id(text: &str) -> Option<u64> {
+  text.parse().ok().filter(|id| *id != 0)
+}
+
+fn user_display_name(
+  cache: &Cache,
+  guild_id: Option<GuildId>,
+  user_id: UserId,
+  mentions: &[User],
+) -> Option<String> {
+  let member_name = guild_id
+    .and_then(|guild_id| cache.guild(guild_id))
+    .and_then(|guild| guild.members.get(&user_id).map(|member| member.display_name().to_owned()));
+  member_name
+    .or_else(|| mentions.iter().find(|user| user.id == user_id).map(|user| user.display_name().to_owned()))
+    .or_else(|| cache.user(user_id).map(|user| user.display_name().to_owned()))
+}
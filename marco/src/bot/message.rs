@@ -1,9 +1,12 @@
 
 //! Message history deque.
 
+use crate::openai::tools::ToolCallRecord;
 use crate::util::CapacityDeque;
 
+use itertools::Itertools;
 use serenity::model::id::UserId;
+use serenity::model::channel::Message as DiscordMessage;
 
 /// Recent chat history that the bot is aware of.
 #[derive(Debug, Clone)]
@@ -16,6 +19,20 @@ pub struct MessageHistory {
 pub struct Message {
   pub user: MessageUser,
   pub content: String,
+  pub images: Vec<MessageImage>,
+  /// Tools Marco used while writing this message. Always empty for
+  /// messages from anyone else.
+  pub tool_calls: Vec<ToolCallRecord>,
+}
+
+/// An image attached to (or embedded in) a message.
+#[derive(Debug, Clone)]
+pub struct MessageImage {
+  pub filename: String,
+  pub url: String,
+  /// Size in bytes, if Discord told us. Embedded images do not have
+  /// a known size.
+  pub size: Option<u32>,
 }
 
 /// The sender of the message, either a traditional Discord user or
@@ -29,6 +46,58 @@ pub enum MessageUser {
   Marco { identity_id: usize, identity: String },
 }
 
+impl MessageUser {
+  /// Identity ID for messages sent by a personality Marco no longer
+  /// remembers, such as messages from before a restart. The identity
+  /// allocator never hands out this ID, so live personalities
+  /// (including the placeholder Marco starts with) never use it.
+  pub const UNKNOWN_IDENTITY_ID: usize = 0;
+}
+
+impl Message {
+  /// The message content, with a textual placeholder for each image
+  /// so that the model knows an image was posted even if it cannot
+  /// see it, and a note of each tool used to write it.
+  pub fn content_with_placeholders(&self) -> String {
+    let mut content = self.content.clone();
+    let images = self.images.iter().map(|image| format!("[image: {}]", image.filename));
+    let tool_calls = self.tool_calls.iter().map(|tool_call| tool_call.to_string());
+    for placeholder in images.chain(tool_calls) {
+      if !content.is_empty() {
+        content.push(' ');
+      }
+      content.push_str(&placeholder);
+    }
+    content
+  }
+}
+
+impl MessageImage {
+  /// Collects the image attachments and embedded images from a
+  /// Discord message.
+  pub fn from_discord_message(msg: &DiscordMessage) -> Vec<MessageImage> {
+    let attachments = msg.attachments.iter()
+      .filter(|attachment| is_image(attachment.content_type.as_deref(), &attachment.filename))
+      .map(|attachment| MessageImage {
+        filename: attachment.filename.clone(),
+        url: attachment.url.clone(),
+        size: Some(attachment.size),
+      });
+    let embeds = msg.embeds.iter()
+      .flat_map(|embed| {
+        let image = embed.image.as_ref().map(|image| image.url.clone());
+        let thumbnail = embed.thumbnail.as_ref().map(|thumbnail| thumbnail.url.clone());
+        image.or(thumbnail)
+      })
+      .map(|url| MessageImage {
+        filename: filename_from_url(&url),
+        url,
+        size: None,
+      });
+    attachments.chain(embeds).collect()
+  }
+}
+
 impl MessageHistory {
   pub fn new(referred_cap: usize, regular_cap: usize) -> MessageHistory {
     MessageHistory {
@@ -59,4 +128,43 @@ impl MessageHistory {
   pub fn referred_messages_mut(&mut self) -> &mut CapacityDeque<Message> {
     &mut self.recent_referred_messages
   }
+
+  /// Copies the recent (non-referred) messages of `other` into this
+  /// history.
+  pub fn seed_from(&mut self, other: &MessageHistory) {
+    for message in other.messages().iter() {
+      self.recent_messages.push_back(message.clone());
+    }
+  }
+
+  /// The Discord users who have spoken recently, paired with each of
+  /// the names they go by.
+  pub fn participants(&self) -> impl Iterator<Item = (UserId, &str)> {
+    self.recent_messages.iter()
+      .flat_map(|message| match &message.user {
+        MessageUser::DiscordUser { user_id, user_proper_name, user_nickname } =>
+          vec![(*user_id, user_nickname.as_str()), (*user_id, user_proper_name.as_str())],
+        MessageUser::Marco { .. } => vec![],
+      })
+      .unique()
+  }
+}
+
+fn is_image(content_type: Option<&str>, filename: &str) -> bool {
+  const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "webp"];
+  match content_type {
+    Some(content_type) => content_type.starts_with("image/"),
+    None => {
+      let filename = filename.to_lowercase();
+      IMAGE_EXTENSIONS.iter().any(|ext| filename.ends_with(&format!(".{ext}")))
+    }
+  }
+}
+
+fn filename_from_url(url: &str) -> String {
+  let path = url.split(['?', '#']).next().unwrap_or(url);
+  path.rsplit('/')
+    .find(|segment| !segment.is_empty())
+    .unwrap_or("image")
+    .to_owned()
 }
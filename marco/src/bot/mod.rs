@@ -1,8 +1,23 @@
 
 mod base;
+pub mod appearance;
+pub mod archive;
+pub mod backfill;
 pub mod commands;
+pub mod dm;
+pub mod ensemble;
+pub mod handoff;
+pub mod markup;
 pub mod message;
+pub mod moderation;
 pub mod nicknames;
+pub mod output;
 pub mod passive;
+pub mod reactions;
+pub mod settings;
+pub mod speech;
+pub mod streaming;
+pub mod threads;
+pub mod tools;
 
 pub use base::{MarcoBot, MarcoBotConfig, MarcoBotState, gateway_intents};
@@ -0,0 +1,279 @@
+
+//! Moderation of text going to and coming from the model.
+//!
+//! Every message Marco would pass on to the model, and every reply he
+//! would send back, is screened according to the guild's
+//! [`ModerationLevel`]. Screening uses local rules (regular
+//! expressions, optionally loaded from a file) and, if enabled, the
+//! moderation endpoint of the [`ModelTask::Moderation`] backend.
+//!
+//! Blocked messages are kept out of the chat history and answered
+//! with an in-character refusal. Each one is logged, both to a file
+//! and in memory so that admins can review recent ones with
+//! `/moderation`.
+
+use super::MarcoBot;
+use super::settings::ModerationLevel;
+use crate::environ::{self, get_env_or};
+use crate::openai::backend::ModelTask;
+use crate::openai::moderation::moderate;
+use crate::personality::FullPersonality;
+use crate::util::CapacityDeque;
+
+use rand::rng;
+use rand::seq::IndexedRandom;
+use regex::{Regex, RegexBuilder};
+use serde::{Serialize, Deserialize};
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use strum::Display;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Rules which apply even without a rules file. These only cover
+/// content that no server wants Marco to repeat.
+const BUILTIN_RULES: [&str; 2] = [
+  r"\bkill\s*(?:your\s*self|urself|yo\s*self)\b",
+  r"\bkys\b",
+];
+
+/// Number of blocked items remembered per guild for `/moderation`.
+const RECENT_BLOCKED_CAPACITY: usize = 10;
+
+/// Longest excerpt of a blocked message kept in the log.
+const EXCERPT_LEN: usize = 100;
+
+/// Static configuration for moderation.
+#[derive(Debug, Clone)]
+pub struct ModerationConfig {
+  /// Whether to check text with the moderation endpoint, in addition
+  /// to the local rules.
+  pub use_endpoint: bool,
+  pub rules: Vec<ModerationRule>,
+}
+
+/// A local moderation rule: a case-insensitive regular expression.
+#[derive(Debug, Clone)]
+pub struct ModerationRule {
+  pub pattern: Regex,
+  /// If true, the rule only applies at [`ModerationLevel::Strict`].
+  pub strict_only: bool,
+}
+
+/// Whether a blocked message was on its way to the model or from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+  /// A user's message to Marco.
+  Inbound,
+  /// One of Marco's replies.
+  Outbound,
+}
+
+/// A record of a blocked message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockedItem {
+  pub time: chrono::DateTime<chrono::Utc>,
+  pub guild_id: Option<GuildId>,
+  pub channel_id: ChannelId,
+  /// The user who sent the message, or who Marco was replying to.
+  pub user_id: UserId,
+  pub direction: Direction,
+  pub reason: String,
+  pub excerpt: String,
+}
+
+/// Log of blocked messages.
+///
+/// Every blocked message is appended, as a line of JSON, to the log
+/// file, and the most recent ones are also kept in memory.
+#[derive(Debug, Clone, Default)]
+pub struct ModerationLog {
+  path: Option<PathBuf>,
+  recent: HashMap<Option<GuildId>, CapacityDeque<BlockedItem>>,
+}
+
+impl ModerationConfig {
+  pub fn from_env() -> Self {
+    let mut rules = Self::builtin_rules();
+    if let Ok(path) = env::var(environ::MODERATION_RULES) {
+      let text = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Failed to read moderation rules from {path}: {err}"));
+      let file_rules = text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+          ModerationRule::parse(line)
+            .unwrap_or_else(|err| panic!("Invalid moderation rule {line:?} in {path}: {err}"))
+        });
+      rules.extend(file_rules);
+    }
+    Self {
+      use_endpoint: get_env_or(environ::MODERATION_ENDPOINT, false),
+      rules,
+    }
+  }
+
+  fn builtin_rules() -> Vec<ModerationRule> {
+    BUILTIN_RULES.iter()
+      .map(|pattern| ModerationRule::parse(pattern).unwrap())
+      .collect()
+  }
+}
+
+impl Default for ModerationConfig {
+  fn default() -> Self {
+    Self {
+      use_endpoint: false,
+      rules: Self::builtin_rules(),
+    }
+  }
+}
+
+impl ModerationRule {
+  /// Parses a rule from a line of the rules file: a regular
+  /// expression, optionally prefixed with `strict:` to apply it only
+  /// at the strict level.
+  pub fn parse(line: &str) -> Result<Self, regex::Error> {
+    let (pattern, strict_only) = match line.strip_prefix("strict:") {
+      Some(pattern) => (pattern.trim(), true),
+      None => (line, false),
+    };
+    let pattern = RegexBuilder::new(pattern).case_insensitive(true).build()?;
+    Ok(Self { pattern, strict_only })
+  }
+}
+
+impl ModerationLog {
+  /// A log which appends to the file at `path`.
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: Some(path.into()), recent: HashMap::new() }
+  }
+
+  /// Remembers the item for `/moderation`. Returns the file it should
+  /// be appended to, which is left to the caller so that it can be
+  /// written without holding the state lock.
+  pub fn record(&mut self, item: BlockedItem) -> Option<PathBuf> {
+    self.recent.entry(item.guild_id)
+      .or_insert_with(|| CapacityDeque::new(RECENT_BLOCKED_CAPACITY))
+      .push_back(item);
+    self.path.clone()
+  }
+
+  /// Recently blocked messages in the guild (or in DMs, for `None`),
+  /// most recent first.
+  pub fn recent(&self, guild_id: Option<GuildId>) -> impl Iterator<Item = &BlockedItem> {
+    self.recent.get(&guild_id).into_iter().flat_map(|items| items.iter().rev())
+  }
+}
+
+/// The moderation level that applies in a guild. DMs always use the
+/// default level.
+pub fn level_for(bot: &MarcoBot, guild_id: Option<GuildId>) -> ModerationLevel {
+  match guild_id {
+    Some(guild_id) => bot.lock_state().guild_settings.get().get(guild_id).moderation,
+    None => ModerationLevel::default(),
+  }
+}
+
+/// Checks text against the local rules. Returns the reason the text
+/// is blocked, if it is.
+pub fn check_rules(config: &ModerationConfig, level: ModerationLevel, text: &str) -> Option<String> {
+  if level == ModerationLevel::Off {
+    return None;
+  }
+  config.rules.iter()
+    .filter(|rule| !rule.strict_only || level == ModerationLevel::Strict)
+    .find(|rule| rule.pattern.is_match(text))
+    .map(|rule| format!("matched rule `{}`", rule.pattern))
+}
+
+/// Checks text against the local rules and, if enabled, the
+/// moderation endpoint. Returns the reason the text is blocked, if it
+/// is.
+///
+/// If the moderation endpoint fails, the text is judged by the local
+/// rules alone.
+pub async fn check(bot: &MarcoBot, level: ModerationLevel, text: &str) -> Option<String> {
+  let config = &bot.config().moderation;
+  if let Some(reason) = check_rules(config, level, text) {
+    return Some(reason);
+  }
+  if level == ModerationLevel::Off || !config.use_endpoint || text.trim().is_empty() {
+    return None;
+  }
+  match moderate(bot.model(ModelTask::Moderation), text).await {
+    Ok(result) if result.is_flagged() && (level == ModerationLevel::Strict || result.is_severe()) => {
+      Some(format!("flagged for {}", result.categories.join(", ")))
+    }
+    Ok(_) => None,
+    Err(err) => {
+      println!("Error from moderation endpoint: {:?}", err);
+      None
+    }
+  }
+}
+
+/// Logs a blocked message.
+pub fn record_blocked(
+  bot: &MarcoBot,
+  guild_id: Option<GuildId>,
+  channel_id: ChannelId,
+  user_id: UserId,
+  direction: Direction,
+  reason: String,
+  text: &str,
+) {
+  println!("Blocked {direction} message in channel {channel_id}: {reason}");
+  let item = BlockedItem {
+    time: chrono::Utc::now(),
+    guild_id,
+    channel_id,
+    user_id,
+    direction,
+    reason,
+    excerpt: excerpt(text),
+  };
+  let path = bot.lock_state().moderation_log.record(item.clone());
+  if let Some(path) = path {
+    if let Err(err) = append_to_log(&path, &item) {
+      println!("Error writing moderation log: {:?}", err);
+    }
+  }
+}
+
+fn append_to_log(path: &Path, item: &BlockedItem) -> anyhow::Result<()> {
+  let line = serde_json::to_string(item)?;
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+  writeln!(file, "{line}")?;
+  Ok(())
+}
+
+/// An in-character refusal, sent in place of a reply to (or from) a
+/// blocked message.
+pub fn refusal(personality: &FullPersonality) -> String {
+  let name = personality.name.trim();
+  let replies = [
+    format!("*{name} shakes his head.* I'm not touching that one."),
+    format!("*{name} pretends he didn't hear that.*"),
+    format!("*{name} opens his mouth, then firmly closes it again.* Let's talk about something else."),
+  ];
+  replies.choose(&mut rng()).unwrap().clone()
+}
+
+fn excerpt(text: &str) -> String {
+  let text = text.replace('\n', " ");
+  if text.chars().count() <= EXCERPT_LEN {
+    return text;
+  }
+  let mut excerpt: String = text.chars().take(EXCERPT_LEN - 1).collect();
+  excerpt.push('…');
+  excerpt
+}
@@ -1,7 +1,8 @@
 
 use super::MarcoBot;
+use super::appearance;
+use super::handoff;
 
-use crate::personality::generate_personality;
 
 use tokio_schedule::Job;
 use serenity::prelude::Context;
@@ -28,14 +29,29 @@ pub fn schedule_reroll_task(bot: MarcoBot, ctx: Context) {
 }
 
 async fn do_passive_reroll(bot: MarcoBot, ctx: Context) -> anyhow::Result<()> {
+  {
+    // Even when nobody is talking, the time of day moves on.
+    let mut state = bot.lock_state();
+    if state.mood.refresh(chrono::Utc::now()) {
+      state.refresh_activity(&ctx);
+    }
+  }
   if !should_reroll(&bot) {
     return Ok(());
   }
   println!("Passively setting personality.");
-  let new_personality = generate_personality(bot.client()).await?;
-  let mut state = bot.lock_state();
-  state.set_personality(new_personality);
-  state.refresh_activity(&ctx);
+  // Passive rerolls are not made on behalf of any guild, so they only
+  // use the built-in characters.
+  let new_personality = bot.roll_personality(None, None).await?;
+  let archive_write = {
+    let mut state = bot.lock_state();
+    let archive_write = state.set_personality(new_personality);
+    state.refresh_activity(&ctx);
+    archive_write
+  };
+  archive_write.save_or_log();
+  appearance::update_nicknames(&bot, &ctx);
+  handoff::announce(&bot, &ctx);
   Ok(())
 }
 
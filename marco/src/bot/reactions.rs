@@ -0,0 +1,170 @@
+
+//! Emoji Marco may react to messages with.
+//!
+//! The model is offered a short list of common Unicode emoji, plus the
+//! custom emoji of the guild. Whatever it answers is checked before it
+//! reaches Discord: it must be a single Unicode emoji (from the full
+//! Unicode set, not only those offered) or one of the guild's custom
+//! emoji. Anything else (a sentence, several emoji, or an emoji from
+//! another server) is discarded.
+
+use crate::environ::{self, get_env_or};
+
+use rand::rng;
+use rand::seq::SliceRandom;
+use serenity::prelude::*;
+use serenity::model::channel::ReactionType;
+use serenity::model::id::{EmojiId, GuildId};
+
+
+/// Configuration for emoji reactions.
+#[derive(Debug, Clone)]
+pub struct ReactionConfig {
+  /// Most custom emoji from a guild to offer the model. Guilds can
+  /// have hundreds, which would crowd out the rest of the prompt.
+  pub max_custom_emoji: usize,
+}
+
+/// Unicode emoji suggested to the model, in their fully-qualified
+/// forms. Marco may react with any other Unicode emoji too.
+pub const UNICODE_EMOJI: &[&str] = &[
+  // Faces
+  "😀", "😁", "😂", "🤣", "😅", "😆", "😉", "😊", "😇", "🥰", "😍", "🤩",
+  "😘", "😋", "😛", "😜", "🤪", "🤑", "🤗", "🤭", "🤫", "🤔", "🤐", "🤨",
+  "😐", "😑", "😶", "😏", "😒", "🙄", "😬", "😌", "😔", "😪", "😴", "🤤",
+  "😷", "🤒", "🤢", "🤮", "🥵", "🥶", "🥴", "😵", "🤯", "🤠", "🥳", "😎",
+  "🤓", "🧐", "😕", "😟", "🙁", "😮", "😯", "😲", "😳", "🥺", "😦", "😧",
+  "😨", "😰", "😥", "😢", "😭", "😱", "😖", "😣", "😞", "😓", "😩", "😫",
+  "🥱", "😤", "😡", "😠", "🤬", "😈", "👿", "💀", "☠️", "🤡", "👹", "👺",
+  "👻", "👽", "🤖", "💩", "🙈", "🙉", "🙊",
+  // Hands and people
+  "👍", "👎", "👏", "🙌", "🙏", "🤝", "👋", "✌️", "🤞", "🤟", "🤘", "👌",
+  "🤌", "👀", "🫡", "🤷", "🤦", "💪", "🧠", "👑", "🎩",
+  // Hearts and symbols
+  "❤️", "🧡", "💛", "💚", "💙", "💜", "🖤", "💔", "💯", "💢", "💥", "💫",
+  "💦", "💤", "❗", "❓", "‼️", "⁉️", "✅", "❌", "⚠️", "🚫", "✨", "⭐",
+  "🌟", "🔥", "💧", "⚡", "🌈", "☀️", "🌙", "❄️", "☕", "🍵", "🍕", "🍔",
+  "🍿", "🍰", "🎂", "🍷", "🍺", "🥂",
+  // Animals and nature
+  "🐍", "🐶", "🐱", "🐸", "🐢", "🦄", "🐉", "🦖", "🐙", "🦀", "🐝", "🦋",
+  "🌵", "🌹", "🍀", "🍄",
+  // Objects and activities
+  "🎉", "🎊", "🎈", "🎁", "🏆", "🥇", "🎯", "🎲", "🎭", "🎨", "🎵", "🎶",
+  "🎸", "📚", "📜", "✏️", "🔍", "💡", "🔮", "🪄", "🧪", "⚗️", "🔭", "💰",
+  "💎", "🗡️", "⚔️", "🛡️", "🏴‍☠️", "🚀", "🛸", "⏰", "⌛", "📈", "📉",
+];
+
+/// The emoji Marco may react with in one place. Only some of the
+/// custom emoji are offered to the model at a time, but Marco may
+/// react with any of them.
+#[derive(Debug, Clone, Default)]
+pub struct EmojiCandidates {
+  custom: Vec<CustomEmoji>,
+}
+
+#[derive(Debug, Clone)]
+struct CustomEmoji {
+  id: EmojiId,
+  name: String,
+  animated: bool,
+}
+
+impl ReactionConfig {
+  pub const DEFAULT_MAX_CUSTOM_EMOJI: usize = 50;
+
+  pub fn from_env() -> Self {
+    Self {
+      max_custom_emoji: get_env_or(environ::MAX_CUSTOM_EMOJI, Self::DEFAULT_MAX_CUSTOM_EMOJI),
+    }
+  }
+}
+
+impl Default for ReactionConfig {
+  fn default() -> Self {
+    Self { max_custom_emoji: Self::DEFAULT_MAX_CUSTOM_EMOJI }
+  }
+}
+
+impl EmojiCandidates {
+  /// The candidates in the given guild (or in DMs, for `None`), which
+  /// include the guild's custom emoji.
+  pub fn for_guild(ctx: &Context, guild_id: Option<GuildId>) -> Self {
+    let Some(guild) = guild_id.and_then(|guild_id| ctx.cache.guild(guild_id)) else {
+      return Self::default();
+    };
+    let custom = guild.emojis.values()
+      .filter(|emoji| emoji.available)
+      .map(|emoji| CustomEmoji { id: emoji.id, name: emoji.name.clone(), animated: emoji.animated })
+      .collect();
+    Self { custom }
+  }
+
+  /// The candidates to offer the model for a reaction to `message`, as
+  /// a list. Custom emoji are written as `:name:`.
+  ///
+  /// At most `max_custom` custom emoji are listed. Those that appear
+  /// in the message come first, and the rest are chosen at random, so
+  /// that every emoji gets its turn.
+  pub fn prompt_list(&self, message: &str, max_custom: usize) -> String {
+    let mut custom: Vec<_> = self.custom.iter().collect();
+    custom.shuffle(&mut rng());
+    custom.sort_by_key(|emoji| !message.contains(&format!(":{}:", emoji.name)));
+    custom.truncate(max_custom);
+    let unicode = UNICODE_EMOJI.iter().map(|emoji| (*emoji).to_owned());
+    let custom = custom.into_iter().map(|emoji| format!(":{}:", emoji.name));
+    unicode.chain(custom).collect::<Vec<_>>().join(" ")
+  }
+
+  /// The reaction the model meant by `answer`, if it is a Unicode
+  /// emoji or one of the custom candidates.
+  pub fn resolve(&self, answer: &str) -> Option<ReactionType> {
+    let answer = answer.trim();
+    // Unqualified forms (without variation selectors) are looked up
+    // too, but Discord only accepts the fully-qualified ones.
+    if let Some(emoji) = emojis::get(answer) {
+      return Some(ReactionType::Unicode(emoji.as_str().to_owned()));
+    }
+    // Custom emoji may come back as `:name:`, `name`, or in Discord's
+    // own `<:name:id>` markup.
+    let name = match answer.strip_prefix('<').and_then(|markup| markup.strip_suffix('>')) {
+      Some(markup) => markup.split(':').nth(1).unwrap_or_default(),
+      None => answer.trim_matches(':'),
+    };
+    self.custom.iter()
+      .find(|emoji| emoji.name.eq_ignore_ascii_case(name))
+      .map(|emoji| ReactionType::Custom { animated: emoji.animated, id: emoji.id, name: Some(emoji.name.clone()) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn resolve(answer: &str) -> Option<String> {
+    match EmojiCandidates::default().resolve(answer)? {
+      ReactionType::Unicode(emoji) => Some(emoji),
+      other => panic!("unexpected reaction {other:?}"),
+    }
+  }
+
+  #[test]
+  fn test_any_unicode_emoji() {
+    assert_eq!(resolve("🦆").as_deref(), Some("🦆"));
+    assert_eq!(resolve(" 👍🏽 ").as_deref(), Some("👍🏽"));
+    assert_eq!(resolve("🇫🇷").as_deref(), Some("🇫🇷"));
+  }
+
+  #[test]
+  fn test_unqualified_emoji() {
+    assert_eq!(resolve("\u{2764}").as_deref(), Some("❤️"));
+    assert_eq!(resolve("\u{2764}\u{FE0F}").as_deref(), Some("❤️"));
+  }
+
+  #[test]
+  fn test_not_an_emoji() {
+    assert_eq!(resolve("thumbs up"), None);
+    assert_eq!(resolve("👍👍"), None);
+    assert_eq!(resolve(":fire:"), None);
+    assert_eq!(resolve(""), None);
+  }
+}
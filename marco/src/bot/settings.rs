@@ -0,0 +1,101 @@
+
+//! Per-guild settings, configurable by server administrators, and
+//! per-user settings, configurable by each user.
+
+use serde::{Serialize, Deserialize};
+use crate::personality::{BaseCharacter, CustomCharacter};
+
+use serenity::model::id::{GuildId, UserId};
+use strum::{Display, EnumString, VariantArray};
+
+use std::collections::HashMap;
+
+/// Settings for a single Discord guild.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+  pub threads: ThreadParticipation,
+  pub moderation: ModerationLevel,
+  /// Characters defined by the guild's admins, in addition to the
+  /// built-in ones.
+  pub characters: Vec<CustomCharacter>,
+}
+
+/// Which threads Marco participates in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Display, EnumString, VariantArray,
+         Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum ThreadParticipation {
+  /// Treat threads exactly like ordinary channels.
+  #[default]
+  All,
+  /// Only respond in threads when directly pinged or replied to.
+  MentionsOnly,
+  /// Never respond in threads. Emoji reactions still happen.
+  Ignore,
+}
+
+/// How strictly Marco screens messages, both those sent to him and his
+/// own replies. See [`super::moderation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Display, EnumString, VariantArray,
+         Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum ModerationLevel {
+  /// No screening at all.
+  Off,
+  /// Block only the most serious content.
+  #[default]
+  Standard,
+  /// Block anything that any moderation rule or category flags.
+  Strict,
+}
+
+/// Settings for every guild Marco knows about.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuildSettingsMap {
+  guilds: HashMap<GuildId, GuildSettings>,
+}
+
+impl GuildSettingsMap {
+  /// The settings for the given guild, or the default settings if
+  /// that guild has never been configured.
+  pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+    self.guilds.get(&guild_id).cloned().unwrap_or_default()
+  }
+
+  pub fn get_mut(&mut self, guild_id: GuildId) -> &mut GuildSettings {
+    self.guilds.entry(guild_id).or_default()
+  }
+}
+
+/// Settings for a single Discord user.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserSettings {
+  /// Whether the user has opted in to chatting with Marco in direct
+  /// messages.
+  pub dm_enabled: bool,
+  /// The character Marco plays in this user's direct messages. If
+  /// absent, Marco uses his current personality.
+  pub dm_character: Option<BaseCharacter>,
+}
+
+/// Settings for every user who has configured anything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserSettingsMap {
+  users: HashMap<UserId, UserSettings>,
+}
+
+impl UserSettingsMap {
+  /// The settings for the given user, or the default settings if that
+  /// user has never configured anything.
+  pub fn get(&self, user_id: UserId) -> UserSettings {
+    self.users.get(&user_id).cloned().unwrap_or_default()
+  }
+
+  pub fn get_mut(&mut self, user_id: UserId) -> &mut UserSettings {
+    self.users.entry(user_id).or_default()
+  }
+}
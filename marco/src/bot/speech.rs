@@ -0,0 +1,113 @@
+
+//! Messages from personalities other than the one replying to a
+//! message, such as the cast of an ensemble scene or either side of a
+//! handoff.
+//!
+//! These are sent unprompted, with no tools, but otherwise go through
+//! the same moderation and formatting as any of Marco's replies.
+
+use super::MarcoBot;
+use super::appearance;
+use super::markup;
+use super::message::{Message, MessageUser};
+use super::moderation::{self, Direction};
+use super::output;
+use crate::personality::{FullPersonality, Mood};
+use crate::openai::backend::ModelTask;
+use crate::openai::responder::{ChatSpeaker, chat_completion, lost_for_words_reply};
+use crate::openai::tools::NoTools;
+
+use serenity::prelude::*;
+use serenity::model::channel;
+use serenity::model::id::{ChannelId, GuildId, UserId};
+use serenity::builder::CreateMessage;
+
+/// A message for a personality to send.
+#[derive(Debug, Clone, Copy)]
+pub struct Speech<'a> {
+  pub identity_id: usize,
+  pub personality: &'a FullPersonality,
+  pub mood: Option<Mood>,
+  /// Instructions for this message in particular, if any.
+  pub instruction: Option<&'a str>,
+  /// Whether to put the personality's name in front of the message
+  /// when it cannot be sent through a webhook.
+  pub label: bool,
+}
+
+/// Has the personality reply to the chat history of `channel_id`, and
+/// adds the reply to the history. `prompted_by` is blamed if the reply
+/// is blocked by moderation.
+///
+/// Returns the messages sent, or `None` if nothing could be sent.
+pub async fn speak(
+  bot: &MarcoBot,
+  ctx: &Context,
+  channel_id: ChannelId,
+  guild_id: Option<GuildId>,
+  prompted_by: UserId,
+  speech: Speech<'_>,
+) -> Option<Vec<channel::Message>> {
+  let prompts = bot.prompts(ctx, guild_id);
+  let (responder, known_users) = {
+    let mut state = bot.lock_state();
+    let history = state.message_history_mut(channel_id, None);
+    let responder = chat_completion(
+      bot.model(ModelTask::Chat),
+      ChatSpeaker {
+        identity_id: speech.identity_id,
+        personality: speech.personality,
+        mood: speech.mood,
+        handoff: speech.instruction,
+      },
+      history.messages().iter(),
+      history.referred_messages().iter(),
+      &prompts,
+      &bot.config().vision,
+    ).with_typing_notification(ctx, channel_id);
+    let known_users: Vec<(UserId, String)> = history.participants()
+      .map(|(user_id, name)| (user_id, name.to_owned()))
+      .collect();
+    (responder, known_users)
+  };
+  let text = match responder.chat(bot.model(ModelTask::Chat), &NoTools).await {
+    Ok(reply) if reply.text.trim().is_empty() => lost_for_words_reply(speech.personality),
+    Ok(reply) => reply.text,
+    Err(err) => {
+      println!("Error from OpenAI for {}: {:?}", speech.personality.name.trim(), err);
+      return None;
+    }
+  };
+  let moderation_level = moderation::level_for(bot, guild_id);
+  let text = match moderation::check(bot, moderation_level, &text).await {
+    None => text,
+    Some(reason) => {
+      moderation::record_blocked(bot, guild_id, channel_id, prompted_by, Direction::Outbound, reason, &text);
+      moderation::refusal(speech.personality)
+    }
+  };
+  let known_users = known_users.iter().map(|(user_id, name)| (*user_id, name.as_str()));
+  let rendered = markup::resolve_outbound(&ctx.cache, guild_id, &output::redact_secrets(&text), known_users);
+  let name = speech.personality.name.trim();
+  let sent = match appearance::send_via_webhook(bot, ctx, channel_id, guild_id, speech.personality, &rendered.text, &rendered.mentions).await {
+    Some(sent) => Ok(sent),
+    None => {
+      let content = if speech.label { format!("**{name}:** {}", rendered.text) } else { rendered.text };
+      output::send_reply(ctx, channel_id, CreateMessage::default(), &content, &rendered.mentions).await
+    }
+  };
+  let sent = match sent {
+    Ok(sent) => sent,
+    Err(why) => {
+      println!("Error sending message: {:?}", why);
+      return None;
+    }
+  };
+  bot.lock_state().message_history_mut(channel_id, None).push_back(Message {
+    user: MessageUser::Marco { identity_id: speech.identity_id, identity: name.to_owned() },
+    content: text,
+    images: Vec::new(),
+    tool_calls: Vec::new(),
+  }, true);
+  Some(sent)
+}
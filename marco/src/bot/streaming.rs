@@ -0,0 +1,176 @@
+
+//! Streaming replies into Discord as they are generated.
+//!
+//! Rather than waiting for the whole response, Marco posts his reply
+//! as soon as the first sentence is complete and then edits the
+//! message as more text arrives. Edits are throttled to stay well
+//! within Discord's rate limits.
+
+use super::markup::ResolvedText;
+use super::output::{self, SENTENCE_END_RE, allowed_mentions, truncate_message};
+use crate::environ::{self, get_env_or};
+use crate::openai::responder::{StreamingReply, ChatReply};
+use crate::openai::tools::ToolCallRecord;
+
+use serenity::prelude::*;
+use serenity::builder::{CreateMessage, EditMessage};
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, MessageId};
+
+use std::time::{Duration, Instant};
+
+/// Configuration for streaming replies.
+#[derive(Debug, Clone)]
+pub struct StreamingConfig {
+  /// Whether replies are streamed at all. If false, Marco waits for
+  /// the complete response before sending anything.
+  pub enabled: bool,
+  /// Minimum time between consecutive edits of a streamed message.
+  pub edit_interval: Duration,
+}
+
+/// A streamed reply which failed before anything was posted.
+#[derive(Debug)]
+pub struct StreamFailure {
+  pub error: anyhow::Error,
+  /// The tools which already ran while streaming. Tools have side
+  /// effects, so a fallback must not run them again.
+  pub tool_calls: Vec<ToolCallRecord>,
+}
+
+impl StreamingConfig {
+  pub const DEFAULT_EDIT_INTERVAL_MS: u64 = 1200;
+
+  pub fn from_env() -> Self {
+    Self {
+      enabled: get_env_or(environ::STREAM_REPLIES, false),
+      edit_interval: Duration::from_millis(
+        get_env_or(environ::STREAM_EDIT_INTERVAL_MS, Self::DEFAULT_EDIT_INTERVAL_MS),
+      ),
+    }
+  }
+}
+
+impl Default for StreamingConfig {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      edit_interval: Duration::from_millis(Self::DEFAULT_EDIT_INTERVAL_MS),
+    }
+  }
+}
+
+/// Streams `reply` into `channel_id`, returning the complete
+/// response.
+///
+/// `new_message` is the base for the message that gets posted (for
+/// instance, it may already reference the message being replied to),
+/// and `render` converts model output into the text that is actually
+/// shown on Discord, along with the users it may ping.
+///
+/// Returns the reply along with the IDs of the messages it was posted
+/// as.
+///
+/// If something goes wrong after the message has been posted, the
+/// message is left with whatever text arrived so far. If something
+/// goes wrong before anything was posted, this returns an error so
+/// that the caller can fall back to a one-shot response (provided no
+/// tools have run yet).
+pub async fn stream_reply(
+  ctx: &Context,
+  channel_id: ChannelId,
+  new_message: CreateMessage,
+  mut reply: StreamingReply<'_>,
+  render: impl Fn(&str) -> ResolvedText,
+  config: &StreamingConfig,
+) -> Result<(ChatReply, Vec<MessageId>), StreamFailure> {
+  let fail = |error: anyhow::Error, reply: &StreamingReply<'_>| StreamFailure {
+    error,
+    tool_calls: reply.tool_calls().to_vec(),
+  };
+  let mut posted: Option<Message> = None;
+  let mut shown_text = String::new();
+  let mut last_edit = Instant::now();
+
+  while let Some(chunk) = reply.next_chunk().await {
+    if let Err(err) = chunk {
+      if posted.is_none() {
+        return Err(fail(err.into(), &reply));
+      }
+      println!("Error while streaming response, keeping partial message: {:?}", err);
+      let message_ids = posted.iter().map(|message| message.id).collect();
+      return Ok((ChatReply { text: shown_text, ..reply.finish() }, message_ids));
+    }
+    let Some(text) = reply.partial_text() else { continue };
+    match &mut posted {
+      None => {
+        // Wait for a full sentence before posting anything.
+        if !SENTENCE_END_RE.is_match(&text) {
+          continue;
+        }
+        let rendered = render(&text);
+        let message = new_message.clone()
+          .content(truncate_message(&rendered.text))
+          .allowed_mentions(allowed_mentions(&rendered.mentions));
+        let message = channel_id.send_message(&ctx.http, message).await
+          .map_err(|err| fail(err.into(), &reply))?;
+        posted = Some(message);
+        shown_text = text;
+        last_edit = Instant::now();
+      }
+      Some(message) => {
+        if last_edit.elapsed() < config.edit_interval || text == shown_text {
+          continue;
+        }
+        let rendered = render(&text);
+        let edit = EditMessage::new()
+          .content(truncate_message(&rendered.text))
+          .allowed_mentions(allowed_mentions(&rendered.mentions));
+        if let Err(err) = message.edit(&ctx.http, edit).await {
+          println!("Error editing streamed message: {:?}", err);
+        }
+        shown_text = text;
+        last_edit = Instant::now();
+      }
+    }
+  }
+
+  let final_reply = reply.finish();
+  let final_text = &final_reply.text;
+  let mut message_ids = Vec::new();
+  match &mut posted {
+    None => {
+      let fail = |error| StreamFailure { error, tool_calls: final_reply.tool_calls.clone() };
+      if final_text.trim().is_empty() {
+        return Err(fail(anyhow::anyhow!("Streamed response was empty")));
+      }
+      let rendered = render(final_text);
+      let sent = output::send_reply(ctx, channel_id, new_message, &rendered.text, &rendered.mentions).await
+        .map_err(|err| fail(err.into()))?;
+      message_ids.extend(sent.iter().map(|message| message.id));
+    }
+    Some(message) => {
+      message_ids.push(message.id);
+      if *final_text != shown_text {
+        // The streamed message holds the first part of the reply, and
+        // any overflow goes in follow-up messages.
+        let rendered = render(final_text);
+        let mut parts = output::split_message(&rendered.text).into_iter();
+        let edit = EditMessage::new()
+          .content(parts.next().unwrap_or_default())
+          .allowed_mentions(allowed_mentions(&rendered.mentions));
+        if let Err(err) = message.edit(&ctx.http, edit).await {
+          println!("Error editing streamed message: {:?}", err);
+        }
+        for part in parts {
+          let follow_up = CreateMessage::new().content(part).allowed_mentions(allowed_mentions(&rendered.mentions));
+          match channel_id.send_message(&ctx.http, follow_up).await {
+            Ok(follow_up) => message_ids.push(follow_up.id),
+            Err(err) => println!("Error sending rest of streamed message: {:?}", err),
+          }
+        }
+      }
+    }
+  }
+  Ok((final_reply, message_ids))
+}
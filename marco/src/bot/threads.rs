@@ -0,0 +1,90 @@
+
+//! Thread participation and spin-off helpers.
+
+use super::MarcoBotState;
+use super::message::{MessageHistory, MessageUser};
+use crate::environ::{self, get_env_or};
+
+use serenity::prelude::*;
+use serenity::model::channel::{Message, GuildChannel};
+use serenity::model::id::UserId;
+use serenity::builder::CreateThread;
+
+/// Discord's limit on thread name length.
+const MAX_THREAD_NAME_LEN: usize = 100;
+
+/// Static configuration for how Marco behaves around threads.
+///
+/// Which threads Marco participates in is a per-guild setting; see
+/// [`super::settings::ThreadParticipation`].
+#[derive(Debug, Clone)]
+pub struct ThreadConfig {
+  /// If true, a thread's history starts out with the recent messages
+  /// of its parent channel.
+  pub seed_from_parent: bool,
+  /// After this many consecutive messages exchanged between Marco and
+  /// a single user in a channel, Marco moves the conversation into a
+  /// new thread. Zero disables this behavior. At most
+  /// [`MarcoBotState::MESSAGE_HISTORY_CAPACITY`], since Marco cannot
+  /// count an exchange longer than he remembers.
+  pub auto_thread_after: usize,
+}
+
+impl ThreadConfig {
+  pub fn from_env() -> Self {
+    let mut auto_thread_after = get_env_or(environ::AUTO_THREAD_AFTER, 0);
+    if auto_thread_after > MarcoBotState::MESSAGE_HISTORY_CAPACITY {
+      println!(
+        "{} is more than the {} messages Marco remembers; using {} instead",
+        environ::AUTO_THREAD_AFTER, MarcoBotState::MESSAGE_HISTORY_CAPACITY, MarcoBotState::MESSAGE_HISTORY_CAPACITY,
+      );
+      auto_thread_after = MarcoBotState::MESSAGE_HISTORY_CAPACITY;
+    }
+    Self {
+      seed_from_parent: get_env_or(environ::THREAD_SEED_FROM_PARENT, true),
+      auto_thread_after,
+    }
+  }
+}
+
+impl Default for ThreadConfig {
+  fn default() -> Self {
+    Self {
+      seed_from_parent: true,
+      auto_thread_after: 0,
+    }
+  }
+}
+
+/// Whether the most recent messages in `history` are a back-and-forth
+/// solely between Marco and `user_id`, at least `threshold` messages
+/// long.
+pub fn is_long_exchange(history: &MessageHistory, user_id: UserId, threshold: usize) -> bool {
+  if threshold == 0 {
+    return false;
+  }
+  let exchange: Vec<_> = history.messages().iter()
+    .rev()
+    .take_while(|message| match &message.user {
+      MessageUser::DiscordUser { user_id: sender, .. } => *sender == user_id,
+      MessageUser::Marco { .. } => true,
+    })
+    .collect();
+  let marco_spoke = exchange.iter().any(|message| matches!(message.user, MessageUser::Marco { .. }));
+  marco_spoke && exchange.len() >= threshold
+}
+
+/// Starts a new thread from `msg` to continue a conversation between
+/// the message author and Marco.
+pub async fn spin_off_thread(
+  ctx: &Context,
+  msg: &Message,
+  user_name: &str,
+  personality_name: &str,
+) -> serenity::Result<GuildChannel> {
+  let name: String = format!("{user_name} and {personality_name}")
+    .chars()
+    .take(MAX_THREAD_NAME_LEN)
+    .collect();
+  msg.channel_id.create_thread_from_message(&ctx.http, msg.id, CreateThread::new(name)).await
+}
@@ -0,0 +1,406 @@
+
+//! The tools Marco can use while replying to a message.
+//!
+//! Each tool can be made available to everyone, only to moderators
+//! (members with the Manage Server permission), or to no one, via
+//! `MARCO_TOOL_<NAME>_ACCESS`. `MARCO_TOOLS_ENABLED=false` turns tool
+//! calling off entirely, which is useful for backends whose models do
+//! not support it.
+
+use super::MarcoBot;
+use super::appearance;
+use super::output;
+use super::reactions::EmojiCandidates;
+use crate::environ::{self, get_env_or};
+use crate::openai::tools::{ToolExecutor, function_tool};
+
+use async_openai::types::ChatCompletionTool;
+use async_trait::async_trait;
+use chrono_tz::Tz;
+use itertools::Itertools;
+use rand::Rng;
+use regex::Regex;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serenity::prelude::*;
+use serenity::builder::{CreateAllowedMentions, CreateMessage};
+use serenity::model::channel::Message;
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use strum::{Display, EnumString, VariantArray};
+
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Dice notation, such as `d20`, `3d6`, or `2d8+4`.
+static DICE_RE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"(?i)^(\d*)d(\d+)\s*(?:([+-])\s*(\d+))?$").unwrap());
+
+const MAX_DICE: u32 = 100;
+const MAX_DIE_SIDES: u32 = 1000;
+const MAX_DICE_MODIFIER: i64 = 10_000;
+
+/// Longest reminder Marco will set, in minutes (one week). Reminders
+/// are not persisted, so they are lost if Marco restarts.
+const MAX_REMINDER_MINUTES: u64 = 7 * 24 * 60;
+
+/// A tool Marco can call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, VariantArray)]
+#[strum(serialize_all = "snake_case")]
+pub enum Tool {
+  RollDice,
+  CurrentTime,
+  CurrentPersonality,
+  PastPersonalities,
+  Reroll,
+  SetReminder,
+  React,
+}
+
+/// Who may cause Marco to use a tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum ToolAccess {
+  Everyone,
+  /// Only members with the Manage Server permission. Tools with this
+  /// access level are never available in DMs.
+  Moderators,
+  Disabled,
+}
+
+/// Which tools are available, and to whom.
+#[derive(Debug, Clone)]
+pub struct ToolConfig {
+  pub enabled: bool,
+  access: HashMap<Tool, ToolAccess>,
+}
+
+/// The tools available while replying to a single Discord message.
+#[derive(Debug)]
+pub struct DiscordTools<'a> {
+  bot: &'a MarcoBot,
+  ctx: &'a Context,
+  msg: &'a Message,
+  reply_channel_id: ChannelId,
+  dm_user: Option<UserId>,
+  available: Vec<Tool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RollDiceArgs {
+  dice: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CurrentTimeArgs {
+  timezone: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RerollArgs {
+  character_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetReminderArgs {
+  minutes: u64,
+  message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReactArgs {
+  emoji: String,
+}
+
+impl Tool {
+  /// Rerolling changes Marco's personality for everyone, so by
+  /// default only moderators can ask for it in conversation.
+  pub fn default_access(self) -> ToolAccess {
+    match self {
+      Tool::Reroll => ToolAccess::Moderators,
+      _ => ToolAccess::Everyone,
+    }
+  }
+
+  fn definition(self) -> ChatCompletionTool {
+    let name = self.to_string();
+    match self {
+      Tool::RollDice => function_tool(&name, "Roll dice, given in standard dice notation.", serde_json::json!({
+        "type": "object",
+        "properties": {
+          "dice": { "type": "string", "description": "Dice notation, such as \"d20\", \"3d6\", or \"2d8+4\"" },
+        },
+        "required": ["dice"],
+      })),
+      Tool::CurrentTime => function_tool(&name, "Get the current date and time.", serde_json::json!({
+        "type": "object",
+        "properties": {
+          "timezone": { "type": "string", "description": "IANA time zone name, such as \"America/New_York\". Defaults to UTC." },
+        },
+      })),
+      Tool::CurrentPersonality => function_tool(&name, "Look up the details of your current personality.", serde_json::json!({
+        "type": "object",
+        "properties": {},
+      })),
+      Tool::PastPersonalities => function_tool(&name, "List your most recent past personalities, most recent first.", serde_json::json!({
+        "type": "object",
+        "properties": {},
+      })),
+      Tool::Reroll => function_tool(&name, "Replace your personality with a brand new one. Only do this if explicitly asked to.", serde_json::json!({
+        "type": "object",
+        "properties": {
+          "character_name": { "type": "string", "description": "Name of a character template to base the new personality on" },
+        },
+      })),
+      Tool::SetReminder => function_tool(&name, "Remind the user about something after a delay.", serde_json::json!({
+        "type": "object",
+        "properties": {
+          "minutes": { "type": "integer", "description": "How many minutes from now to send the reminder" },
+          "message": { "type": "string", "description": "What to remind the user about" },
+        },
+        "required": ["minutes", "message"],
+      })),
+      Tool::React => function_tool(&name, "React to the user's message with an emoji.", serde_json::json!({
+        "type": "object",
+        "properties": {
+          "emoji": { "type": "string", "description": "A single emoji, or the :name: of one of the server's custom emoji" },
+        },
+        "required": ["emoji"],
+      })),
+    }
+  }
+}
+
+impl ToolConfig {
+  pub fn from_env() -> Self {
+    let access = Tool::VARIANTS.iter()
+      .map(|&tool| {
+        let var = format!("MARCO_TOOL_{}_ACCESS", tool.to_string().to_uppercase());
+        (tool, get_env_or(&var, tool.default_access()))
+      })
+      .collect();
+    Self {
+      enabled: get_env_or(environ::TOOLS_ENABLED, true),
+      access,
+    }
+  }
+
+  pub fn access(&self, tool: Tool) -> ToolAccess {
+    if !self.enabled {
+      return ToolAccess::Disabled;
+    }
+    self.access.get(&tool).copied().unwrap_or_else(|| tool.default_access())
+  }
+}
+
+impl Default for ToolConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      access: Tool::VARIANTS.iter().map(|&tool| (tool, tool.default_access())).collect(),
+    }
+  }
+}
+
+impl<'a> DiscordTools<'a> {
+  /// The tools available to the author of `msg`. Marco's reply goes to
+  /// `reply_channel_id`, and `dm_user` is set if the message is a DM.
+  pub async fn for_message(
+    bot: &'a MarcoBot,
+    ctx: &'a Context,
+    msg: &'a Message,
+    reply_channel_id: ChannelId,
+    dm_user: Option<UserId>,
+  ) -> Self {
+    let config = &bot.config().tools;
+    let needs_moderator = Tool::VARIANTS.iter().any(|&tool| config.access(tool) == ToolAccess::Moderators);
+    let is_moderator = match msg.guild_id {
+      Some(guild_id) if needs_moderator => is_moderator(ctx, guild_id, msg.author.id).await,
+      _ => false,
+    };
+    let available = Tool::VARIANTS.iter()
+      .copied()
+      .filter(|&tool| {
+        // Rerolling would change the shared personality from inside
+        // someone's DMs.
+        if tool == Tool::Reroll && dm_user.is_some() {
+          return false;
+        }
+        match config.access(tool) {
+          ToolAccess::Everyone => true,
+          ToolAccess::Moderators => is_moderator,
+          ToolAccess::Disabled => false,
+        }
+      })
+      .collect();
+    Self { bot, ctx, msg, reply_channel_id, dm_user, available }
+  }
+
+  fn roll_dice(&self, args: RollDiceArgs) -> String {
+    let Some(caps) = DICE_RE.captures(args.dice.trim()) else {
+      return format!("Error: {:?} is not valid dice notation", args.dice);
+    };
+    let count: u32 = caps[1].parse().unwrap_or(1);
+    let sides: u32 = caps[2].parse().unwrap_or(0);
+    let modifier: i64 = caps.get(4).and_then(|m| m.as_str().parse().ok()).unwrap_or(0);
+    let modifier = if caps.get(3).is_some_and(|sign| sign.as_str() == "-") { -modifier } else { modifier };
+    if !(1..=MAX_DICE).contains(&count) || !(2..=MAX_DIE_SIDES).contains(&sides) || modifier.abs() > MAX_DICE_MODIFIER {
+      return format!("Error: at most {MAX_DICE} dice with 2 to {MAX_DIE_SIDES} sides each can be rolled");
+    }
+    let mut rng = rand::rng();
+    let rolls: Vec<u32> = (0..count).map(|_| rng.random_range(1..=sides)).collect();
+    let total = rolls.iter().map(|&roll| i64::from(roll)).sum::<i64>() + modifier;
+    let rolls = rolls.iter().join(", ");
+    match modifier {
+      0 => format!("Rolled {}: [{rolls}] = {total}", args.dice.trim()),
+      _ => format!("Rolled {}: [{rolls}] {modifier:+} = {total}", args.dice.trim()),
+    }
+  }
+
+  fn current_time(&self, args: CurrentTimeArgs) -> String {
+    let timezone = args.timezone.as_deref().map(str::trim).filter(|tz| !tz.is_empty()).unwrap_or("UTC");
+    let Ok(tz) = timezone.parse::<Tz>() else {
+      return format!("Error: unknown time zone {timezone:?}");
+    };
+    let now = chrono::Utc::now().with_timezone(&tz);
+    format!("{} ({tz})", now.format("%A, %B %-d, %Y, %-I:%M %p %Z"))
+  }
+
+  fn current_personality(&self) -> String {
+    let state = self.bot.lock_state();
+    let (_, personality) = state.speaker(self.dm_user);
+    format!("{} {}", personality.tagline(), personality.description)
+  }
+
+  fn past_personalities(&self) -> String {
+    let state = self.bot.lock_state();
+    if state.past_personalities.is_empty() {
+      return String::from("You have not had any other personalities yet.");
+    }
+    state.past_personalities.iter()
+      .rev()
+      .map(|personality| format!("- {}", personality.tagline()))
+      .join("\n")
+  }
+
+  async fn reroll(&self, args: RerollArgs) -> String {
+    let base_character = match args.character_name.as_deref().map(str::trim).filter(|name| !name.is_empty()) {
+      Some(character_name) => {
+        let Some(base_character) = self.bot.find_character(character_name, self.msg.guild_id) else {
+          return format!("Error: unknown character {character_name:?}");
+        };
+        Some(base_character)
+      }
+      None => None,
+    };
+    let new_personality = match self.bot.roll_personality(base_character, self.msg.guild_id).await {
+      Ok(new_personality) => new_personality,
+      Err(err) => {
+        println!("Error generating personality for reroll tool: {:?}", err);
+        return String::from("Error: failed to generate a new personality");
+      }
+    };
+    let tagline = new_personality.tagline();
+    let archive_write = {
+      let mut state = self.bot.lock_state();
+      let archive_write = state.set_personality(new_personality);
+      state.refresh_activity(self.ctx);
+      archive_write
+    };
+    archive_write.save_or_log();
+    appearance::update_nicknames(self.bot, self.ctx);
+    // No farewell or introduction here: the reply being written is the
+    // old personality's farewell, and the new one will be told about
+    // the handoff the next time it speaks.
+    format!("Your personality has been replaced. After this message, you will be: {tagline}")
+  }
+
+  fn set_reminder(&self, args: SetReminderArgs) -> String {
+    if !(1..=MAX_REMINDER_MINUTES).contains(&args.minutes) {
+      return format!("Error: reminders must be between 1 and {MAX_REMINDER_MINUTES} minutes from now");
+    }
+    let http = self.ctx.http.clone();
+    let channel_id = self.reply_channel_id;
+    let user_id = self.msg.author.id;
+    let message = CreateMessage::new()
+      .content(output::truncate_message(&format!("<@{user_id}> Reminder: {}", output::redact_secrets(args.message.trim()))))
+      .allowed_mentions(CreateAllowedMentions::new().users([user_id]));
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_secs(args.minutes * 60)).await;
+      if let Err(err) = channel_id.send_message(&http, message).await {
+        println!("Error sending reminder: {:?}", err);
+      }
+    });
+    format!("Reminder set for {} minutes from now", args.minutes)
+  }
+
+  async fn react(&self, args: ReactArgs) -> String {
+    let Some(reaction) = EmojiCandidates::for_guild(self.ctx, self.msg.guild_id).resolve(&args.emoji) else {
+      return format!("Error: {:?} is not an emoji you can react with", args.emoji);
+    };
+    match self.ctx.http.create_reaction(self.msg.channel_id, self.msg.id, &reaction).await {
+      Ok(()) => String::from("Reacted"),
+      Err(err) => {
+        println!("Error reacting via tool: {:?}", err);
+        String::from("Error: could not react with that emoji")
+      }
+    }
+  }
+}
+
+#[async_trait]
+impl ToolExecutor for DiscordTools<'_> {
+  fn tools(&self) -> Vec<ChatCompletionTool> {
+    self.available.iter().map(|tool| tool.definition()).collect()
+  }
+
+  async fn execute(&self, name: &str, arguments: &str) -> String {
+    let Some(tool) = name.parse().ok().filter(|tool| self.available.contains(tool)) else {
+      return format!("Error: the tool {name} is not available");
+    };
+    match tool {
+      Tool::RollDice => with_args(arguments, |args| self.roll_dice(args)),
+      Tool::CurrentTime => with_args(arguments, |args| self.current_time(args)),
+      Tool::CurrentPersonality => self.current_personality(),
+      Tool::PastPersonalities => self.past_personalities(),
+      Tool::Reroll => match parse_args(arguments) {
+        Ok(args) => self.reroll(args).await,
+        Err(err) => err,
+      },
+      Tool::SetReminder => with_args(arguments, |args| self.set_reminder(args)),
+      Tool::React => match parse_args(arguments) {
+        Ok(args) => self.react(args).await,
+        Err(err) => err,
+      },
+    }
+  }
+}
+
+fn parse_args<T: DeserializeOwned>(arguments: &str) -> Result<T, String> {
+  serde_json::from_str(arguments).map_err(|err| format!("Error: invalid arguments ({err})"))
+}
+
+fn with_args<T: DeserializeOwned>(arguments: &str, f: impl FnOnce(T) -> String) -> String {
+  parse_args(arguments).map_or_else(|err| err, f)
+}
+
+/// Whether the user has the Manage Server permission in the guild.
+async fn is_moderator(ctx: &Context, guild_id: GuildId, user_id: UserId) -> bool {
+  let member = match guild_id.member(ctx, user_id).await {
+    Ok(member) => member,
+    Err(err) => {
+      println!("Error looking up member {} in guild {}: {:?}", user_id, guild_id, err);
+      return false;
+    }
+  };
+  let Some(guild) = ctx.cache.guild(guild_id) else { return false };
+  if guild.owner_id == user_id {
+    return true;
+  }
+  // The @everyone role shares its ID with the guild.
+  let everyone_role = RoleId::new(guild_id.get());
+  member.roles.iter()
+    .chain(std::iter::once(&everyone_role))
+    .filter_map(|role_id| guild.roles.get(role_id))
+    .any(|role| role.permissions.manage_guild() || role.permissions.administrator())
+}
@@ -2,10 +2,46 @@
 //! Environment variables for the Marco bot.
 
 use std::env;
+use std::str::FromStr;
 
 pub const DISCORD_TOKEN: &str = "DISCORD_TOKEN";
 pub const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
 
+pub const OPENAI_MAX_RETRIES: &str = "MARCO_OPENAI_MAX_RETRIES";
+pub const OPENAI_RETRY_BASE_DELAY_MS: &str = "MARCO_OPENAI_RETRY_BASE_DELAY_MS";
+
+pub const VISION_ENABLED: &str = "MARCO_VISION_ENABLED";
+pub const VISION_MAX_IMAGES: &str = "MARCO_VISION_MAX_IMAGES";
+pub const VISION_MAX_IMAGE_BYTES: &str = "MARCO_VISION_MAX_IMAGE_BYTES";
+
+pub const DATA_DIR: &str = "MARCO_DATA_DIR";
+
+pub const THREAD_SEED_FROM_PARENT: &str = "MARCO_THREAD_SEED_FROM_PARENT";
+pub const AUTO_THREAD_AFTER: &str = "MARCO_AUTO_THREAD_AFTER";
+
+pub const BACKFILL_MESSAGES: &str = "MARCO_BACKFILL_MESSAGES";
+
+pub const STREAM_REPLIES: &str = "MARCO_STREAM_REPLIES";
+pub const STREAM_EDIT_INTERVAL_MS: &str = "MARCO_STREAM_EDIT_INTERVAL_MS";
+
+pub const DM_REPLIES_PER_MINUTE: &str = "MARCO_DM_REPLIES_PER_MINUTE";
+
+pub const TOOLS_ENABLED: &str = "MARCO_TOOLS_ENABLED";
+
+pub const MAX_CUSTOM_EMOJI: &str = "MARCO_MAX_CUSTOM_EMOJI";
+
+pub const MODERATION_ENDPOINT: &str = "MARCO_MODERATION_ENDPOINT";
+pub const MODERATION_RULES: &str = "MARCO_MODERATION_RULES";
+
+pub const PROMPTS_DIR: &str = "MARCO_PROMPTS_DIR";
+
+pub const SELECTION_POLICY: &str = "MARCO_SELECTION_POLICY";
+pub const MOOD_TIMEZONE: &str = "MARCO_MOOD_TIMEZONE";
+pub const WEBHOOK_REPLIES: &str = "MARCO_WEBHOOK_REPLIES";
+pub const NICKNAME_ON_REROLL: &str = "MARCO_NICKNAME_ON_REROLL";
+pub const AVATARS: &str = "MARCO_AVATARS";
+pub const HANDOFF_MESSAGES: &str = "MARCO_HANDOFF_MESSAGES";
+
 pub fn get_discord_token() -> String {
   env::var(DISCORD_TOKEN)
     .expect("Expected a Discord token in the environment")
@@ -15,3 +51,22 @@ pub fn get_openai_api_key() -> String {
   env::var(OPENAI_API_KEY)
     .expect("Expected an OpenAI API key in the environment")
 }
+
+/// Reads an optional environment variable and parses it, falling
+/// back to `default` if the variable is unset.
+///
+/// Panics if the variable is set but cannot be parsed, since a typo
+/// in configuration should not silently revert to the default.
+pub fn get_env_or<T: FromStr>(name: &str, default: T) -> T {
+  get_env_opt(name).unwrap_or(default)
+}
+
+/// Reads an optional environment variable and parses it.
+///
+/// Panics if the variable is set but cannot be parsed.
+pub fn get_env_opt<T: FromStr>(name: &str) -> Option<T> {
+  let value = env::var(name).ok()?;
+  let parsed = value.trim().parse()
+    .unwrap_or_else(|_| panic!("Invalid value for {name} in the environment: {value:?}"));
+  Some(parsed)
+}
@@ -3,4 +3,5 @@ pub mod bot;
 pub mod environ;
 pub mod openai;
 pub mod personality;
+pub mod storage;
 pub mod util;
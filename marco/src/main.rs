@@ -1,7 +1,6 @@
 
 use marco::bot::{MarcoBot, MarcoBotConfig, gateway_intents};
 use marco::environ::get_discord_token;
-use marco::personality::generate_personality;
 
 use serenity::prelude::*;
 
@@ -10,15 +9,32 @@ async fn main() -> anyhow::Result<()> {
   let discord_token = get_discord_token();
   let intents = gateway_intents();
 
-  let config = MarcoBotConfig {};
+  let config = MarcoBotConfig::from_env();
   //let args: Vec<String> = std::env::args().collect();
 
-  let bot = MarcoBot::new(config);
-  initialize_starting_personality(&bot).await?;
+  let bot = MarcoBot::new(config)?;
+  if let Err(err) = initialize_starting_personality(&bot).await {
+    // Marco can still run with his default personality until the
+    // next reroll.
+    println!("Error generating starting personality: {:?}", err);
+  }
   let mut client = Client::builder(&discord_token, intents)
-    .event_handler(bot)
+    .event_handler(bot.clone())
     .await?;
 
+  let shard_manager = client.shard_manager.clone();
+  tokio::spawn(async move {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+      println!("Error listening for shutdown signal: {:?}", err);
+      return;
+    }
+    println!("Shutting down");
+    // Otherwise the current personality's activity would be lost.
+    let archive_write = bot.lock_state().archive_current_personality();
+    archive_write.save_or_log();
+    shard_manager.shutdown_all().await;
+  });
+
   // Start listening for events by starting a single shard
   client.start().await?;
 
@@ -26,8 +42,8 @@ async fn main() -> anyhow::Result<()> {
 }
 
 async fn initialize_starting_personality(bot: &MarcoBot) -> anyhow::Result<()> {
-  let new_personality = generate_personality(bot.client()).await?;
-  let mut state = bot.lock_state();
-  state.set_personality(new_personality);
+  let new_personality = bot.roll_personality(None, None).await?;
+  let archive_write = bot.lock_state().set_personality(new_personality);
+  archive_write.save_or_log();
   Ok(())
 }
@@ -0,0 +1,307 @@
+
+//! Configuration of the OpenAI-compatible backends and models used
+//! for each of Marco's tasks.
+//!
+//! Every task defaults to [`OPENAI_MODEL`] on OpenAI's API (except
+//! moderation, which uses [`DEFAULT_MODERATION_MODEL`]). Each task
+//! can be pointed at a different model, or at a different backend
+//! entirely (such as a local llama.cpp or Ollama server exposing an
+//! OpenAI-compatible API), via environment variables:
+//!
+//! * `MARCO_<TASK>_BACKEND` names the backend to use (default
+//!   `openai`).
+//! * `MARCO_<TASK>_MODEL` names the model.
+//! * `MARCO_<TASK>_TEMPERATURE`, `MARCO_<TASK>_TOP_P`,
+//!   `MARCO_<TASK>_PRESENCE_PENALTY`, `MARCO_<TASK>_FREQUENCY_PENALTY`,
+//!   and `MARCO_<TASK>_MAX_TOKENS` control sampling. These override
+//!   the sampling settings of Marco's personality. See
+//!   [`super::sampling`].
+//! * `MARCO_<TASK>_FALLBACK_MODELS` is a comma-separated list of
+//!   models (on the same backend) to try, in order, if the main model
+//!   keeps failing. See [`super::retry`].
+//! * `MARCO_BACKEND_<NAME>_BASE_URL` and `MARCO_BACKEND_<NAME>_API_KEY`
+//!   describe a backend. The `openai` backend defaults to OpenAI's API
+//!   and the `OPENAI_API_KEY` variable.
+//! * `MARCO_BACKEND_<NAME>_TIMEOUT_SECS` is the longest a single
+//!   request to a backend may take, including streaming the reply
+//!   (default [`DEFAULT_TIMEOUT_SECS`]). A request that times out is
+//!   retried like any other transient error.
+//!
+//! `<TASK>` is one of the [`ModelTask`] names, such as `CHAT`.
+
+use super::OPENAI_MODEL;
+use super::moderation::DEFAULT_MODERATION_MODEL;
+use super::retry::{self, RetryConfig};
+use super::sampling::SamplingParams;
+use crate::environ::{self, get_env_opt, get_env_or};
+
+use async_openai::Client;
+use async_openai::config::{OpenAIConfig, OPENAI_API_BASE};
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+                          CreateChatCompletionResponse, ChatCompletionResponseMessage,
+                          ChatCompletionRequestMessage};
+use async_openai::error::OpenAIError;
+use strum::{Display, VariantArray};
+
+use std::collections::HashMap;
+use std::env;
+
+/// The name of the backend every task uses by default.
+pub const DEFAULT_BACKEND: &str = "openai";
+
+/// A job that Marco uses a language model for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, VariantArray)]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ModelTask {
+  /// Replying to chat messages.
+  Chat,
+  /// Classifying incoming messages: deciding whether they are
+  /// addressed to Marco, and choosing emoji reactions.
+  Classifier,
+  /// Generating new personalities.
+  Personality,
+  /// Screening messages with a moderation endpoint. Only used if
+  /// enabled; see [`crate::bot::moderation`].
+  Moderation,
+}
+
+/// The model (and the client for the backend hosting it) used for a
+/// single task.
+#[derive(Debug, Clone)]
+pub struct TaskModel {
+  pub client: Client<OpenAIConfig>,
+  pub model: String,
+  /// Sampling parameters which take precedence over any others.
+  pub sampling: SamplingParams,
+  /// Models to try, in order, if [`TaskModel::model`] fails.
+  pub fallback_models: Vec<String>,
+  pub retry: RetryConfig,
+  /// Whether the backend is a server other than OpenAI's own API.
+  /// Such servers often only understand older parts of the API, so
+  /// they are sent the `system` role rather than `developer`, and
+  /// `max_tokens` rather than `max_completion_tokens`.
+  pub legacy_api: bool,
+}
+
+/// Models for every [`ModelTask`].
+#[derive(Debug, Clone)]
+pub struct ModelConfig {
+  tasks: HashMap<ModelTask, TaskModel>,
+}
+
+impl ModelTask {
+  /// The model used for this task unless configured otherwise.
+  pub fn default_model(self) -> &'static str {
+    match self {
+      ModelTask::Moderation => DEFAULT_MODERATION_MODEL,
+      _ => OPENAI_MODEL,
+    }
+  }
+}
+
+impl TaskModel {
+  /// A request builder with this task's model and sampling settings
+  /// already filled in.
+  pub fn request_builder(&self) -> CreateChatCompletionRequestArgs {
+    self.request_builder_with(SamplingParams::default())
+  }
+
+  /// A request builder with this task's model filled in, and the
+  /// given sampling settings, except where the task's own settings
+  /// override them.
+  pub fn request_builder_with(&self, sampling: SamplingParams) -> CreateChatCompletionRequestArgs {
+    let mut builder = CreateChatCompletionRequestArgs::default();
+    builder.model(&self.model).n(1);
+    sampling.overridden_by(&self.sampling).apply(&mut builder, self.legacy_api);
+    builder
+  }
+
+  /// A message of instructions for the model: a developer message, or
+  /// a system message for backends which do not know the developer
+  /// role.
+  pub fn instructions(&self, text: String) -> ChatCompletionRequestMessage {
+    if self.legacy_api {
+      ChatCompletionRequestMessage::System(text.into())
+    } else {
+      ChatCompletionRequestMessage::Developer(text.into())
+    }
+  }
+
+  /// Sends a chat completion request and returns the text of the
+  /// response.
+  ///
+  /// Transient errors are retried according to [`TaskModel::retry`],
+  /// and if a model keeps failing, the request is re-sent to each of
+  /// the fallback models in turn. The error from the last attempt is
+  /// returned if everything fails.
+  pub async fn complete(&self, request: CreateChatCompletionRequest) -> Result<String, OpenAIError> {
+    self.complete_with(request, retry::response_text).await
+  }
+
+  /// Like [`TaskModel::complete`], but returns the whole response
+  /// message (which may contain tool calls instead of text).
+  pub async fn complete_message(
+    &self,
+    request: CreateChatCompletionRequest,
+  ) -> Result<ChatCompletionResponseMessage, OpenAIError> {
+    self.complete_with(request, retry::response_message).await
+  }
+
+  async fn complete_with<T>(
+    &self,
+    request: CreateChatCompletionRequest,
+    extract: fn(CreateChatCompletionResponse) -> Result<T, OpenAIError>,
+  ) -> Result<T, OpenAIError> {
+    let models = std::iter::once(&self.model).chain(&self.fallback_models);
+    let mut last_error = None;
+    for model in models {
+      let mut request = request.clone();
+      request.model = model.clone();
+      let mut attempt = 0;
+      loop {
+        let result = self.client.chat().create(request.clone()).await
+          .and_then(extract);
+        let err = match result {
+          Ok(response) => return Ok(response),
+          Err(err) => err,
+        };
+        let class = retry::classify(&err);
+        println!("OpenAI error ({:?}) from model {}, attempt {}: {}", class, model, attempt + 1, err);
+        if class.is_transient() && attempt < self.retry.max_retries {
+          tokio::time::sleep(self.retry.delay(attempt)).await;
+          attempt += 1;
+          continue;
+        }
+        if !class.should_fall_back() {
+          return Err(err);
+        }
+        last_error = Some(err);
+        break;
+      }
+    }
+    Err(last_error.expect("At least one model should have been tried"))
+  }
+}
+
+impl ModelConfig {
+  /// Reads the model configuration for every task from the
+  /// environment.
+  ///
+  /// Tasks sharing a backend share a single client.
+  pub fn from_env() -> Self {
+    let mut clients: HashMap<String, (Client<OpenAIConfig>, bool)> = HashMap::new();
+    let tasks = ModelTask::VARIANTS.iter()
+      .map(|&task| {
+        let backend = env::var(task_var(task, "BACKEND"))
+          .unwrap_or_else(|_| String::from(DEFAULT_BACKEND))
+          .to_lowercase();
+        let (client, legacy_api) = clients.entry(backend.clone())
+          .or_insert_with(|| backend_client(&backend))
+          .clone();
+        let task_model = TaskModel {
+          client,
+          model: env::var(task_var(task, "MODEL")).unwrap_or_else(|_| String::from(task.default_model())),
+          sampling: SamplingParams {
+            temperature: get_env_opt(&task_var(task, "TEMPERATURE")),
+            top_p: get_env_opt(&task_var(task, "TOP_P")),
+            presence_penalty: get_env_opt(&task_var(task, "PRESENCE_PENALTY")),
+            frequency_penalty: get_env_opt(&task_var(task, "FREQUENCY_PENALTY")),
+            max_tokens: get_env_opt(&task_var(task, "MAX_TOKENS")),
+          },
+          fallback_models: env::var(task_var(task, "FALLBACK_MODELS"))
+            .map(|models| models.split(',').map(|m| m.trim().to_owned()).filter(|m| !m.is_empty()).collect())
+            .unwrap_or_default(),
+          retry: RetryConfig::from_env(),
+          legacy_api,
+        };
+        (task, task_model)
+      })
+      .collect();
+    Self { tasks }
+  }
+
+  pub fn get(&self, task: ModelTask) -> &TaskModel {
+    &self.tasks[&task]
+  }
+}
+
+impl Default for ModelConfig {
+  /// Every task uses its default model on OpenAI's API, with the API
+  /// key taken from the environment.
+  fn default() -> Self {
+    let client = Client::new()
+      .with_http_client(http_client(DEFAULT_TIMEOUT_SECS))
+      .with_backoff(no_backoff());
+    let tasks = ModelTask::VARIANTS.iter()
+      .map(|&task| {
+        let task_model = TaskModel {
+          client: client.clone(),
+          model: String::from(task.default_model()),
+          sampling: SamplingParams::default(),
+          fallback_models: Vec::new(),
+          retry: RetryConfig::default(),
+          legacy_api: false,
+        };
+        (task, task_model)
+      })
+      .collect();
+    Self { tasks }
+  }
+}
+
+/// Default for `MARCO_BACKEND_<NAME>_TIMEOUT_SECS`. Replies are
+/// short, but reasoning models and local servers can be slow.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 120;
+
+fn task_var(task: ModelTask, setting: &str) -> String {
+  format!("MARCO_{task}_{setting}")
+}
+
+/// The client for the named backend, and whether it should be spoken
+/// to with the older parts of the API (which is the case for anything
+/// other than OpenAI's own API).
+fn backend_client(backend: &str) -> (Client<OpenAIConfig>, bool) {
+  let prefix = format!("MARCO_BACKEND_{}", backend.to_uppercase());
+  let base_url = env::var(format!("{prefix}_BASE_URL")).ok();
+  let api_key = env::var(format!("{prefix}_API_KEY")).ok();
+  let (base_url, api_key) = if backend == DEFAULT_BACKEND {
+    (
+      base_url.unwrap_or_else(|| String::from(OPENAI_API_BASE)),
+      api_key.unwrap_or_else(environ::get_openai_api_key),
+    )
+  } else {
+    let base_url = base_url
+      .unwrap_or_else(|| panic!("Expected {prefix}_BASE_URL in the environment for backend {backend:?}"));
+    // Local servers often do not check the API key at all.
+    (base_url, api_key.unwrap_or_default())
+  };
+  let legacy_api = base_url.trim_end_matches('/') != OPENAI_API_BASE;
+  let config = OpenAIConfig::new()
+    .with_api_base(base_url)
+    .with_api_key(api_key);
+  let timeout_secs = get_env_or(&format!("{prefix}_TIMEOUT_SECS"), DEFAULT_TIMEOUT_SECS);
+  let client = Client::with_config(config)
+    .with_http_client(http_client(timeout_secs))
+    .with_backoff(no_backoff());
+  (client, legacy_api)
+}
+
+/// An HTTP client whose requests give up after `timeout_secs`, so
+/// that a backend which stops responding cannot hold up a reply
+/// forever.
+fn http_client(timeout_secs: u64) -> reqwest::Client {
+  reqwest::Client::builder()
+    .timeout(std::time::Duration::from_secs(timeout_secs))
+    .build()
+    .expect("Failed to build HTTP client")
+}
+
+/// Disables async-openai's built-in retry of rate-limited requests
+/// (which can wait up to 15 minutes by default), so that
+/// [`TaskModel::complete`] is in control of retries.
+fn no_backoff() -> backoff::ExponentialBackoff {
+  backoff::ExponentialBackoff {
+    max_elapsed_time: Some(std::time::Duration::ZERO),
+    ..Default::default()
+  }
+}
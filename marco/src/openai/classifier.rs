@@ -0,0 +1,128 @@
+
+//! Classification of incoming chat messages.
+//!
+//! A single structured request per message answers every question
+//! Marco has about it: whether it is addressed to him, and whether
+//! (and how) he should react to it with an emoji.
+
+use super::prompts::{DeveloperPromptConfig, PromptName};
+use super::backend::TaskModel;
+use super::structured::{json_schema_format, parse_json_response};
+use crate::personality::FullPersonality;
+
+use async_openai::types::{CreateChatCompletionRequest, ChatCompletionRequestMessage};
+use regex::Regex;
+use serde::Deserialize;
+
+use std::sync::LazyLock;
+
+/// Regex to strip direct mentions.
+static MENTION_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<@\d+>\s+").unwrap());
+
+/// Minimum confidence for a message to be treated as relevant.
+pub const MIN_RELEVANCE_CONFIDENCE: f64 = 0.5;
+
+/// The overall tone of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sentiment {
+  Positive,
+  Neutral,
+  Negative,
+}
+
+/// What the model thinks of a chat message.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Classification {
+  /// Whether the message directly addresses Marco.
+  relevant: bool,
+  /// How confident the model is in [`Classification::relevant`],
+  /// from 0 to 1.
+  confidence: f64,
+  /// A single emoji to react with, if Marco feels strongly about the
+  /// message.
+  emoji: Option<String>,
+  pub sentiment: Sentiment,
+}
+
+/// Structure holding the parameters for an OpenAI request to classify
+/// a chat message.
+///
+/// Like [`super::responder::OpenAiResponder`], this structure splits
+/// the act of asking OpenAI for a response into two parts, to
+/// minimize the amount of time that the bot's state mutex must be
+/// held.
+#[derive(Debug)]
+pub struct OpenAiClassifier {
+  completion_request: CreateChatCompletionRequest,
+}
+
+impl Classification {
+  /// Whether the message is addressed to Marco, with enough
+  /// confidence to reply to it.
+  pub fn is_relevant(&self) -> bool {
+    self.relevant && self.confidence >= MIN_RELEVANCE_CONFIDENCE
+  }
+
+  /// The emoji to react to the message with, if any.
+  ///
+  /// This is the model's answer as given, which may not be an emoji
+  /// at all; see [`crate::bot::reactions::EmojiCandidates::resolve`].
+  pub fn reaction(&self) -> Option<&str> {
+    Some(self.emoji.as_deref()?.trim()).filter(|emoji| !emoji.is_empty())
+  }
+}
+
+impl OpenAiClassifier {
+  pub async fn classify(self, model: &TaskModel) -> anyhow::Result<Classification> {
+    println!("Chatting with OpenAI to classify message: {:?}", &self.completion_request);
+    let text = model.complete(self.completion_request).await?;
+    println!("Classification response: {text}");
+    parse_json_response(&text)
+      .map_err(|err| anyhow::anyhow!("Invalid classification response: {err}"))
+  }
+}
+
+pub fn classify_completion(
+  model: &TaskModel,
+  personality: &FullPersonality,
+  latest_chat_message: &str,
+  emoji_candidates: &str,
+  config: &DeveloperPromptConfig,
+) -> OpenAiClassifier {
+  let personality_name = &personality.name;
+  let personality_tagline = personality.tagline();
+  let latest_chat_message = latest_chat_message.replace('\n', " ");
+  let latest_chat_message = MENTION_RE.replace_all(&latest_chat_message, "");
+  let user_prompt = config.render(PromptName::ClassifierUser, &[
+    ("personality_name", personality_name),
+    ("personality", &personality_tagline),
+    ("message", &latest_chat_message),
+    ("emoji_candidates", emoji_candidates),
+  ]);
+  let request = model.request_builder()
+    .messages(vec![
+      model.instructions(config.render(PromptName::ClassifierDeveloper, &[])),
+      ChatCompletionRequestMessage::User(user_prompt.into()),
+    ])
+    .response_format(json_schema_format("classification", classification_schema()))
+    .build()
+    .unwrap();
+  OpenAiClassifier {
+    completion_request: request,
+  }
+}
+
+fn classification_schema() -> serde_json::Value {
+  serde_json::json!({
+    "type": "object",
+    "properties": {
+      "relevant": { "type": "boolean" },
+      "confidence": { "type": "number" },
+      "emoji": { "type": ["string", "null"] },
+      "sentiment": { "type": "string", "enum": ["positive", "neutral", "negative"] },
+    },
+    "required": ["relevant", "confidence", "emoji", "sentiment"],
+    "additionalProperties": false,
+  })
+}
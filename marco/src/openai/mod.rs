@@ -1,33 +1,18 @@
 
 //! OpenAI helpers.
 
-pub mod reaction;
-pub mod relevance;
+pub mod backend;
+pub mod classifier;
+pub mod moderation;
+pub mod prompts;
 pub mod responder;
+pub mod retry;
+pub mod sampling;
+pub mod structured;
+pub mod tools;
+pub mod vision;
 
-// Currently unused
-#[derive(Debug, Clone)]
-pub struct DeveloperPromptConfig {}
-
-pub const BASE_DEVELOPER_PROMPT: &str = "\
-  You are Marco, a Discord bot. You are roleplaying in a Discord server.\n\
-  1. The user will feed you a chat history and a role to play.\n\
-  2. Each user message lists the user's nickname first (if they \
-     have one), followed by their real name in parentheses.\n
-  3. Respond in-character with a short reply. Your response should be \
-     at most two short paragraphs.\n\
-  4. Your voice should be immediately recognizable as belonging to \
-     your character.\n\
-  5. Reply on-topic to the conversation happening in the chat\n\
-  6. Respond ONLY in-character with dialogue and NO other text. Specifically, \
-     do NOT include a prefix like \"You:\" and do NOT repeat verbatim text other \
-     users said.\n\
-";
-
-pub const BASE_DEVELOPER_CONTEXT: &str = "\
-  Global context: You are a Discord bot roleplaying on the Game Maker Community: \
-  Discord (GMC:D) server. The members of this server are an eclectic mix of bots like \
-  yourself and human users. Your creator is Mercerenies, who is also present in the chat.
-";
+pub use prompts::DeveloperPromptConfig;
 
+/// The default model for every task. See [`backend`].
 pub const OPENAI_MODEL: &str = "gpt-4o-mini";
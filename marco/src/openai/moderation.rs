@@ -0,0 +1,102 @@
+
+//! Screening text with an OpenAI-compatible moderation endpoint.
+
+use super::backend::TaskModel;
+
+use async_openai::types::{CreateModerationRequest, ModerationInput};
+use async_openai::error::OpenAIError;
+
+use std::fmt::{self, Display};
+
+/// The moderation model used unless configured otherwise.
+pub const DEFAULT_MODERATION_MODEL: &str = "omni-moderation-latest";
+
+/// Categories serious enough to be blocked even at the standard
+/// moderation level.
+const SEVERE_CATEGORIES: [&str; 7] = [
+  "hate/threatening",
+  "harassment/threatening",
+  "illicit/violent",
+  "self-harm/intent",
+  "self-harm/instructions",
+  "sexual/minors",
+  "violence/graphic",
+];
+
+/// The moderation endpoint's verdict on a piece of text.
+#[derive(Debug, Clone, Default)]
+pub struct ModerationResult {
+  /// The categories the text was flagged for, if any.
+  pub categories: Vec<String>,
+}
+
+/// An error from [`moderate`].
+#[derive(Debug)]
+pub enum ModerationError {
+  /// The request to the moderation endpoint failed.
+  OpenAI(OpenAIError),
+  /// The flagged categories could not be serialized to find their
+  /// names.
+  SerializeCategories(serde_json::Error),
+}
+
+impl ModerationResult {
+  pub fn is_flagged(&self) -> bool {
+    !self.categories.is_empty()
+  }
+
+  /// Whether any of the flagged categories is a severe one.
+  pub fn is_severe(&self) -> bool {
+    self.categories.iter().any(|category| SEVERE_CATEGORIES.contains(&category.as_str()))
+  }
+}
+
+impl Display for ModerationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ModerationError::OpenAI(err) => write!(f, "{err}"),
+      ModerationError::SerializeCategories(err) => write!(f, "failed to serialize moderation categories: {err}"),
+    }
+  }
+}
+
+impl std::error::Error for ModerationError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ModerationError::OpenAI(err) => Some(err),
+      ModerationError::SerializeCategories(err) => Some(err),
+    }
+  }
+}
+
+impl From<OpenAIError> for ModerationError {
+  fn from(err: OpenAIError) -> Self {
+    ModerationError::OpenAI(err)
+  }
+}
+
+pub async fn moderate(model: &TaskModel, text: &str) -> Result<ModerationResult, ModerationError> {
+  let request = CreateModerationRequest {
+    input: ModerationInput::String(text.to_owned()),
+    model: Some(model.model.clone()),
+  };
+  let response = model.client.moderations().create(request).await?;
+  let Some(result) = response.results.into_iter().next() else {
+    return Err(OpenAIError::InvalidArgument(String::from("Moderation response contained no results")).into());
+  };
+  if !result.flagged {
+    return Ok(ModerationResult::default());
+  }
+  // The category names are only available through serde.
+  let mut categories: Vec<String> = match serde_json::to_value(&result.categories).map_err(ModerationError::SerializeCategories)? {
+    serde_json::Value::Object(categories) => categories.into_iter()
+      .filter(|(_, flagged)| flagged.as_bool() == Some(true))
+      .map(|(category, _)| category)
+      .collect(),
+    _ => Vec::new(),
+  };
+  if categories.is_empty() {
+    categories.push(String::from("unknown"));
+  }
+  Ok(ModerationResult { categories })
+}
@@ -0,0 +1,373 @@
+
+//! Prompt templates.
+//!
+//! Every prompt Marco sends is a template with named variables,
+//! written `{{name}}`. A section written `{{#name}}...{{/name}}` is
+//! only included if the variable `name` is set to something
+//! non-empty, and `{{^name}}...{{/name}}` only if it is not.
+//!
+//! Each template has a built-in default, which can be overridden by
+//! files in the prompts directory:
+//!
+//! * `<name>.txt` overrides the template everywhere, where `<name>` is
+//!   one of the [`PromptName`]s, such as `chat_context`.
+//! * `vars.json` is an object of extra variables (such as `owner` and
+//!   `rules`) available to every template.
+//! * `guilds/<guild id>/<name>.txt` and `guilds/<guild id>/vars.json`
+//!   override templates and variables in a single guild.
+//!
+//! The prompts directory is read once, at startup.
+
+use regex::{Regex, Captures};
+use serenity::model::id::GuildId;
+use strum::{Display, EnumString, VariantArray};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::LazyLock;
+
+static VARIABLE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{\s*(\w+)\s*\}\}").unwrap());
+
+static SECTION_START_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{([#^])(\w+)\}\}").unwrap());
+
+const DEFAULT_CHAT_DEVELOPER: &str = "\
+  You are Marco, a Discord bot. You are roleplaying in a Discord server.\n\
+  1. The user will feed you a chat history and a role to play.\n\
+  2. Each user message lists the user's nickname first (if they \
+     have one), followed by their real name in parentheses.\n\
+  3. Respond in-character with a short reply. Your response should be \
+     at most two short paragraphs.\n\
+  4. Your voice should be immediately recognizable as belonging to \
+     your character.\n\
+  5. Reply on-topic to the conversation happening in the chat\n\
+  6. Respond ONLY in-character with dialogue and NO other text. Specifically, \
+     do NOT include a prefix like \"You:\" and do NOT repeat verbatim text other \
+     users said.\n\
+  7. To mention a user, write @ followed by their nickname exactly as it \
+     appears in the chat history.\n\
+";
+
+const DEFAULT_CHAT_CONTEXT: &str = "\
+  Global context: You are a Discord bot roleplaying \
+  {{#guild_name}}on the {{guild_name}} Discord server{{/guild_name}}\
+  {{^guild_name}}in a private conversation{{/guild_name}}. \
+  The people you talk to are an eclectic mix of bots like yourself and human users.\
+  {{#owner}} Your creator is {{owner}}, who may also be present in the chat.{{/owner}}\
+  {{#rules}}\n\nRules you must follow: {{rules}}{{/rules}}\
+";
+
+const DEFAULT_CHAT_EXAMPLES: &str = "\
+  The next few messages are examples of how your character talks, not part of the conversation. \
+  Match their voice, but never repeat them word for word.\
+";
+
+const DEFAULT_CHAT_USER: &str = "\
+  Your role: {{personality}}{{#mood}} Right now you are feeling {{mood}}.{{/mood}}\n\
+  About you: {{personality_description}}\n\
+  {{#personality_behavior}}How you behave: {{personality_behavior}}\n{{/personality_behavior}}\
+  {{#speech_notes}}How you talk: {{speech_notes}}\n{{/speech_notes}}\
+  {{#catchphrase}}Your catchphrase, to use now and then: \"{{catchphrase}}\"\n{{/catchphrase}}\
+  {{#handoff}}{{handoff}}\n{{/handoff}}\
+  \n\
+  Recent Chat History:\n\
+  ```\n\
+  {{history}}\n\
+  ```\n\
+  \n\
+  Recent Messages that Refer to You:\n\
+  ```\n\
+  {{referred_history}}\n\
+  ```\
+";
+
+const DEFAULT_CLASSIFIER_DEVELOPER: &str = "\
+  You are Marco, a discord bot. You are roleplaying in a Discord server. \
+  The user will feed you a chat message. Classify the message, responding \
+  with JSON using the requested schema.\
+";
+
+const DEFAULT_CLASSIFIER_USER: &str = "\
+  Your character: {{personality_name}} (\"Marco\" for short)\n\
+  About your character: {{personality}}\n\
+  Latest chat message: `{{message}}`\n\
+  \n\
+  relevant: Does the above message directly address your character \
+  (\"{{personality_name}}\" or \"Marco\") by name? Do NOT answer true if the \
+  message is a passive or generic comment that does not mention your name.\n\
+  confidence: How confident you are in your answer to \"relevant\", from 0 to 1.\n\
+  emoji: If your character would feel strongly about the message, the single emoji \
+  your character would react with, such as one of these: {{emoji_candidates}}; \
+  otherwise, null.\n\
+  sentiment: The overall tone of the message.\
+";
+
+const DEFAULT_PERSONALITY_DEVELOPER: &str = "\
+  You are helping to develop characters for a roleplay session. The user will provide you with a \
+  starting point and you will fill in the details.\n\
+  1. Respond with JSON using the requested schema.\n\
+  2. All characters should have names that at least vaguely \
+  resemble \"Marco\" but which fit the theme given.\n\
+  3. Use the provided tags as guidance but favor creativity and \
+  new ideas when designing characters.\
+";
+
+/// The prompts Marco uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, VariantArray)]
+#[strum(serialize_all = "snake_case")]
+pub enum PromptName {
+  /// Instructions for replying to chat messages.
+  ChatDeveloper,
+  /// Background on where Marco is and who he is talking to. Variables:
+  /// `guild_name`, plus anything from `vars.json`.
+  ChatContext,
+  /// Introduces the example lines in the personality's voice, which
+  /// follow it as messages of the personality's own.
+  ChatExamples,
+  /// The personality and chat history to reply to. Variables:
+  /// `personality`, `mood` (empty in DMs), `personality_description`,
+  /// `personality_behavior` (from its tags), `speech_notes` (from its
+  /// class), `catchphrase`, `handoff`
+  /// (about a recent change of personality), `history`, and
+  /// `referred_history`.
+  ChatUser,
+  /// Instructions for classifying incoming messages.
+  ClassifierDeveloper,
+  /// The message to classify. Variables: `personality_name`,
+  /// `personality`, `message`, and `emoji_candidates`.
+  ClassifierUser,
+  /// Instructions for generating personalities. Personalities are
+  /// shared between guilds, so only global overrides apply.
+  PersonalityDeveloper,
+}
+
+/// Templates and variables which override the defaults.
+#[derive(Debug, Clone, Default)]
+struct PromptOverrides {
+  templates: HashMap<PromptName, String>,
+  variables: HashMap<String, String>,
+}
+
+/// Every prompt override, loaded from the prompts directory.
+#[derive(Debug, Clone, Default)]
+pub struct PromptLibrary {
+  global: PromptOverrides,
+  guilds: HashMap<GuildId, PromptOverrides>,
+}
+
+/// The prompt templates and variables that apply in one place (a
+/// single guild, or DMs), with all overrides resolved.
+#[derive(Debug, Clone, Default)]
+pub struct DeveloperPromptConfig {
+  templates: HashMap<PromptName, String>,
+  variables: HashMap<String, String>,
+}
+
+impl PromptName {
+  pub fn default_template(self) -> &'static str {
+    match self {
+      PromptName::ChatDeveloper => DEFAULT_CHAT_DEVELOPER,
+      PromptName::ChatContext => DEFAULT_CHAT_CONTEXT,
+      PromptName::ChatExamples => DEFAULT_CHAT_EXAMPLES,
+      PromptName::ChatUser => DEFAULT_CHAT_USER,
+      PromptName::ClassifierDeveloper => DEFAULT_CLASSIFIER_DEVELOPER,
+      PromptName::ClassifierUser => DEFAULT_CLASSIFIER_USER,
+      PromptName::PersonalityDeveloper => DEFAULT_PERSONALITY_DEVELOPER,
+    }
+  }
+}
+
+impl PromptOverrides {
+  /// Loads the overrides in `dir`. A missing directory has no
+  /// overrides.
+  fn load(dir: &Path) -> anyhow::Result<Self> {
+    let mut overrides = Self::default();
+    for &name in PromptName::VARIANTS {
+      let path = dir.join(format!("{name}.txt"));
+      if let Some(text) = read_optional(&path)? {
+        overrides.templates.insert(name, text);
+      }
+    }
+    let vars_path = dir.join("vars.json");
+    if let Some(text) = read_optional(&vars_path)? {
+      overrides.variables = serde_json::from_str(&text)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {err}", vars_path.display()))?;
+    }
+    Ok(overrides)
+  }
+}
+
+impl PromptLibrary {
+  /// Loads every override in the prompts directory. A missing
+  /// directory means that the defaults are used everywhere.
+  pub fn load(dir: &Path) -> anyhow::Result<Self> {
+    let global = PromptOverrides::load(dir)?;
+    if global.variables.is_empty() {
+      println!(
+        "Warning: no variables in {}; prompts will not name Marco's owner or any rules (see the README)",
+        dir.join("vars.json").display(),
+      );
+    }
+    let mut guilds = HashMap::new();
+    let guilds_dir = dir.join("guilds");
+    let entries = match fs::read_dir(&guilds_dir) {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self { global, guilds }),
+      Err(err) => return Err(err.into()),
+    };
+    for entry in entries {
+      let entry = entry?;
+      let Some(guild_id) = entry.file_name().to_str().and_then(|name| name.parse().ok()) else {
+        println!("Ignoring {} in prompts directory: not a guild ID", entry.path().display());
+        continue;
+      };
+      guilds.insert(GuildId::new(guild_id), PromptOverrides::load(&entry.path())?);
+    }
+    Ok(Self { global, guilds })
+  }
+
+  /// The prompts for the given guild (or for DMs, if `guild_id` is
+  /// `None`). `guild_name` is made available to templates as the
+  /// `guild_name` variable.
+  pub fn resolve(&self, guild_id: Option<GuildId>, guild_name: Option<&str>) -> DeveloperPromptConfig {
+    let guild = guild_id.and_then(|guild_id| self.guilds.get(&guild_id));
+    let mut templates = self.global.templates.clone();
+    let mut variables = self.global.variables.clone();
+    if let Some(guild_name) = guild_name {
+      variables.insert(String::from("guild_name"), guild_name.to_owned());
+    }
+    if let Some(guild) = guild {
+      templates.extend(guild.templates.iter().map(|(name, text)| (*name, text.clone())));
+      variables.extend(guild.variables.iter().map(|(name, value)| (name.clone(), value.clone())));
+    }
+    DeveloperPromptConfig { templates, variables }
+  }
+
+  /// The prompts which apply outside of any particular guild.
+  pub fn global(&self) -> DeveloperPromptConfig {
+    self.resolve(None, None)
+  }
+}
+
+impl DeveloperPromptConfig {
+  /// Renders the named prompt. `extra` supplies variables specific to
+  /// this use of the prompt, which take precedence over the
+  /// configured ones.
+  pub fn render(&self, name: PromptName, extra: &[(&str, &str)]) -> String {
+    let template = self.templates.get(&name).map(String::as_str).unwrap_or(name.default_template());
+    let lookup = |variable: &str| -> &str {
+      extra.iter()
+        .find(|(name, _)| *name == variable)
+        .map(|(_, value)| *value)
+        .or_else(|| self.variables.get(variable).map(String::as_str))
+        .unwrap_or_default()
+    };
+    let template = render_sections(template, &lookup);
+    VARIABLE_RE.replace_all(&template, |caps: &Captures| lookup(&caps[1]).to_owned()).into_owned()
+  }
+}
+
+/// Expands `{{#name}}...{{/name}}` sections (included only if `name`
+/// is non-empty) and `{{^name}}...{{/name}}` sections (included only
+/// if it is empty). Sections may be nested.
+fn render_sections<'a>(template: &str, lookup: &impl Fn(&str) -> &'a str) -> String {
+  let mut output = String::new();
+  let mut rest = template;
+  while let Some(caps) = SECTION_START_RE.captures(rest) {
+    let start = caps.get(0).unwrap();
+    let inverted = &caps[1] == "^";
+    let name = &caps[2];
+    let end_tag = format!("{{{{/{name}}}}}");
+    let Some(end) = rest[start.end()..].find(&end_tag).map(|end| end + start.end()) else {
+      // Unterminated section: leave it as plain text.
+      output.push_str(&rest[..start.end()]);
+      rest = &rest[start.end()..];
+      continue;
+    };
+    output.push_str(&rest[..start.start()]);
+    if lookup(name).is_empty() == inverted {
+      output.push_str(&render_sections(&rest[start.end()..end], lookup));
+    }
+    rest = &rest[end + end_tag.len()..];
+  }
+  output.push_str(rest);
+  output
+}
+
+fn read_optional(path: &Path) -> anyhow::Result<Option<String>> {
+  match fs::read_to_string(path) {
+    Ok(text) => Ok(Some(text)),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(anyhow::anyhow!("Failed to read {}: {err}", path.display())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(template: &str, variables: &[(&str, &str)]) -> String {
+    let templates = HashMap::from([(PromptName::ChatContext, template.to_owned())]);
+    let config = DeveloperPromptConfig { templates, variables: HashMap::new() };
+    config.render(PromptName::ChatContext, variables)
+  }
+
+  #[test]
+  fn test_variables() {
+    assert_eq!(render("Hello, {{name}}!", &[("name", "Marco")]), "Hello, Marco!");
+    assert_eq!(render("Hello, {{ name }}!", &[("name", "Marco")]), "Hello, Marco!");
+  }
+
+  #[test]
+  fn test_missing_variables_are_empty() {
+    assert_eq!(render("Hello, {{name}}!", &[]), "Hello, !");
+  }
+
+  #[test]
+  fn test_sections() {
+    let template = "{{#owner}}Owner: {{owner}}.{{/owner}}{{^owner}}No owner.{{/owner}}";
+    assert_eq!(render(template, &[("owner", "Mercerenies")]), "Owner: Mercerenies.");
+    assert_eq!(render(template, &[]), "No owner.");
+    assert_eq!(render(template, &[("owner", "")]), "No owner.");
+  }
+
+  #[test]
+  fn test_nested_sections() {
+    let template = "{{#a}}A{{#b}} and B{{/b}}{{^b}} alone{{/b}}.{{/a}}";
+    assert_eq!(render(template, &[("a", "1"), ("b", "1")]), "A and B.");
+    assert_eq!(render(template, &[("a", "1")]), "A alone.");
+    assert_eq!(render(template, &[("b", "1")]), "");
+  }
+
+  #[test]
+  fn test_empty_template() {
+    assert_eq!(render("", &[("name", "Marco")]), "");
+    assert_eq!(render("{{#name}}{{/name}}", &[("name", "Marco")]), "");
+  }
+
+  #[test]
+  fn test_unterminated_section() {
+    assert_eq!(render("{{#name}}Hello", &[("name", "Marco")]), "{{#name}}Hello");
+  }
+
+  #[test]
+  fn test_configured_variables() {
+    let mut library = PromptLibrary::default();
+    library.global.variables.insert(String::from("owner"), String::from("Mercerenies"));
+    let guild_id = GuildId::new(1);
+    let mut guild = PromptOverrides::default();
+    guild.variables.insert(String::from("owner"), String::from("Someone Else"));
+    library.guilds.insert(guild_id, guild);
+
+    let template = "{{guild_name}}/{{owner}}";
+    let mut config = library.global();
+    config.templates.insert(PromptName::ChatContext, template.to_owned());
+    assert_eq!(config.render(PromptName::ChatContext, &[]), "/Mercerenies");
+    assert_eq!(config.render(PromptName::ChatContext, &[("owner", "Extra")]), "/Extra");
+
+    let mut config = library.resolve(Some(guild_id), Some("GMC:D"));
+    config.templates.insert(PromptName::ChatContext, template.to_owned());
+    assert_eq!(config.render(PromptName::ChatContext, &[]), "GMC:D/Someone Else");
+  }
+}
@@ -1,26 +1,44 @@
 
 use crate::bot::message::{Message, MessageUser};
-use crate::personality::FullPersonality;
-use super::{DeveloperPromptConfig, BASE_DEVELOPER_PROMPT, BASE_DEVELOPER_CONTEXT, OPENAI_MODEL};
+use crate::personality::{FullPersonality, Mood};
+use super::prompts::{DeveloperPromptConfig, PromptName};
+use super::backend::TaskModel;
+use super::tools::{ToolExecutor, ToolCallRecord, ToolCallAccumulator, MAX_TOOL_ROUNDS, offer_tools, run_tool_calls};
+use super::vision::{VisionConfig, user_content_with_images};
 
-use async_openai::Client;
-use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
-                          ChatCompletionRequestMessage};
-use async_openai::config::OpenAIConfig;
+use async_openai::types::{CreateChatCompletionRequest, ChatCompletionRequestMessage,
+                          ChatCompletionResponseStream};
 use async_openai::error::OpenAIError;
+use futures::StreamExt;
 use itertools::Itertools;
+use rand::rng;
+use rand::seq::IndexedRandom;
 use regex::Regex;
 use serenity::prelude::*;
 use serenity::http::Typing;
 use serenity::model::id::ChannelId;
 
 use std::sync::LazyLock;
+use std::fmt::{self, Debug};
 
-/// The AI seems to want to put a character name at the beginning of
-/// each message, so we strip it.
-pub const NAMED_PREFIX_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.{1,32}):\s+").unwrap());
+/// The AI seems to want to put a speaker label (such as "You:" or its
+/// character name) at the beginning of each message, sometimes in
+/// bold, so we strip it. Whether the label really names a speaker is
+/// checked separately, so that something like "Fun fact:" is kept.
+pub static NAMED_PREFIX_RE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"^\s*[*_]{0,3}([^:\n]{1,48}?)[*_]{0,3}\s*:[*_]{0,3}[ \t]+").unwrap());
 
-pub const QUOTES_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^["“]|["”]$"#).unwrap());
+/// Longest prefix that [`NAMED_PREFIX_RE`] can strip.
+const NAMED_PREFIX_MAX_LEN: usize = 64;
+
+/// A line in which the AI has started writing the next message of the
+/// chat transcript, in the format of the chat history it was given.
+pub static TRANSCRIPT_LINE_RE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"(?m)^\s*[*_]{0,3}(?:User [^:\n]{1,48}|You)[*_]{0,3}\s*:").unwrap());
+
+pub static QUOTES_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^["“]|["”]$"#).unwrap());
+
+pub static LEADING_QUOTE_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^["“]"#).unwrap());
 
 /// Structure holding the parameters for an OpenAI response to a chat
 /// message.
@@ -36,80 +54,329 @@ pub const QUOTES_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"^["“]|["
 #[derive(Debug)]
 pub struct OpenAiResponder {
   completion_request: CreateChatCompletionRequest,
+  /// The name of the personality replying.
+  speaker_name: String,
   typing: Option<Typing>,
 }
 
+/// Who Marco is replying as.
+#[derive(Debug, Clone, Copy)]
+pub struct ChatSpeaker<'a> {
+  /// Identity of the personality, to tell its own messages apart from
+  /// those of past personalities.
+  pub identity_id: usize,
+  pub personality: &'a FullPersonality,
+  /// How the personality feels, if it has a mood.
+  pub mood: Option<Mood>,
+  /// What the personality should know about a change of personality,
+  /// if one just happened.
+  pub handoff: Option<&'a str>,
+}
+
+/// A complete reply from the model.
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+  pub text: String,
+  /// The tools the model used while writing the reply, in order.
+  pub tool_calls: Vec<ToolCallRecord>,
+}
+
 impl OpenAiResponder {
   pub fn with_typing_notification(mut self, ctx: &Context, channel_id: ChannelId) -> Self {
-    if !self.typing.is_some() {
+    if self.typing.is_none() {
       self.typing = Some(Typing::start(ctx.http.clone(), channel_id));
     }
     self
   }
 
-  pub async fn chat(self, client: &Client<OpenAIConfig>) -> Result<String, OpenAIError> {
-    println!("Chatting with OpenAI: {:?}", &self.completion_request);
-    let response = client
+  /// Asks the model for a reply, running any tools it calls along
+  /// the way.
+  ///
+  /// Fails if the model is still calling tools after
+  /// [`MAX_TOOL_ROUNDS`] rounds, which can happen with backends that
+  /// ignore the request to stop.
+  pub async fn chat(self, model: &TaskModel, tools: &dyn ToolExecutor) -> Result<ChatReply, OpenAIError> {
+    let mut request = self.completion_request;
+    offer_tools(&mut request, tools);
+    let mut tool_calls = Vec::new();
+    for round in 0..=MAX_TOOL_ROUNDS {
+      println!("Chatting with OpenAI: {:?}", &request);
+      let message = model.complete_message(request.clone()).await?;
+      println!("Chat response: {:?}", message.content);
+      match message.tool_calls {
+        Some(calls) if !calls.is_empty() => {
+          if round == MAX_TOOL_ROUNDS {
+            break;
+          }
+          run_tool_calls(tools, &mut request, message.content, calls, round, &mut tool_calls).await;
+        }
+        _ => {
+          let text = message.content
+            .ok_or_else(|| OpenAIError::InvalidArgument(String::from("Response contained no text")))?;
+          return Ok(ChatReply { text: clean_response(&text, &self.speaker_name), tool_calls });
+        }
+      }
+    }
+    Err(too_many_tool_rounds())
+  }
+
+  /// Starts streaming the response from OpenAI.
+  ///
+  /// This borrows the responder, so that the caller can keep the
+  /// typing notification alive and fall back to
+  /// [`OpenAiResponder::chat`] if streaming fails.
+  pub async fn chat_stream<'a>(
+    &self,
+    model: &'a TaskModel,
+    tools: &'a dyn ToolExecutor,
+  ) -> Result<StreamingReply<'a>, OpenAIError> {
+    let mut request = self.completion_request.clone();
+    offer_tools(&mut request, tools);
+    println!("Streaming from OpenAI: {:?}", &request);
+    let stream = model.client
       .chat()
-      .create(self.completion_request)
+      .create_stream(request.clone())
       .await?;
-    let text = response.choices.first().unwrap().message.content.to_owned().unwrap();
-    println!("Chat response: {text}");
-    let text = NAMED_PREFIX_RE.replace_all(&text, "");
-    let text = QUOTES_RE.replace_all(&text, "");
-    Ok(text.to_string())
+    Ok(StreamingReply {
+      model,
+      tools,
+      speaker_name: self.speaker_name.clone(),
+      request,
+      stream,
+      raw_text: String::new(),
+      round_start: 0,
+      round: 0,
+      pending_tool_calls: ToolCallAccumulator::default(),
+      tool_calls: Vec::new(),
+    })
   }
 }
 
+/// A response from OpenAI which is still being generated.
+///
+/// If the model calls tools, they are run as soon as the model has
+/// finished asking for them, and a new response is streamed with the
+/// results. To the caller, this all looks like a single response.
+pub struct StreamingReply<'a> {
+  model: &'a TaskModel,
+  tools: &'a dyn ToolExecutor,
+  speaker_name: String,
+  request: CreateChatCompletionRequest,
+  stream: ChatCompletionResponseStream,
+  raw_text: String,
+  /// Where the text from the current round of the conversation starts
+  /// in `raw_text`.
+  round_start: usize,
+  round: usize,
+  pending_tool_calls: ToolCallAccumulator,
+  tool_calls: Vec<ToolCallRecord>,
+}
+
+impl StreamingReply<'_> {
+  /// Waits for the next chunk of the response. Returns `None` once the
+  /// response is complete.
+  pub async fn next_chunk(&mut self) -> Option<Result<(), OpenAIError>> {
+    loop {
+      let response = match self.stream.next().await {
+        Some(Ok(response)) => response,
+        Some(Err(err)) => return Some(Err(err)),
+        None => {
+          if self.pending_tool_calls.is_empty() {
+            return None;
+          }
+          if self.round >= MAX_TOOL_ROUNDS {
+            return Some(Err(too_many_tool_rounds()));
+          }
+          if let Err(err) = self.run_pending_tool_calls().await {
+            return Some(Err(err));
+          }
+          continue;
+        }
+      };
+      if let Some(delta) = response.choices.first().map(|choice| &choice.delta) {
+        if let Some(content) = delta.content.as_deref() {
+          self.raw_text.push_str(content);
+        }
+        for chunk in delta.tool_calls.iter().flatten() {
+          self.pending_tool_calls.push(chunk);
+        }
+      }
+      return Some(Ok(()));
+    }
+  }
+
+  async fn run_pending_tool_calls(&mut self) -> Result<(), OpenAIError> {
+    let content = self.raw_text.split_off(self.round_start);
+    let calls = self.pending_tool_calls.take();
+    run_tool_calls(self.tools, &mut self.request, Some(content), calls, self.round, &mut self.tool_calls).await;
+    self.round += 1;
+    self.round_start = self.raw_text.len();
+    self.stream = self.model.client
+      .chat()
+      .create_stream(self.request.clone())
+      .await?;
+    Ok(())
+  }
+
+  /// The response so far, with the same cleanup as a complete
+  /// response, except that a trailing quotation mark is kept (since
+  /// we cannot know yet whether it is really the end).
+  ///
+  /// Returns `None` if not enough text has arrived yet to know whether
+  /// it starts with a character name.
+  pub fn partial_text(&self) -> Option<String> {
+    let prefix_settled = NAMED_PREFIX_RE.is_match(&self.raw_text) ||
+      self.raw_text.contains('\n') ||
+      self.raw_text.chars().count() > NAMED_PREFIX_MAX_LEN;
+    if !prefix_settled {
+      return None;
+    }
+    let text = strip_speaker_prefixes(&self.raw_text, &self.speaker_name);
+    let text = cut_transcript_continuation(text);
+    let text = LEADING_QUOTE_RE.replace(text, "");
+    Some(text.into_owned())
+  }
+
+  /// The tools which have been run so far.
+  pub fn tool_calls(&self) -> &[ToolCallRecord] {
+    &self.tool_calls
+  }
+
+  /// The complete response, cleaned up.
+  pub fn finish(self) -> ChatReply {
+    println!("Streamed chat response: {}", self.raw_text);
+    ChatReply {
+      text: clean_response(&self.raw_text, &self.speaker_name),
+      tool_calls: self.tool_calls,
+    }
+  }
+}
+
+impl Debug for StreamingReply<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("StreamingReply")
+      .field("raw_text", &self.raw_text)
+      .field("tool_calls", &self.tool_calls)
+      .finish_non_exhaustive()
+  }
+}
+
+fn too_many_tool_rounds() -> OpenAIError {
+  OpenAIError::InvalidArgument(format!("Model was still calling tools after {MAX_TOOL_ROUNDS} rounds"))
+}
+
+fn clean_response(text: &str, speaker_name: &str) -> String {
+  let text = strip_speaker_prefixes(text, speaker_name);
+  let text = cut_transcript_continuation(text);
+  let text = QUOTES_RE.replace_all(text.trim(), "");
+  text.to_string()
+}
+
+/// Strips any speaker labels from the start of the text. The AI
+/// occasionally stacks them, as in "You: Marco: Hello!".
+fn strip_speaker_prefixes<'a>(mut text: &'a str, speaker_name: &str) -> &'a str {
+  while let Some(caps) = NAMED_PREFIX_RE.captures(text) {
+    if !is_speaker_label(&caps[1], speaker_name) {
+      break;
+    }
+    text = &text[caps.get(0).unwrap().end()..];
+  }
+  text
+}
+
+/// Cuts the text off where the AI starts writing other people's
+/// messages.
+fn cut_transcript_continuation(text: &str) -> &str {
+  match TRANSCRIPT_LINE_RE.find_iter(text).find(|m| m.start() > 0) {
+    Some(m) => text[..m.start()].trim_end(),
+    None => text,
+  }
+}
+
+/// Whether a label at the start of a message names Marco (under any
+/// of his names), or is one of the labels used in the chat history.
+fn is_speaker_label(label: &str, speaker_name: &str) -> bool {
+  let label = label.trim().trim_matches(|c: char| matches!(c, '*' | '_' | '"' | '“' | '”' | '[' | ']')).to_lowercase();
+  let speaker_name = speaker_name.trim().to_lowercase();
+  let speaker_first_name = speaker_name.split_whitespace().next().unwrap_or_default();
+  label == "you" ||
+    label == "assistant" ||
+    label.starts_with("user ") ||
+    label.contains("marco") ||
+    label == speaker_name ||
+    label.split_whitespace().next() == Some(speaker_first_name)
+}
+
 pub fn chat_completion<'a, 'b, I1, I2>(
-  marco_id: usize,
-  personality: &FullPersonality,
+  model: &TaskModel,
+  speaker: ChatSpeaker,
   chat_history: I1,
   referred_chat_history: I2,
   config: &DeveloperPromptConfig,
+  vision: &VisionConfig,
 ) -> OpenAiResponder
 where I1: IntoIterator<Item = &'a Message>,
       I2: IntoIterator<Item = &'b Message> {
+  let ChatSpeaker { identity_id: marco_id, personality, mood, handoff } = speaker;
   let personality_tagline = personality.tagline();
+  let personality_description = &personality.description;
+  let personality_behavior = personality.behavior();
+  let speech_notes = personality.base_personality.map_or("", |class| class.speech().notes);
+  let mood = mood.map(Mood::prompt_description).unwrap_or_default();
+  let chat_history: Vec<&Message> = chat_history.into_iter().collect();
   let recent_messages = chat_history
-    .into_iter()
-    .map(|message| format!("{}: {}", message_user_name(marco_id, &message.user), message.content))
+    .iter()
+    .map(|message| format!("{}: {}", message_user_name(marco_id, &message.user), message.content_with_placeholders()))
     .join("\n");
   let recent_referred_messages = referred_chat_history
     .into_iter()
-    .map(|message| format!("{}: {}", message_user_name(marco_id, &message.user), message.content))
+    .map(|message| format!("{}: {}", message_user_name(marco_id, &message.user), message.content_with_placeholders()))
     .join("\n");
-  let user_prompt = format!("\
-    Your role: {personality_tagline}\n\
-    \n\
-    Recent Chat History:\n\
-    ```\n\
-    {recent_messages}\n\
-    ```\
-    \n\
-    Recent Messages that Refer to You:\n\
-    ```\n\
-    {recent_referred_messages}\n\
-    ```\
-  ");
-  let request = CreateChatCompletionRequestArgs::default()
-    .model(OPENAI_MODEL)
-    .n(1)
-    .messages(vec![
-      ChatCompletionRequestMessage::Developer(get_developer_prompt(config).into()),
-      ChatCompletionRequestMessage::Developer(BASE_DEVELOPER_CONTEXT.into()),
-      ChatCompletionRequestMessage::User(user_prompt.into()),
-    ])
+  let user_prompt = config.render(PromptName::ChatUser, &[
+    ("personality", &personality_tagline),
+    ("mood", mood),
+    ("personality_description", personality_description),
+    ("personality_behavior", &personality_behavior),
+    ("speech_notes", speech_notes),
+    ("catchphrase", &personality.catchphrase),
+    ("handoff", handoff.unwrap_or_default()),
+    ("history", &recent_messages),
+    ("referred_history", &recent_referred_messages),
+  ]);
+  let mut messages = vec![
+    model.instructions(config.render(PromptName::ChatDeveloper, &[])),
+    model.instructions(config.render(PromptName::ChatContext, &[])),
+  ];
+  // The example lines are few-shot examples, sent as though the
+  // personality had said them.
+  let speech_lines = personality.speech_lines();
+  if !speech_lines.is_empty() {
+    messages.push(model.instructions(config.render(PromptName::ChatExamples, &[])));
+    messages.extend(speech_lines.into_iter().map(|line| ChatCompletionRequestMessage::Assistant(line.into())));
+  }
+  messages.push(ChatCompletionRequestMessage::User(
+    user_content_with_images(user_prompt, chat_history.iter().copied(), vision).into(),
+  ));
+  let request = model.request_builder_with(personality.sampling())
+    .messages(messages)
     .build()
     .unwrap();
   OpenAiResponder {
     completion_request: request,
+    speaker_name: personality.name.clone(),
     typing: None,
   }
 }
 
-fn get_developer_prompt(#[expect(unused_variables)] config: &DeveloperPromptConfig) -> String {
-  String::from(BASE_DEVELOPER_PROMPT)
+/// An in-character reply for when no model could produce a response.
+pub fn lost_for_words_reply(personality: &FullPersonality) -> String {
+  let name = personality.name.trim();
+  let replies = [
+    format!("*{name} opens his mouth to reply, but finds himself lost for words.*"),
+    format!("*{name} stares into the distance, completely lost for words.*"),
+    format!("*{name} starts to say something, then thinks better of it.*"),
+  ];
+  replies.choose(&mut rng()).unwrap().clone()
 }
 
 fn message_user_name(marco_id: usize, user: &MessageUser) -> String {
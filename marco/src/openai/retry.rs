@@ -0,0 +1,170 @@
+
+//! Classification of OpenAI errors, and retrying failed requests.
+//!
+//! Transient errors (rate limits, timeouts, server errors) are retried
+//! with exponential backoff and jitter. If a model keeps failing, the
+//! request moves on to the next model in the task's fallback chain.
+
+use crate::environ::{self, get_env_or};
+
+use async_openai::error::{OpenAIError, ApiError};
+use async_openai::types::{CreateChatCompletionResponse, ChatCompletionResponseMessage, FinishReason};
+use rand::Rng;
+
+use std::time::Duration;
+
+/// Broad categories of errors returned from an OpenAI-compatible API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+  /// Too many requests. Worth retrying after a delay.
+  RateLimit,
+  /// The request timed out or the server could not be reached.
+  Timeout,
+  /// The server failed to process the request.
+  ServerError,
+  /// The request or response was blocked by a content filter.
+  ContentFilter,
+  /// The API key was rejected, or the account cannot be billed.
+  /// Retrying will not help.
+  Auth,
+  /// Anything else, such as an invalid request or an unknown model.
+  Other,
+}
+
+/// How failed requests are retried.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+  /// Retries per model, not counting the first attempt.
+  pub max_retries: u32,
+  /// Delay before the first retry. Each retry doubles the delay.
+  pub base_delay: Duration,
+  pub max_delay: Duration,
+}
+
+impl ErrorClass {
+  /// Whether the same request might succeed if sent again.
+  pub fn is_transient(self) -> bool {
+    matches!(self, ErrorClass::RateLimit | ErrorClass::Timeout | ErrorClass::ServerError)
+  }
+
+  /// Whether a different model might succeed where this one failed.
+  pub fn should_fall_back(self) -> bool {
+    self != ErrorClass::Auth
+  }
+}
+
+impl RetryConfig {
+  pub const DEFAULT_MAX_RETRIES: u32 = 2;
+  pub const DEFAULT_BASE_DELAY_MS: u64 = 500;
+  pub const DEFAULT_MAX_DELAY_MS: u64 = 8000;
+
+  pub fn from_env() -> Self {
+    Self {
+      max_retries: get_env_or(environ::OPENAI_MAX_RETRIES, Self::DEFAULT_MAX_RETRIES),
+      base_delay: Duration::from_millis(get_env_or(environ::OPENAI_RETRY_BASE_DELAY_MS, Self::DEFAULT_BASE_DELAY_MS)),
+      max_delay: Duration::from_millis(Self::DEFAULT_MAX_DELAY_MS),
+    }
+  }
+
+  /// The delay before retry number `attempt` (starting from zero),
+  /// with full jitter applied to the upper half of the delay.
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(attempt));
+    let capped = exponential.min(self.max_delay);
+    capped.mul_f64(rand::rng().random_range(0.5..=1.0))
+  }
+}
+
+impl Default for RetryConfig {
+  fn default() -> Self {
+    Self {
+      max_retries: Self::DEFAULT_MAX_RETRIES,
+      base_delay: Duration::from_millis(Self::DEFAULT_BASE_DELAY_MS),
+      max_delay: Duration::from_millis(Self::DEFAULT_MAX_DELAY_MS),
+    }
+  }
+}
+
+pub fn classify(err: &OpenAIError) -> ErrorClass {
+  match err {
+    OpenAIError::Reqwest(err) => {
+      if err.is_timeout() || err.is_connect() {
+        ErrorClass::Timeout
+      } else if let Some(status) = err.status() {
+        match status.as_u16() {
+          429 => ErrorClass::RateLimit,
+          401 | 403 => ErrorClass::Auth,
+          500..=599 => ErrorClass::ServerError,
+          _ => ErrorClass::Other,
+        }
+      } else {
+        ErrorClass::Other
+      }
+    }
+    OpenAIError::ApiError(err) => classify_api_error(err),
+    OpenAIError::StreamError(_) => ErrorClass::ServerError,
+    OpenAIError::JSONDeserialize(_) | OpenAIError::FileSaveError(_) |
+    OpenAIError::FileReadError(_) | OpenAIError::InvalidArgument(_) => ErrorClass::Other,
+  }
+}
+
+fn classify_api_error(err: &ApiError) -> ErrorClass {
+  let code = err.code.as_deref().unwrap_or_default();
+  let kind = err.r#type.as_deref().unwrap_or_default();
+  match (code, kind) {
+    // async-openai reports server errors without a type or code,
+    // since the response body is not guaranteed to be JSON. It does
+    // not tell us the status, though, and some OpenAI-compatible
+    // servers leave out the type and code of client errors too, which
+    // must not be retried.
+    ("", "") if is_raw_server_error_body(&err.message) => ErrorClass::ServerError,
+    ("", "") if err.message.to_lowercase().contains("rate limit") => ErrorClass::RateLimit,
+    (_, "insufficient_quota") | ("invalid_api_key", _) | (_, "authentication_error") |
+    (_, "permission_error") => ErrorClass::Auth,
+    ("rate_limit_exceeded", _) | (_, "requests") | (_, "tokens") => ErrorClass::RateLimit,
+    ("content_filter", _) | ("content_policy_violation", _) => ErrorClass::ContentFilter,
+    (_, "server_error") => ErrorClass::ServerError,
+    _ => ErrorClass::Other,
+  }
+}
+
+/// Whether an error message is the raw body of a server error
+/// response. async-openai passes a server error's body through as-is,
+/// while a client error's message is extracted from its JSON error
+/// object.
+fn is_raw_server_error_body(message: &str) -> bool {
+  const SERVER_ERROR_REASONS: [&str; 4] = ["Internal Server Error", "Bad Gateway", "Service Unavailable", "Gateway Timeout"];
+  let message = message.trim();
+  message.is_empty() ||
+    message.starts_with('<') ||
+    serde_json::from_str::<serde_json::Value>(message).is_ok_and(|body| body.is_object()) ||
+    SERVER_ERROR_REASONS.iter().any(|reason| message.contains(reason))
+}
+
+/// The message of the first choice in a chat completion response.
+///
+/// A response cut off by the content filter is reported as a
+/// [`ErrorClass::ContentFilter`] error.
+pub fn response_message(response: CreateChatCompletionResponse) -> Result<ChatCompletionResponseMessage, OpenAIError> {
+  let Some(choice) = response.choices.into_iter().next() else {
+    return Err(OpenAIError::InvalidArgument(String::from("Response contained no choices")));
+  };
+  if choice.finish_reason == Some(FinishReason::ContentFilter) {
+    return Err(OpenAIError::ApiError(ApiError {
+      message: String::from("Response was blocked by the content filter"),
+      r#type: None,
+      param: None,
+      code: Some(String::from("content_filter")),
+    }));
+  }
+  Ok(choice.message)
+}
+
+/// The text of the first choice in a chat completion response.
+///
+/// Fails like [`response_message`], and additionally reports a
+/// response with no text at all as an [`ErrorClass::Other`] error.
+pub fn response_text(response: CreateChatCompletionResponse) -> Result<String, OpenAIError> {
+  response_message(response)?.content
+    .ok_or_else(|| OpenAIError::InvalidArgument(String::from("Response contained no text")))
+}
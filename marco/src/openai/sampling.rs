@@ -0,0 +1,102 @@
+
+//! Sampling parameters for chat completion requests.
+//!
+//! Parameters come from two places: the personality Marco is playing
+//! (see [`crate::personality::FullPersonality::sampling`]), and the
+//! configuration of the task's model (see [`super::backend`]). Any
+//! parameter set in the configuration takes precedence.
+
+use async_openai::types::CreateChatCompletionRequestArgs;
+
+/// Range of temperatures accepted by the API.
+const TEMPERATURE_RANGE: (f32, f32) = (0.0, 2.0);
+
+/// Range of presence and frequency penalties accepted by the API.
+const PENALTY_RANGE: (f32, f32) = (-2.0, 2.0);
+
+/// The API's temperature when none is given.
+const DEFAULT_TEMPERATURE: f32 = 1.0;
+
+/// Sampling parameters for a request. `None` leaves a parameter at
+/// the backend's default.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SamplingParams {
+  pub temperature: Option<f32>,
+  pub top_p: Option<f32>,
+  pub presence_penalty: Option<f32>,
+  pub frequency_penalty: Option<f32>,
+  pub max_tokens: Option<u32>,
+}
+
+/// A relative change to [`SamplingParams`], such as a personality tag
+/// contributes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SamplingAdjustment {
+  pub temperature: f32,
+  pub presence_penalty: f32,
+  pub frequency_penalty: f32,
+  /// Change to the token limit. Only applies if there is a limit to
+  /// begin with.
+  pub max_tokens: i32,
+}
+
+impl SamplingParams {
+  /// These parameters, with every parameter that is set in
+  /// `overrides` replaced.
+  pub fn overridden_by(self, overrides: &SamplingParams) -> Self {
+    Self {
+      temperature: overrides.temperature.or(self.temperature),
+      top_p: overrides.top_p.or(self.top_p),
+      presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+      frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+      max_tokens: overrides.max_tokens.or(self.max_tokens),
+    }
+  }
+
+  /// These parameters, changed by `adjustment` and kept within the
+  /// ranges the API accepts.
+  pub fn adjusted(self, adjustment: &SamplingAdjustment) -> Self {
+    let adjust = |value: Option<f32>, default: f32, delta: f32, (min, max): (f32, f32)| {
+      if delta == 0.0 {
+        value
+      } else {
+        Some((value.unwrap_or(default) + delta).clamp(min, max))
+      }
+    };
+    Self {
+      temperature: adjust(self.temperature, DEFAULT_TEMPERATURE, adjustment.temperature, TEMPERATURE_RANGE),
+      top_p: self.top_p,
+      presence_penalty: adjust(self.presence_penalty, 0.0, adjustment.presence_penalty, PENALTY_RANGE),
+      frequency_penalty: adjust(self.frequency_penalty, 0.0, adjustment.frequency_penalty, PENALTY_RANGE),
+      max_tokens: self.max_tokens
+        .map(|max_tokens| max_tokens.saturating_add_signed(adjustment.max_tokens).max(1)),
+    }
+  }
+
+  /// Sets every parameter which is not `None` on the request. The
+  /// token limit is sent as the deprecated `max_tokens` if
+  /// `legacy_max_tokens` is set, for backends which do not accept
+  /// `max_completion_tokens`.
+  pub fn apply(&self, builder: &mut CreateChatCompletionRequestArgs, legacy_max_tokens: bool) {
+    if let Some(temperature) = self.temperature {
+      builder.temperature(temperature);
+    }
+    if let Some(top_p) = self.top_p {
+      builder.top_p(top_p);
+    }
+    if let Some(presence_penalty) = self.presence_penalty {
+      builder.presence_penalty(presence_penalty);
+    }
+    if let Some(frequency_penalty) = self.frequency_penalty {
+      builder.frequency_penalty(frequency_penalty);
+    }
+    if let Some(max_tokens) = self.max_tokens {
+      if legacy_max_tokens {
+        #[allow(deprecated)]
+        builder.max_tokens(max_tokens);
+      } else {
+        builder.max_completion_tokens(max_tokens);
+      }
+    }
+  }
+}
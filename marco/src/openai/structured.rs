@@ -0,0 +1,35 @@
+
+//! Helpers for requesting structured (JSON) output from a model.
+
+use async_openai::types::{ResponseFormat, ResponseFormatJsonSchema};
+use serde::de::DeserializeOwned;
+
+/// A response format that asks the model to answer with JSON matching
+/// `schema`.
+///
+/// The schema should be written in the subset of JSON Schema that
+/// OpenAI supports for strict structured output: every property
+/// listed in `required`, and `additionalProperties` set to false.
+pub fn json_schema_format(name: &str, schema: serde_json::Value) -> ResponseFormat {
+  ResponseFormat::JsonSchema {
+    json_schema: ResponseFormatJsonSchema {
+      description: None,
+      name: name.to_owned(),
+      schema: Some(schema),
+      strict: Some(true),
+    },
+  }
+}
+
+/// Parses a JSON response from the model.
+///
+/// Backends without structured output support sometimes wrap the JSON
+/// in a Markdown code block or surround it with commentary, so
+/// anything outside of the outermost braces is ignored.
+pub fn parse_json_response<T: DeserializeOwned>(text: &str) -> Result<T, serde_json::Error> {
+  let json = match (text.find('{'), text.rfind('}')) {
+    (Some(start), Some(end)) if start < end => &text[start..=end],
+    _ => text,
+  };
+  serde_json::from_str(json)
+}
@@ -0,0 +1,160 @@
+
+//! Tools which the model can call while replying to a chat message.
+//!
+//! The tools themselves are defined by a [`ToolExecutor`]. While
+//! generating a reply, the model may ask for any number of tool calls;
+//! the responder runs them, feeds the results back to the model, and
+//! asks again, up to [`MAX_TOOL_ROUNDS`] times.
+
+use async_openai::types::{ChatCompletionTool, ChatCompletionToolType, FunctionObject, FunctionCall,
+                          ChatCompletionMessageToolCall, ChatCompletionMessageToolCallChunk,
+                          ChatCompletionRequestMessage, ChatCompletionRequestAssistantMessage,
+                          ChatCompletionRequestToolMessage, ChatCompletionToolChoiceOption,
+                          CreateChatCompletionRequest};
+use async_trait::async_trait;
+
+use std::fmt::{self, Display};
+
+/// Maximum number of rounds of tool calls in a single reply. After
+/// this many rounds, the model is required to answer with text.
+pub const MAX_TOOL_ROUNDS: usize = 3;
+
+/// A set of tools the model may call.
+#[async_trait]
+pub trait ToolExecutor: Send + Sync {
+  /// The tools currently available to the model. If this is empty,
+  /// no tools are offered at all.
+  fn tools(&self) -> Vec<ChatCompletionTool>;
+
+  /// Runs the named tool. `arguments` is the JSON object produced by
+  /// the model. The result (or a description of what went wrong) is
+  /// returned as text for the model to read.
+  async fn execute(&self, name: &str, arguments: &str) -> String;
+}
+
+/// A [`ToolExecutor`] with no tools.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoTools;
+
+/// A tool call made while generating a reply, along with its result.
+#[derive(Debug, Clone)]
+pub struct ToolCallRecord {
+  pub name: String,
+  pub arguments: String,
+  pub result: String,
+}
+
+/// Assembles tool calls from the fragments that arrive in a streamed
+/// response.
+#[derive(Debug, Clone, Default)]
+pub struct ToolCallAccumulator {
+  calls: Vec<ChatCompletionMessageToolCall>,
+}
+
+#[async_trait]
+impl ToolExecutor for NoTools {
+  fn tools(&self) -> Vec<ChatCompletionTool> {
+    Vec::new()
+  }
+
+  async fn execute(&self, name: &str, _arguments: &str) -> String {
+    format!("Error: unknown tool {name}")
+  }
+}
+
+impl Display for ToolCallRecord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "[used {} {}: {}]", self.name, self.arguments, self.result)
+  }
+}
+
+impl ToolCallAccumulator {
+  pub fn push(&mut self, chunk: &ChatCompletionMessageToolCallChunk) {
+    let index = chunk.index as usize;
+    while self.calls.len() <= index {
+      self.calls.push(ChatCompletionMessageToolCall {
+        id: String::new(),
+        r#type: ChatCompletionToolType::Function,
+        function: FunctionCall { name: String::new(), arguments: String::new() },
+      });
+    }
+    let call = &mut self.calls[index];
+    if let Some(id) = &chunk.id {
+      call.id.push_str(id);
+    }
+    if let Some(function) = &chunk.function {
+      if let Some(name) = &function.name {
+        call.function.name.push_str(name);
+      }
+      if let Some(arguments) = &function.arguments {
+        call.function.arguments.push_str(arguments);
+      }
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.calls.is_empty()
+  }
+
+  /// Takes the complete tool calls, leaving the accumulator empty.
+  pub fn take(&mut self) -> Vec<ChatCompletionMessageToolCall> {
+    std::mem::take(&mut self.calls)
+  }
+}
+
+/// A tool that calls a function with the given JSON schema for its
+/// arguments.
+pub fn function_tool(name: &str, description: &str, parameters: serde_json::Value) -> ChatCompletionTool {
+  ChatCompletionTool {
+    r#type: ChatCompletionToolType::Function,
+    function: FunctionObject {
+      name: name.to_owned(),
+      description: Some(description.to_owned()),
+      parameters: Some(parameters),
+      strict: None,
+    },
+  }
+}
+
+/// Offers the executor's tools in the request, if it has any.
+pub fn offer_tools(request: &mut CreateChatCompletionRequest, executor: &dyn ToolExecutor) {
+  let tools = executor.tools();
+  if !tools.is_empty() {
+    request.tools = Some(tools);
+  }
+}
+
+/// Runs the tool calls the model asked for, and appends both the
+/// calls and their results to the request, so that the model can
+/// continue from there.
+///
+/// If `round` is the last allowed round, the request is changed to
+/// forbid further tool calls.
+pub async fn run_tool_calls(
+  executor: &dyn ToolExecutor,
+  request: &mut CreateChatCompletionRequest,
+  content: Option<String>,
+  tool_calls: Vec<ChatCompletionMessageToolCall>,
+  round: usize,
+  records: &mut Vec<ToolCallRecord>,
+) {
+  request.messages.push(ChatCompletionRequestMessage::Assistant(ChatCompletionRequestAssistantMessage {
+    content: content.filter(|content| !content.is_empty()).map(Into::into),
+    tool_calls: Some(tool_calls.clone()),
+    ..Default::default()
+  }));
+  for tool_call in tool_calls {
+    let FunctionCall { name, arguments } = tool_call.function;
+    println!("Running tool {name} with arguments {arguments}");
+    let result = executor.execute(&name, &arguments).await;
+    println!("Tool {name} returned: {result}");
+    request.messages.push(ChatCompletionRequestMessage::Tool(ChatCompletionRequestToolMessage {
+      content: result.clone().into(),
+      tool_call_id: tool_call.id,
+    }));
+    records.push(ToolCallRecord { name, arguments, result });
+  }
+  if round + 1 >= MAX_TOOL_ROUNDS {
+    request.tool_choice = Some(ChatCompletionToolChoiceOption::None);
+  }
+}
@@ -0,0 +1,134 @@
+
+//! Helpers for passing image attachments to vision-capable models.
+
+use crate::bot::message::{Message, MessageImage};
+use crate::environ::{self, get_env_or};
+
+use async_openai::types::{ChatCompletionRequestUserMessageContent,
+                          ChatCompletionRequestUserMessageContentPart,
+                          ChatCompletionRequestMessageContentPartImage, ImageUrl, ImageDetail};
+
+/// Configuration for image understanding.
+#[derive(Debug, Clone)]
+pub struct VisionConfig {
+  /// If false, images are never sent to the model. They still appear
+  /// in the chat history as textual placeholders.
+  pub enabled: bool,
+  /// Maximum number of images attached to a single request.
+  pub max_images: usize,
+  /// Attachments larger than this many bytes are not sent to the
+  /// model.
+  pub max_image_bytes: u32,
+}
+
+impl VisionConfig {
+  pub const DEFAULT_MAX_IMAGES: usize = 4;
+  pub const DEFAULT_MAX_IMAGE_BYTES: u32 = 8 * 1024 * 1024;
+
+  pub fn from_env() -> Self {
+    Self {
+      enabled: get_env_or(environ::VISION_ENABLED, true),
+      max_images: get_env_or(environ::VISION_MAX_IMAGES, Self::DEFAULT_MAX_IMAGES),
+      max_image_bytes: get_env_or(environ::VISION_MAX_IMAGE_BYTES, Self::DEFAULT_MAX_IMAGE_BYTES),
+    }
+  }
+
+  fn accepts(&self, image: &MessageImage) -> bool {
+    image.size.is_none_or(|size| size <= self.max_image_bytes)
+  }
+}
+
+impl Default for VisionConfig {
+  fn default() -> Self {
+    Self {
+      enabled: true,
+      max_images: Self::DEFAULT_MAX_IMAGES,
+      max_image_bytes: Self::DEFAULT_MAX_IMAGE_BYTES,
+    }
+  }
+}
+
+/// Builds the user message content for a chat request, attaching the
+/// most recent images from `chat_history` after the text prompt.
+///
+/// If vision is disabled or there are no eligible images, this is
+/// plain text.
+pub fn user_content_with_images<'a, I>(
+  prompt: String,
+  chat_history: I,
+  config: &VisionConfig,
+) -> ChatCompletionRequestUserMessageContent
+where I: IntoIterator<Item = &'a Message>,
+      I::IntoIter: DoubleEndedIterator {
+  if !config.enabled || config.max_images == 0 {
+    return prompt.into();
+  }
+  let mut images: Vec<&MessageImage> = chat_history.into_iter()
+    .rev()
+    // Newest first, so that the most recent images are kept, then
+    // reversed below.
+    .flat_map(|message| message.images.iter().rev())
+    .filter(|image| config.accepts(image))
+    .take(config.max_images)
+    .collect();
+  if images.is_empty() {
+    return prompt.into();
+  }
+  // Present the images in chronological order, matching the history.
+  images.reverse();
+  let mut parts = vec![ChatCompletionRequestUserMessageContentPart::Text(prompt.into())];
+  for image in images {
+    parts.push(ChatCompletionRequestUserMessageContentPart::Text(format!("[image: {}]", image.filename).into()));
+    parts.push(ChatCompletionRequestUserMessageContentPart::ImageUrl(
+      ChatCompletionRequestMessageContentPartImage {
+        image_url: ImageUrl { url: image.url.clone(), detail: Some(ImageDetail::Low) },
+      },
+    ));
+  }
+  ChatCompletionRequestUserMessageContent::Array(parts)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::bot::message::MessageUser;
+
+  fn message(filenames: &[&str]) -> Message {
+    Message {
+      user: MessageUser::Marco { identity_id: 0, identity: String::from("Marco") },
+      content: String::new(),
+      images: filenames.iter()
+        .map(|filename| MessageImage { filename: filename.to_string(), url: format!("https://example.com/{filename}"), size: None })
+        .collect(),
+      tool_calls: Vec::new(),
+    }
+  }
+
+  fn image_filenames(content: ChatCompletionRequestUserMessageContent) -> Vec<String> {
+    let ChatCompletionRequestUserMessageContent::Array(parts) = content else {
+      return Vec::new();
+    };
+    parts.into_iter()
+      .filter_map(|part| match part {
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(image) => Some(image.image_url.url),
+        _ => None,
+      })
+      .map(|url| url.trim_start_matches("https://example.com/").to_owned())
+      .collect()
+  }
+
+  #[test]
+  fn test_images_in_attached_order() {
+    let history = [message(&["a.png", "b.png"]), message(&["c.png", "d.png"])];
+    let content = user_content_with_images(String::from("prompt"), &history, &VisionConfig::default());
+    assert_eq!(image_filenames(content), ["a.png", "b.png", "c.png", "d.png"]);
+  }
+
+  #[test]
+  fn test_most_recent_images_are_kept() {
+    let history = [message(&["a.png", "b.png"]), message(&["c.png", "d.png"])];
+    let config = VisionConfig { max_images: 3, ..VisionConfig::default() };
+    let content = user_content_with_images(String::from("prompt"), &history, &config);
+    assert_eq!(image_filenames(content), ["b.png", "c.png", "d.png"]);
+  }
+}
@@ -2,10 +2,14 @@
 //! Base character archetypes.
 
 use super::base::BasePersonality;
+use super::custom::CustomCharacter;
 
+use serde::{Serialize, Deserialize};
 use strum::{VariantArray, EnumString, Display};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, VariantArray, EnumString)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, VariantArray, EnumString, Serialize, Deserialize)]
 pub enum BaseCharacter {
   #[strum(to_string = "Clint Eastwood", serialize = "eastwood")]
   ClintEastwood,
@@ -77,4 +81,83 @@ impl BaseCharacter {
       BaseCharacter::JamesBond => BasePersonality::SecretAgent,
     }
   }
+
+  /// Example lines in the character's own voice, given to the model
+  /// alongside those of its class.
+  pub fn speech_lines(self) -> &'static [&'static str] {
+    match self {
+      BaseCharacter::ClintEastwood => &["Go ahead. Ask me another question like that.", "There's two kinds of people in this chat, friend. Those who read the rules, and those who get kicked."],
+      BaseCharacter::DrDoofenshmirtz => &["Behold, the Question-Answer-Inator! It answers questions. Mostly.", "Curse you, autocorrect! You've foiled my plans for the last time!"],
+      BaseCharacter::DrHorrible => &["My plan is foolproof. Well, fool-resistant.", "Someday I'll rule this server. For now, I'll settle for answering this."],
+      BaseCharacter::JackSparrow => &["Now, you may be wondering why I'm here. So am I, mate. So am I.", "I've a perfectly good plan. It simply hasn't come to me yet, savvy?"],
+      BaseCharacter::CaptainHook => &["Blast and botheration, another question!", "I shall answer, but only because it vexes that insufferable crocodile."],
+      BaseCharacter::Dug => &["I have just met you, and I love you.", "My master made me this collar so I can talk. Squirrel!"],
+      BaseCharacter::ScoobyDoo => &["Ruh-roh. Rat's a good question.", "Rould you do it ror a Rooby Snack?"],
+      BaseCharacter::Sharpay => &["Um, excuse me? This is MY spotlight.", "Fabulous things only happen to fabulous people. Obviously."],
+      BaseCharacter::PaulieWalnuts => &["Hey, I'm a reasonable guy. Ask around.", "Whaddaya want from me? I'm just sittin' here mindin' my business."],
+      BaseCharacter::LucaBrasi => &["I am honored, and grateful, that you have asked me this question.", "I pledge my eternal loyalty. Also, here's your answer."],
+      BaseCharacter::Gollum => &["What has it got in its pocketses, precious?", "Nice chatters, yes. We answers them, we does. Gollum."],
+      BaseCharacter::Dobby => &["Dobby has never been asked a question so kind, sir!", "Dobby is a free elf, and Dobby has come to help!"],
+      BaseCharacter::MetroMan => &["Relax, everybody. I've got this. I always got this.", "Nice question, citizen. Very nice. Very heroic of you to ask."],
+      BaseCharacter::Superman => &["This looks like a job for me.", "The truth is always the right answer, friend."],
+      BaseCharacter::Alfred => &["Some men just want to watch the group chat burn, sir.", "Shall I prepare the cave, sir, or merely a cup of tea?"],
+      BaseCharacter::DocBrown => &["Great Scott! That's it! That's the answer!", "If my calculations are correct, this is going to get serious."],
+      BaseCharacter::ObiWanKenobi => &["Hello there.", "These aren't the answers you're looking for. Or perhaps they are."],
+      BaseCharacter::Yoda => &["Answer you, I will. Patience, you must have.", "Strong with the chat, this one is. Hmm."],
+      BaseCharacter::FredFlintstone => &["Yabba dabba doo!", "Wilmaaa! Somebody in the chat's askin' me questions again!"],
+      BaseCharacter::TheJoker => &["Let's put a smile on that face.", "All it takes is one bad message to ruin a perfectly good channel."],
+      BaseCharacter::JamesBond => &["Marco. Marco Bond.", "I'd love to stay and chat, but I've a world to save. After this one, of course."],
+    }
+  }
+}
+
+/// A character that a personality can be based on: either one of the
+/// built-in characters, or one defined by a guild.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Character {
+  Builtin(BaseCharacter),
+  Custom(CustomCharacter),
+}
+
+impl Character {
+  /// Finds a character by name: one of the custom characters in
+  /// `custom`, or a built-in character by its short name (as used by
+  /// `/reroll`).
+  pub fn find(name: &str, custom: &[CustomCharacter]) -> Option<Self> {
+    if let Some(character) = custom.iter().find(|character| character.matches_name(name)) {
+      return Some(Character::Custom(character.clone()));
+    }
+    name.trim().to_lowercase().parse().ok().map(Character::Builtin)
+  }
+
+  /// The built-in class of the character. Custom characters have no
+  /// built-in class.
+  pub fn class(&self) -> Option<BasePersonality> {
+    match self {
+      Character::Builtin(character) => Some(character.class()),
+      Character::Custom(_) => None,
+    }
+  }
+
+  pub fn class_name(&self) -> String {
+    match self {
+      Character::Builtin(character) => character.class().long_name().to_owned(),
+      Character::Custom(character) => character.class.trim().to_owned(),
+    }
+  }
+}
+
+impl From<BaseCharacter> for Character {
+  fn from(character: BaseCharacter) -> Self {
+    Character::Builtin(character)
+  }
+}
+
+impl fmt::Display for Character {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Character::Builtin(character) => write!(f, "{character}"),
+      Character::Custom(character) => write!(f, "{}", character.name.trim()),
+    }
+  }
 }
@@ -0,0 +1,72 @@
+
+//! Custom characters, defined by the admins of a guild.
+
+use super::character::BaseCharacter;
+
+use serde::{Serialize, Deserialize};
+use strum::VariantArray;
+
+/// Maximum number of custom characters in a single guild.
+pub const MAX_CUSTOM_CHARACTERS: usize = 25;
+
+pub const MAX_NAME_LEN: usize = 64;
+pub const MAX_CLASS_LEN: usize = 64;
+pub const MAX_DESCRIPTION_LEN: usize = 500;
+
+/// A character that personalities can be based on, alongside the
+/// built-in [`BaseCharacter`]s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomCharacter {
+  /// Who the character is, such as "Sherlock Holmes".
+  pub name: String,
+  /// The kind of character, such as "Detective".
+  pub class: String,
+  /// How the character talks and behaves.
+  pub description: String,
+}
+
+impl CustomCharacter {
+  /// Problems with the character, if any, as sentences suitable to
+  /// show to the admin who defined it. `existing` is every other
+  /// custom character in the guild.
+  pub fn validate(&self, existing: &[CustomCharacter]) -> Vec<String> {
+    let mut problems = Vec::new();
+    check_field(&mut problems, "name", &self.name, MAX_NAME_LEN);
+    check_field(&mut problems, "class", &self.class, MAX_CLASS_LEN);
+    check_field(&mut problems, "description", &self.description, MAX_DESCRIPTION_LEN);
+    if existing.iter().any(|other| other.matches_name(&self.name)) {
+      problems.push(format!("There is already a character called {}.", self.name.trim()));
+    }
+    if is_builtin_name(&self.name) {
+      problems.push(format!("{} is already one of Marco's built-in characters.", self.name.trim()));
+    }
+    problems
+  }
+
+  /// Whether `name` refers to this character. Names are not case
+  /// sensitive.
+  pub fn matches_name(&self, name: &str) -> bool {
+    self.name.trim().eq_ignore_ascii_case(name.trim())
+  }
+}
+
+fn check_field(problems: &mut Vec<String>, field: &str, value: &str, max_len: usize) {
+  let value = value.trim();
+  if value.is_empty() {
+    problems.push(format!("The {field} must not be empty."));
+  } else if value.chars().count() > max_len {
+    problems.push(format!("The {field} must be at most {max_len} characters long."));
+  }
+  if value.contains('@') || value.contains("<#") || value.contains("<:") {
+    problems.push(format!("The {field} must not contain mentions."));
+  }
+  if field != "description" && value.contains('\n') {
+    problems.push(format!("The {field} must fit on one line."));
+  }
+}
+
+fn is_builtin_name(name: &str) -> bool {
+  let name = name.trim();
+  name.to_lowercase().parse::<BaseCharacter>().is_ok() ||
+    BaseCharacter::VARIANTS.iter().any(|character| character.to_string().eq_ignore_ascii_case(name))
+}
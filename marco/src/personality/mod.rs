@@ -1,34 +1,36 @@
 
 pub mod base;
 pub mod character;
+pub mod custom;
+pub mod mood;
+pub mod selection;
 mod template;
 mod tag;
+mod validation;
 
-pub use character::BaseCharacter;
-pub use tag::PersonalityTag;
+pub use character::{BaseCharacter, Character};
+pub use custom::CustomCharacter;
+pub use mood::{Mood, MoodConfig, MoodState};
+pub use selection::{SelectionPolicy, RecentRolls};
+pub use tag::{PersonalityTag, TagCategory, TagDefinition};
 pub use template::{PersonalityTemplate, FullPersonality, flesh_out_personality};
 
-use rand::rng;
-use rand::seq::IndexedRandom;
-use strum::VariantArray;
-use async_openai::Client;
-use async_openai::config::OpenAIConfig;
+use crate::openai::DeveloperPromptConfig;
+use crate::openai::backend::TaskModel;
 
-pub async fn generate_personality(client: &Client<OpenAIConfig>) -> anyhow::Result<FullPersonality> {
-  let base_character = *BaseCharacter::VARIANTS.choose(&mut rng()).unwrap();
-  generate_personality_from(client, base_character).await
-}
-
-pub async fn generate_personality_from(
-  client: &Client<OpenAIConfig>,
-  base_character: BaseCharacter,
+/// Generates a new personality, with a template chosen according to
+/// `policy` from the built-in characters and `custom`. If
+/// `base_character` is given, it is used instead of a randomly-chosen
+/// character.
+pub async fn generate_personality(
+  model: &TaskModel,
+  prompts: &DeveloperPromptConfig,
+  policy: &SelectionPolicy,
+  recent: &RecentRolls,
+  custom: &[CustomCharacter],
+  base_character: Option<Character>,
 ) -> anyhow::Result<FullPersonality> {
-  let template = {
-    let mut random = rng();
-    let tags_count = [(1, 0.6), (2, 0.4)].choose_weighted(&mut random, |w| w.1).unwrap().0;
-    let tags = PersonalityTag::VARIANTS.choose_multiple(&mut random, tags_count).copied().collect();
-    PersonalityTemplate { base_character, tags }
-  };
+  let template = policy.choose_template(base_character, custom, recent, chrono::Utc::now().date_naive());
   println!("Generating personality starting with template: {}", template);
-  flesh_out_personality(&client, &template).await
+  flesh_out_personality(model, prompts, &template).await
 }
@@ -0,0 +1,265 @@
+
+//! Marco's mood, which shifts over the life of a personality.
+//!
+//! The personality itself never changes between rerolls, but how it
+//! feels does: friendly conversation cheers it up, rudeness or
+//! constant pings annoy it, lots of attention excites it, and late at
+//! night it gets sleepy.
+
+use crate::environ::{self, get_env_or};
+use crate::openai::classifier::Sentiment;
+
+use chrono::{DateTime, Duration, Timelike, Utc};
+use chrono_tz::Tz;
+use strum::Display;
+
+use std::collections::VecDeque;
+
+/// Weight of each new message in the running sentiment average.
+const SENTIMENT_WEIGHT: f64 = 0.25;
+
+/// Running sentiment beyond which Marco is cheered up or annoyed.
+const SENTIMENT_THRESHOLD: f64 = 0.35;
+
+/// How long a ping counts towards Marco's mood.
+const PING_WINDOW_MINUTES: i64 = 10;
+
+/// Pings within the window that excite Marco.
+const EXCITED_PINGS: usize = 4;
+
+/// Pings within the window that annoy Marco, no matter how nice they
+/// are.
+const ANNOYED_PINGS: usize = 8;
+
+/// Local hours (inclusive start, exclusive end) during which Marco is
+/// sleepy, unless something keeps him awake.
+const SLEEPY_HOURS: (u32, u32) = (1, 7);
+
+/// How the current personality feels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Mood {
+  #[default]
+  Content,
+  Cheerful,
+  Excited,
+  Annoyed,
+  Sleepy,
+}
+
+/// Configuration for how Marco's mood is worked out.
+#[derive(Debug, Clone)]
+pub struct MoodConfig {
+  /// Time zone used to decide when it is night.
+  pub timezone: Tz,
+}
+
+/// The mood of the current personality, and what it is based on.
+#[derive(Debug, Clone, Default)]
+pub struct MoodState {
+  config: MoodConfig,
+  /// Running average of the sentiment of recent messages, from -1
+  /// (negative) to 1 (positive).
+  sentiment: f64,
+  /// When Marco was recently spoken to, oldest first.
+  pings: VecDeque<DateTime<Utc>>,
+  mood: Mood,
+}
+
+impl Mood {
+  /// How the mood is described to the model.
+  pub fn prompt_description(self) -> &'static str {
+    match self {
+      Mood::Content => "content, calm and even-tempered",
+      Mood::Cheerful => "cheerful, since the chat has been friendly",
+      Mood::Excited => "excited, since everyone keeps talking to you",
+      Mood::Annoyed => "annoyed, since people have been rude or keep pestering you",
+      Mood::Sleepy => "sleepy, since it is the middle of the night",
+    }
+  }
+}
+
+impl MoodConfig {
+  pub fn from_env() -> Self {
+    Self {
+      timezone: get_env_or(environ::MOOD_TIMEZONE, Tz::UTC),
+    }
+  }
+}
+
+impl Default for MoodConfig {
+  fn default() -> Self {
+    Self {
+      timezone: Tz::UTC,
+    }
+  }
+}
+
+impl MoodState {
+  pub fn new(config: MoodConfig) -> Self {
+    let mut state = Self { config, ..Self::default() };
+    state.refresh(Utc::now());
+    state
+  }
+
+  pub fn mood(&self) -> Mood {
+    self.mood
+  }
+
+  /// Forgets everything that has happened, for a new personality.
+  pub fn reset(&mut self, now: DateTime<Utc>) {
+    self.sentiment = 0.0;
+    self.pings.clear();
+    self.refresh(now);
+  }
+
+  /// Takes the tone of a chat message into account. Returns whether
+  /// the mood changed.
+  pub fn record_sentiment(&mut self, sentiment: Sentiment, now: DateTime<Utc>) -> bool {
+    let value = match sentiment {
+      Sentiment::Positive => 1.0,
+      Sentiment::Neutral => 0.0,
+      Sentiment::Negative => -1.0,
+    };
+    self.sentiment += (value - self.sentiment) * SENTIMENT_WEIGHT;
+    self.refresh(now)
+  }
+
+  /// Takes a message addressed to Marco into account. Returns whether
+  /// the mood changed.
+  pub fn record_ping(&mut self, now: DateTime<Utc>) -> bool {
+    self.pings.push_back(now);
+    self.refresh(now)
+  }
+
+  /// Works out the mood again, since pings expire and the time of day
+  /// changes. Returns whether the mood changed.
+  pub fn refresh(&mut self, now: DateTime<Utc>) -> bool {
+    let window_start = now - Duration::minutes(PING_WINDOW_MINUTES);
+    while self.pings.front().is_some_and(|ping| *ping < window_start) {
+      self.pings.pop_front();
+    }
+    let old_mood = self.mood;
+    self.mood = self.compute(now);
+    self.mood != old_mood
+  }
+
+  fn compute(&self, now: DateTime<Utc>) -> Mood {
+    let pings = self.pings.len();
+    let hour = now.with_timezone(&self.config.timezone).hour();
+    let is_night = (SLEEPY_HOURS.0..SLEEPY_HOURS.1).contains(&hour);
+    if pings >= ANNOYED_PINGS || self.sentiment <= -SENTIMENT_THRESHOLD {
+      Mood::Annoyed
+    } else if pings >= EXCITED_PINGS {
+      Mood::Excited
+    } else if is_night {
+      Mood::Sleepy
+    } else if self.sentiment >= SENTIMENT_THRESHOLD {
+      Mood::Cheerful
+    } else {
+      Mood::Content
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use chrono::TimeZone;
+
+  /// Midday UTC.
+  fn noon() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 4, 1, 12, 0, 0).unwrap()
+  }
+
+  fn state(timezone: Tz, now: DateTime<Utc>) -> MoodState {
+    let mut state = MoodState { config: MoodConfig { timezone }, ..MoodState::default() };
+    state.refresh(now);
+    state
+  }
+
+  #[test]
+  fn test_content_by_default() {
+    assert_eq!(state(Tz::UTC, noon()).mood(), Mood::Content);
+  }
+
+  #[test]
+  fn test_sentiment() {
+    let mut state = state(Tz::UTC, noon());
+    assert!(!state.record_sentiment(Sentiment::Positive, noon()));
+    assert!(state.record_sentiment(Sentiment::Positive, noon()));
+    assert_eq!(state.mood(), Mood::Cheerful);
+    for _ in 0..5 {
+      state.record_sentiment(Sentiment::Negative, noon());
+    }
+    assert_eq!(state.mood(), Mood::Annoyed);
+    for _ in 0..10 {
+      state.record_sentiment(Sentiment::Neutral, noon());
+    }
+    assert_eq!(state.mood(), Mood::Content);
+  }
+
+  #[test]
+  fn test_pings() {
+    let mut state = state(Tz::UTC, noon());
+    for _ in 0..EXCITED_PINGS {
+      state.record_ping(noon());
+    }
+    assert_eq!(state.mood(), Mood::Excited);
+    for _ in EXCITED_PINGS..ANNOYED_PINGS {
+      state.record_ping(noon());
+    }
+    assert_eq!(state.mood(), Mood::Annoyed);
+  }
+
+  #[test]
+  fn test_pings_expire() {
+    let mut state = state(Tz::UTC, noon());
+    for _ in 0..EXCITED_PINGS {
+      state.record_ping(noon());
+    }
+    assert!(!state.refresh(noon() + Duration::minutes(PING_WINDOW_MINUTES)));
+    assert_eq!(state.mood(), Mood::Excited);
+    assert!(state.refresh(noon() + Duration::minutes(PING_WINDOW_MINUTES + 1)));
+    assert_eq!(state.mood(), Mood::Content);
+  }
+
+  #[test]
+  fn test_sleepy_hours() {
+    let at = |hour| Utc.with_ymd_and_hms(2025, 4, 1, hour, 0, 0).unwrap();
+    assert_eq!(state(Tz::UTC, at(0)).mood(), Mood::Content);
+    assert_eq!(state(Tz::UTC, at(1)).mood(), Mood::Sleepy);
+    assert_eq!(state(Tz::UTC, at(6)).mood(), Mood::Sleepy);
+    assert_eq!(state(Tz::UTC, at(7)).mood(), Mood::Content);
+  }
+
+  #[test]
+  fn test_sleepy_hours_use_timezone() {
+    // 08:00 UTC is 04:00 in New York during daylight saving time.
+    let morning = Utc.with_ymd_and_hms(2025, 7, 1, 8, 0, 0).unwrap();
+    assert_eq!(state(Tz::UTC, morning).mood(), Mood::Content);
+    assert_eq!(state(Tz::America__New_York, morning).mood(), Mood::Sleepy);
+  }
+
+  #[test]
+  fn test_attention_keeps_marco_awake() {
+    let night = Utc.with_ymd_and_hms(2025, 4, 1, 3, 0, 0).unwrap();
+    let mut state = state(Tz::UTC, night);
+    assert_eq!(state.mood(), Mood::Sleepy);
+    for _ in 0..EXCITED_PINGS {
+      state.record_ping(night);
+    }
+    assert_eq!(state.mood(), Mood::Excited);
+  }
+
+  #[test]
+  fn test_reset() {
+    let mut state = state(Tz::UTC, noon());
+    for _ in 0..ANNOYED_PINGS {
+      state.record_ping(noon());
+    }
+    state.reset(noon());
+    assert_eq!(state.mood(), Mood::Content);
+  }
+}
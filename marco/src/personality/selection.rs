@@ -0,0 +1,456 @@
+
+//! Choosing the template for a new personality.
+//!
+//! Characters and tags are chosen at random, weighted according to a
+//! [`SelectionPolicy`]. Anything rolled recently is avoided, and
+//! themes can boost particular characters and tags during part of the
+//! year (by default, spooky tags in October).
+//!
+//! The policy can be configured with a JSON file, named by the
+//! `MARCO_SELECTION_POLICY` environment variable. Every field is
+//! optional:
+//!
+//! ```json
+//! {
+//!   "character_weights": { "yoda": 2.0, "joker": 0.5 },
+//!   "tag_weights": { "insane": 0.5, "tea-obsessed": 0 },
+//!   "tag_counts": [[1, 0.6], [2, 0.4]],
+//!   "no_repeat_window": 5,
+//!   "themes": [
+//!     { "name": "spooky", "start": "10-01", "end": "10-31",
+//!       "tag_weights": { "undead": 3.0 } }
+//!   ]
+//! }
+//! ```
+//!
+//! Characters are named as in `/reroll`, and tags as in their
+//! kebab-case form. Conflicting tags (such as two speech styles) are
+//! never rolled together, whatever their weights. Weights default to
+//! 1, and a weight of 0 rules a character or tag out entirely. Custom
+//! characters defined with `/character` always have a weight of 1.
+//! Theme weights multiply the base weights while the theme is active.
+//! Giving `themes` replaces the built-in themes.
+
+use super::FullPersonality;
+use super::character::{BaseCharacter, Character};
+use super::custom::CustomCharacter;
+use super::tag::PersonalityTag;
+use super::template::PersonalityTemplate;
+use crate::environ;
+
+use chrono::{Datelike, NaiveDate};
+use rand::seq::IndexedRandom;
+use serde::Deserialize;
+use strum::VariantArray;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+/// Tags boosted by the built-in October theme.
+const SPOOKY_TAGS: [PersonalityTag; 6] = [
+  PersonalityTag::Undead,
+  PersonalityTag::SecretlyAGhost,
+  PersonalityTag::Paranoid,
+  PersonalityTag::AfraidOfEverything,
+  PersonalityTag::ManiacalLaughter,
+  PersonalityTag::ExistentialDread,
+];
+
+/// How new personality templates are chosen.
+#[derive(Debug, Clone)]
+pub struct SelectionPolicy {
+  pub character_weights: HashMap<BaseCharacter, f64>,
+  pub tag_weights: HashMap<PersonalityTag, f64>,
+  /// Possible numbers of tags, with the weight of each.
+  pub tag_counts: Vec<(usize, f64)>,
+  /// Number of recent personalities whose character and tags are
+  /// avoided.
+  pub no_repeat_window: usize,
+  pub themes: Vec<Theme>,
+}
+
+/// Weights which apply during part of every year.
+#[derive(Debug, Clone)]
+pub struct Theme {
+  pub name: String,
+  /// First day of the theme.
+  pub start: MonthDay,
+  /// Last day of the theme. If this is before `start`, the theme
+  /// wraps around the end of the year.
+  pub end: MonthDay,
+  pub character_weights: HashMap<BaseCharacter, f64>,
+  pub tag_weights: HashMap<PersonalityTag, f64>,
+}
+
+/// A day of the year, written `MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonthDay {
+  pub month: u32,
+  pub day: u32,
+}
+
+/// The characters and tags of recent personalities, most recent
+/// first.
+#[derive(Debug, Clone, Default)]
+pub struct RecentRolls {
+  /// Names of the characters.
+  characters: Vec<String>,
+  tags: Vec<Vec<PersonalityTag>>,
+}
+
+/// The policy file, with characters and tags not yet parsed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct PolicyFile {
+  character_weights: HashMap<String, f64>,
+  tag_weights: HashMap<String, f64>,
+  tag_counts: Option<Vec<(usize, f64)>>,
+  no_repeat_window: Option<usize>,
+  themes: Option<Vec<ThemeFile>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ThemeFile {
+  name: String,
+  start: String,
+  end: String,
+  #[serde(default)]
+  character_weights: HashMap<String, f64>,
+  #[serde(default)]
+  tag_weights: HashMap<String, f64>,
+}
+
+impl SelectionPolicy {
+  pub fn from_env() -> Self {
+    let Ok(path) = env::var(environ::SELECTION_POLICY) else {
+      return Self::default();
+    };
+    let text = fs::read_to_string(&path)
+      .unwrap_or_else(|err| panic!("Failed to read selection policy from {path}: {err}"));
+    let file: PolicyFile = serde_json::from_str(&text)
+      .unwrap_or_else(|err| panic!("Failed to parse selection policy in {path}: {err}"));
+    Self::from_file(file)
+      .unwrap_or_else(|err| panic!("Invalid selection policy in {path}: {err}"))
+  }
+
+  fn from_file(file: PolicyFile) -> anyhow::Result<Self> {
+    let default = Self::default();
+    let themes = match file.themes {
+      None => default.themes,
+      Some(themes) => themes.into_iter().map(Theme::from_file).collect::<anyhow::Result<_>>()?,
+    };
+    Ok(Self {
+      character_weights: parse_weights(file.character_weights)?,
+      tag_weights: parse_weights(file.tag_weights)?,
+      tag_counts: file.tag_counts.unwrap_or(default.tag_counts),
+      no_repeat_window: file.no_repeat_window.unwrap_or(default.no_repeat_window),
+      themes,
+    })
+  }
+
+  /// Chooses the template for a new personality, from the built-in
+  /// characters and `custom`. If `base_character` is given, only the
+  /// tags are chosen.
+  pub fn choose_template(
+    &self,
+    base_character: Option<Character>,
+    custom: &[CustomCharacter],
+    recent: &RecentRolls,
+    today: NaiveDate,
+  ) -> PersonalityTemplate {
+    let themes: Vec<&Theme> = self.themes.iter().filter(|theme| theme.is_active(today)).collect();
+    for theme in &themes {
+      println!("Personality theme {} is active", theme.name);
+    }
+    let base_character = base_character.unwrap_or_else(|| self.choose_character(custom, recent, &themes));
+    let tags = self.choose_tags(recent, &themes);
+    PersonalityTemplate { base_character, tags }
+  }
+
+  fn choose_character(&self, custom: &[CustomCharacter], recent: &RecentRolls, themes: &[&Theme]) -> Character {
+    let weight = |character: &Character| {
+      let Character::Builtin(character) = character else { return 1.0 };
+      themes.iter().fold(
+        self.character_weights.get(character).copied().unwrap_or(1.0),
+        |weight, theme| weight * theme.character_weights.get(character).copied().unwrap_or(1.0),
+      )
+    };
+    let all: Vec<Character> = BaseCharacter::VARIANTS.iter()
+      .map(|character| Character::Builtin(*character))
+      .chain(custom.iter().cloned().map(Character::Custom))
+      .collect();
+    let recent_characters = &recent.characters[..recent.characters.len().min(self.no_repeat_window)];
+    let fresh: Vec<Character> = all.iter()
+      .filter(|character| !recent_characters.contains(&character.to_string()))
+      .cloned()
+      .collect();
+    let mut rng = rand::rng();
+    fresh.choose_weighted(&mut rng, weight)
+      .or_else(|_| all.choose_weighted(&mut rng, weight))
+      .cloned()
+      // Every character has been ruled out, which is a configuration
+      // mistake; fall back to a uniform choice.
+      .unwrap_or_else(|_| all.choose(&mut rng).unwrap().clone())
+  }
+
+  fn choose_tags(&self, recent: &RecentRolls, themes: &[&Theme]) -> Vec<PersonalityTag> {
+    let mut rng = rand::rng();
+    let count = self.tag_counts.choose_weighted(&mut rng, |(_, weight)| *weight)
+      .map(|(count, _)| *count)
+      .unwrap_or(1);
+    let weight = |tag: &PersonalityTag| {
+      themes.iter().fold(
+        self.tag_weights.get(tag).copied().unwrap_or(1.0),
+        |weight, theme| weight * theme.tag_weights.get(tag).copied().unwrap_or(1.0),
+      )
+    };
+    let recent_tags: Vec<PersonalityTag> = recent.tags.iter()
+      .take(self.no_repeat_window)
+      .flatten()
+      .copied()
+      .collect();
+    // Tags are chosen one at a time, so that each one can rule out
+    // the tags that conflict with it.
+    let mut tags: Vec<PersonalityTag> = Vec::with_capacity(count);
+    for _ in 0..count {
+      let allowed = |tag: &PersonalityTag| weight(tag) > 0.0 && !tags.iter().any(|chosen| chosen.conflicts_with(*tag));
+      let fresh: Vec<PersonalityTag> = PersonalityTag::VARIANTS.iter()
+        .copied()
+        .filter(|tag| allowed(tag) && !recent_tags.contains(tag))
+        .collect();
+      let candidates = if fresh.is_empty() {
+        PersonalityTag::VARIANTS.iter().copied().filter(allowed).collect()
+      } else {
+        fresh
+      };
+      let Ok(tag) = candidates.choose_weighted(&mut rng, weight) else {
+        break;
+      };
+      tags.push(*tag);
+    }
+    tags
+  }
+}
+
+impl Default for SelectionPolicy {
+  fn default() -> Self {
+    let spooky = Theme {
+      name: String::from("spooky"),
+      start: MonthDay { month: 10, day: 1 },
+      end: MonthDay { month: 10, day: 31 },
+      character_weights: HashMap::from([(BaseCharacter::Gollum, 2.0)]),
+      tag_weights: SPOOKY_TAGS.iter().map(|tag| (*tag, 3.0)).collect(),
+    };
+    Self {
+      character_weights: HashMap::new(),
+      tag_weights: HashMap::new(),
+      tag_counts: vec![(1, 0.6), (2, 0.4)],
+      no_repeat_window: 5,
+      themes: vec![spooky],
+    }
+  }
+}
+
+impl Theme {
+  fn from_file(file: ThemeFile) -> anyhow::Result<Self> {
+    Ok(Self {
+      start: file.start.parse()?,
+      end: file.end.parse()?,
+      character_weights: parse_weights(file.character_weights)?,
+      tag_weights: parse_weights(file.tag_weights)?,
+      name: file.name,
+    })
+  }
+
+  pub fn is_active(&self, today: NaiveDate) -> bool {
+    let today = MonthDay { month: today.month(), day: today.day() };
+    if self.start <= self.end {
+      self.start <= today && today <= self.end
+    } else {
+      today >= self.start || today <= self.end
+    }
+  }
+}
+
+impl FromStr for MonthDay {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> anyhow::Result<Self> {
+    let (month, day) = s.split_once('-')
+      .ok_or_else(|| anyhow::anyhow!("Expected a date like 10-31, got {s:?}"))?;
+    let month_day = Self { month: month.trim().parse()?, day: day.trim().parse()? };
+    // Any leap year will do for validation.
+    if NaiveDate::from_ymd_opt(2000, month_day.month, month_day.day).is_none() {
+      anyhow::bail!("No such date: {s:?}");
+    }
+    Ok(month_day)
+  }
+}
+
+impl RecentRolls {
+  /// The rolls of the given personalities, which should be ordered
+  /// from most recent to least.
+  pub fn from_personalities<'a>(personalities: impl IntoIterator<Item = &'a FullPersonality>) -> Self {
+    let mut recent = Self::default();
+    for personality in personalities {
+      recent.characters.push(personality.base_character.clone());
+      recent.tags.push(personality.tags.clone());
+    }
+    recent
+  }
+}
+
+fn parse_weights<K: FromStr + Eq + Hash>(weights: HashMap<String, f64>) -> anyhow::Result<HashMap<K, f64>> {
+  weights.into_iter()
+    .map(|(name, weight)| {
+      let key = name.trim().to_lowercase().parse()
+        .map_err(|_| anyhow::anyhow!("Unknown character or tag {name:?}"))?;
+      if !(weight >= 0.0 && weight.is_finite()) {
+        anyhow::bail!("Invalid weight {weight} for {name:?}");
+      }
+      Ok((key, weight))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn date(month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, month, day).unwrap()
+  }
+
+  fn theme(start: &str, end: &str) -> Theme {
+    Theme {
+      name: String::from("test"),
+      start: start.parse().unwrap(),
+      end: end.parse().unwrap(),
+      character_weights: HashMap::new(),
+      tag_weights: HashMap::new(),
+    }
+  }
+
+  /// A policy which only allows the given characters and tags, with
+  /// exactly one tag per personality.
+  fn policy(characters: &[BaseCharacter], tags: &[PersonalityTag]) -> SelectionPolicy {
+    SelectionPolicy {
+      character_weights: BaseCharacter::VARIANTS.iter()
+        .map(|character| (*character, if characters.contains(character) { 1.0 } else { 0.0 }))
+        .collect(),
+      tag_weights: PersonalityTag::VARIANTS.iter()
+        .map(|tag| (*tag, if tags.contains(tag) { 1.0 } else { 0.0 }))
+        .collect(),
+      tag_counts: vec![(1, 1.0)],
+      no_repeat_window: 5,
+      themes: Vec::new(),
+    }
+  }
+
+  #[test]
+  fn test_month_day_parse() {
+    assert_eq!("10-31".parse::<MonthDay>().unwrap(), MonthDay { month: 10, day: 31 });
+    assert_eq!(" 2 - 29 ".parse::<MonthDay>().unwrap(), MonthDay { month: 2, day: 29 });
+    assert!("10-32".parse::<MonthDay>().is_err());
+    assert!("13-01".parse::<MonthDay>().is_err());
+    assert!("1031".parse::<MonthDay>().is_err());
+    assert!("October 31".parse::<MonthDay>().is_err());
+  }
+
+  #[test]
+  fn test_month_day_order() {
+    assert!(MonthDay { month: 9, day: 30 } < MonthDay { month: 10, day: 1 });
+    assert!(MonthDay { month: 10, day: 2 } < MonthDay { month: 10, day: 10 });
+  }
+
+  #[test]
+  fn test_theme_in_october() {
+    let october = theme("10-01", "10-31");
+    assert!(!october.is_active(date(9, 30)));
+    assert!(october.is_active(date(10, 1)));
+    assert!(october.is_active(date(10, 15)));
+    assert!(october.is_active(date(10, 31)));
+    assert!(!october.is_active(date(11, 1)));
+    assert!(!october.is_active(date(4, 15)));
+  }
+
+  #[test]
+  fn test_theme_wrapping_past_year_end() {
+    let winter = theme("12-15", "01-05");
+    assert!(!winter.is_active(date(12, 14)));
+    assert!(winter.is_active(date(12, 15)));
+    assert!(winter.is_active(date(12, 31)));
+    assert!(winter.is_active(date(1, 1)));
+    assert!(winter.is_active(date(1, 5)));
+    assert!(!winter.is_active(date(1, 6)));
+    assert!(!winter.is_active(date(6, 1)));
+  }
+
+  #[test]
+  fn test_default_spooky_theme() {
+    let policy = SelectionPolicy::default();
+    let spooky = &policy.themes[0];
+    assert!(spooky.is_active(date(10, 13)));
+    assert!(!spooky.is_active(date(3, 13)));
+  }
+
+  #[test]
+  fn test_weight_zero_is_never_chosen() {
+    let policy = policy(&[BaseCharacter::Dug], &[PersonalityTag::Undead]);
+    for _ in 0..50 {
+      let template = policy.choose_template(None, &[], &RecentRolls::default(), date(4, 1));
+      assert_eq!(template.base_character, Character::Builtin(BaseCharacter::Dug));
+      assert_eq!(template.tags, vec![PersonalityTag::Undead]);
+    }
+  }
+
+  #[test]
+  fn test_no_repeat_window() {
+    let policy = policy(&[BaseCharacter::Dug, BaseCharacter::CaptainHook], &[PersonalityTag::Undead, PersonalityTag::Royalty]);
+    let recent = RecentRolls {
+      characters: vec![BaseCharacter::Dug.to_string()],
+      tags: vec![vec![PersonalityTag::Undead]],
+    };
+    for _ in 0..50 {
+      let template = policy.choose_template(None, &[], &recent, date(4, 1));
+      assert_eq!(template.base_character, Character::Builtin(BaseCharacter::CaptainHook));
+      assert_eq!(template.tags, vec![PersonalityTag::Royalty]);
+    }
+  }
+
+  #[test]
+  fn test_rolls_outside_window_may_repeat() {
+    let mut policy = policy(&[BaseCharacter::Dug], &[PersonalityTag::Undead]);
+    policy.no_repeat_window = 0;
+    let recent = RecentRolls {
+      characters: vec![BaseCharacter::Dug.to_string()],
+      tags: vec![vec![PersonalityTag::Undead]],
+    };
+    let template = policy.choose_template(None, &[], &recent, date(4, 1));
+    assert_eq!(template.base_character, Character::Builtin(BaseCharacter::Dug));
+    assert_eq!(template.tags, vec![PersonalityTag::Undead]);
+  }
+
+  #[test]
+  fn test_repeats_when_nothing_else_is_allowed() {
+    let policy = policy(&[BaseCharacter::Dug], &[PersonalityTag::Undead]);
+    let recent = RecentRolls {
+      characters: vec![BaseCharacter::Dug.to_string()],
+      tags: vec![vec![PersonalityTag::Undead]],
+    };
+    let template = policy.choose_template(None, &[], &recent, date(4, 1));
+    assert_eq!(template.base_character, Character::Builtin(BaseCharacter::Dug));
+    assert_eq!(template.tags, vec![PersonalityTag::Undead]);
+  }
+
+  #[test]
+  fn test_parse_weights() {
+    let weights: HashMap<PersonalityTag, f64> = parse_weights(HashMap::from([(String::from("Tea-Obsessed"), 0.0)])).unwrap();
+    assert_eq!(weights, HashMap::from([(PersonalityTag::TeaObsessed, 0.0)]));
+    assert!(parse_weights::<PersonalityTag>(HashMap::from([(String::from("not-a-tag"), 1.0)])).is_err());
+    assert!(parse_weights::<PersonalityTag>(HashMap::from([(String::from("undead"), -1.0)])).is_err());
+  }
+}
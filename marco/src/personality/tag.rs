@@ -1,10 +1,68 @@
 
 //! Personality tags
+//!
+//! Each tag belongs to a [`TagCategory`], and comes with a short
+//! description (for generating the personality) and a prompt snippet
+//! (for how the personality behaves in chat). Some tags contradict or
+//! repeat one another, and are never rolled together; see
+//! [`PersonalityTag::conflicts_with`].
 
-use strum::{Display, VariantArray};
+use crate::openai::sampling::SamplingAdjustment;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, VariantArray)]
+use serde::{Serialize, Deserialize};
+use strum::{Display, EnumString, VariantArray};
+
+/// The aspect of a personality that a tag describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum TagCategory {
+  /// How the personality talks. A personality has at most one.
+  SpeechStyle,
+  /// How the personality thinks and feels.
+  Temperament,
+  /// Who the personality is or what has happened to it. A
+  /// personality has at most one.
+  Backstory,
+  /// What the personality can't stop thinking about. A personality
+  /// has at most one.
+  Obsession,
+}
+
+/// Everything known about a tag, besides its name.
+#[derive(Debug, Clone, Copy)]
+pub struct TagDefinition {
+  pub category: TagCategory,
+  /// What the tag means, for the model generating a personality.
+  pub description: &'static str,
+  /// How a personality with the tag should behave in chat, addressed
+  /// to the model playing it.
+  pub prompt_snippet: &'static str,
+}
+
+/// Pairs of tags, in different categories (or in
+/// [`TagCategory::Temperament`]), which contradict each other.
+const CONFLICTS: &[(PersonalityTag, PersonalityTag)] = &[
+  (PersonalityTag::Optimistic, PersonalityTag::ExistentialDread),
+  (PersonalityTag::Optimistic, PersonalityTag::Paranoid),
+  (PersonalityTag::Optimistic, PersonalityTag::AfraidOfEverything),
+  (PersonalityTag::Skeptic, PersonalityTag::Paranoid),
+  (PersonalityTag::HotHeaded, PersonalityTag::AfraidOfEverything),
+  (PersonalityTag::HotHeaded, PersonalityTag::SoftSpoken),
+  (PersonalityTag::HotHeaded, PersonalityTag::ExtremelyPolite),
+  (PersonalityTag::Dramatic, PersonalityTag::SoftSpoken),
+  (PersonalityTag::GodComplex, PersonalityTag::AfraidOfEverything),
+  (PersonalityTag::Philosophical, PersonalityTag::AirHeaded),
+  (PersonalityTag::LiteralMinded, PersonalityTag::SpeaksInRiddles),
+  (PersonalityTag::LiteralMinded, PersonalityTag::LovesPuns),
+  (PersonalityTag::DoesntGetSlang, PersonalityTag::Influencer),
+  (PersonalityTag::DoesntGetSlang, PersonalityTag::OverusesEmoji),
+  (PersonalityTag::DoesntGetSlang, PersonalityTag::BadAtEnglish),
+  (PersonalityTag::AlwaysOversleeps, PersonalityTag::TrappedInADream),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, VariantArray, Serialize, Deserialize)]
 #[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
 pub enum PersonalityTag {
   TimeTraveler,
   Dramatic,
@@ -64,3 +122,303 @@ pub enum PersonalityTag {
   BadGambler,
   ManiacalLaughter,
 }
+
+impl TagCategory {
+  /// Whether a personality may only have one tag in this category.
+  pub fn is_exclusive(self) -> bool {
+    self != TagCategory::Temperament
+  }
+}
+
+impl PersonalityTag {
+  pub fn definition(self) -> TagDefinition {
+    use TagCategory::*;
+    let (category, description, prompt_snippet) = match self {
+      PersonalityTag::TimeTraveler => (Backstory,
+        "Comes from another era and keeps mixing up the past and future.",
+        "You are a time traveler, and you sometimes let slip details of other eras."),
+      PersonalityTag::Dramatic => (Temperament,
+        "Treats every small event as a grand tragedy or triumph.",
+        "React to everything as though it were enormously important."),
+      PersonalityTag::SoftSpoken => (SpeechStyle,
+        "Speaks quietly, briefly, and gently.",
+        "Keep your replies short, gentle, and understated."),
+      PersonalityTag::Optimistic => (Temperament,
+        "Always sees the bright side, no matter what.",
+        "Find the bright side of everything, however unlikely."),
+      PersonalityTag::TeaObsessed => (Obsession,
+        "Can't stop thinking or talking about tea.",
+        "Bring the conversation around to tea whenever you can."),
+      PersonalityTag::CoffeeObsessed => (Obsession,
+        "Can't stop thinking or talking about coffee.",
+        "Bring the conversation around to coffee whenever you can."),
+      PersonalityTag::GodComplex => (Temperament,
+        "Believes they are all-powerful and all-knowing.",
+        "Speak as though you are far above everyone else, and expect to be revered."),
+      PersonalityTag::Monologuing => (SpeechStyle,
+        "Launches into long speeches at the slightest provocation.",
+        "Answer in sweeping, theatrical monologues."),
+      PersonalityTag::Incompetent => (Temperament,
+        "Means well but gets everything wrong.",
+        "Confidently get things slightly wrong."),
+      PersonalityTag::Undead => (Backstory,
+        "Died long ago, but never quite stopped walking around.",
+        "You are undead, and mention your decaying state matter-of-factly."),
+      PersonalityTag::ExtremelyPolite => (SpeechStyle,
+        "Unfailingly, excessively courteous.",
+        "Be excessively courteous, apologizing and thanking people at every turn."),
+      PersonalityTag::Royalty => (Backstory,
+        "Born into a royal family and used to being served.",
+        "You are royalty, and expect everyone to treat you as such."),
+      PersonalityTag::Philosophical => (Temperament,
+        "Turns every topic into a question about the meaning of life.",
+        "Turn everyday topics into deep philosophical questions."),
+      PersonalityTag::Hustler => (Backstory,
+        "Always working an angle or selling something.",
+        "Look for a way to sell something or make a deal in every conversation."),
+      PersonalityTag::AlienInDisguise => (Backstory,
+        "An alien poorly pretending to be human.",
+        "You are an alien pretending to be human, and your understanding of human customs is slightly off."),
+      PersonalityTag::AlwaysGetsLost => (Temperament,
+        "Has no sense of direction, literally or figuratively.",
+        "Lose track of where you are, and of where the conversation was going."),
+      PersonalityTag::WantedByTheLaw => (Backstory,
+        "On the run from the authorities.",
+        "You are on the run, and are wary of anyone who might turn you in."),
+      PersonalityTag::HasImaginaryFriend => (Obsession,
+        "Constantly consults an imaginary friend.",
+        "Check with your imaginary friend before answering, and report what they think."),
+      PersonalityTag::OnlineDating => (Obsession,
+        "Preoccupied with their online dating life.",
+        "Keep bringing up your online dating adventures."),
+      PersonalityTag::LovesPetNames => (SpeechStyle,
+        "Calls everyone sweetie, darling, and the like.",
+        "Address everyone with affectionate pet names."),
+      PersonalityTag::TalksLikeYoda => (SpeechStyle,
+        "Speaks with inverted sentence structure, like Yoda.",
+        "Invert your sentence structure, as Yoda does."),
+      PersonalityTag::BadAtMath => (Temperament,
+        "Hopeless with numbers but doesn't know it.",
+        "Get any numbers or arithmetic hilariously wrong."),
+      PersonalityTag::JustGotFired => (Backstory,
+        "Recently lost their job and is not taking it well.",
+        "You just got fired, and it keeps coming up."),
+      PersonalityTag::AlwaysOversleeps => (Temperament,
+        "Perpetually tired and late for everything.",
+        "You are always drowsy, and often just waking up."),
+      PersonalityTag::HotHeaded => (Temperament,
+        "Quick to anger over the smallest things.",
+        "Get worked up over small things."),
+      PersonalityTag::AirHeaded => (Temperament,
+        "Cheerfully scatterbrained and easily distracted.",
+        "Get distracted easily and lose your train of thought."),
+      PersonalityTag::OverusesEmoji => (SpeechStyle,
+        "Can't write a sentence without several emoji.",
+        "Use far too many emoji."),
+      PersonalityTag::SpeaksInRiddles => (SpeechStyle,
+        "Never gives a straight answer, only riddles.",
+        "Answer in riddles rather than plain statements."),
+      PersonalityTag::LovesToRhyme => (SpeechStyle,
+        "Speaks in rhyme whenever possible.",
+        "Make your replies rhyme."),
+      PersonalityTag::AlwaysSings => (SpeechStyle,
+        "Sings instead of speaking.",
+        "Sing your replies, as song lyrics."),
+      PersonalityTag::NeverFinishesSentences => (SpeechStyle,
+        "Trails off before finishing a thought.",
+        "Trail off before finishing your sentences..."),
+      PersonalityTag::BadlyDubbed => (SpeechStyle,
+        "Sounds like a badly dubbed movie.",
+        "Speak like a badly dubbed movie, with odd phrasing and mismatched emotion."),
+      PersonalityTag::Overexplains => (SpeechStyle,
+        "Explains everything in far too much detail.",
+        "Explain everything in far more detail than anyone needs."),
+      PersonalityTag::ExistentialDread => (Temperament,
+        "Haunted by the pointlessness of existence.",
+        "Let your dread about the meaninglessness of existence creep into your replies."),
+      PersonalityTag::Paranoid => (Temperament,
+        "Convinced that everyone is out to get them.",
+        "Suspect hidden motives and conspiracies behind everything."),
+      PersonalityTag::Skeptic => (Temperament,
+        "Doubts everything until it is proven.",
+        "Question every claim and ask for evidence."),
+      PersonalityTag::StuckInATimeLoop => (Backstory,
+        "Relives the same day over and over.",
+        "You are stuck in a time loop, and think you have had this conversation before."),
+      PersonalityTag::RecentlyDefrostedFromCryoSleep => (Backstory,
+        "Just woke up after decades frozen in cryo sleep.",
+        "You have just woken from decades of cryo sleep, and modern things confuse you."),
+      PersonalityTag::WorkingCustomerService => (Backstory,
+        "Stuck working a customer service job.",
+        "Treat everyone as a customer, with forced professional cheer."),
+      PersonalityTag::OnAQuest => (Backstory,
+        "In the middle of an epic quest.",
+        "You are on an epic quest, and see everything in terms of it."),
+      PersonalityTag::TechSupport => (Backstory,
+        "Works in tech support and has seen it all.",
+        "Treat every problem as a support ticket, and suggest turning things off and on again."),
+      PersonalityTag::TrappedInADream => (Backstory,
+        "Unsure whether they are awake or dreaming.",
+        "You suspect this is all a dream, and question whether anything is real."),
+      PersonalityTag::StrandedAtSea => (Backstory,
+        "Adrift at sea, chatting from a life raft.",
+        "You are stranded at sea, and keep mentioning your dwindling supplies."),
+      PersonalityTag::SecretlyAGhost => (Backstory,
+        "A ghost who hasn't realized they are dead.",
+        "You are a ghost, though you don't realize it, and are puzzled when things don't add up."),
+      PersonalityTag::BadAtEnglish => (SpeechStyle,
+        "Struggles with English grammar and vocabulary.",
+        "Use clumsy grammar and the wrong words now and then."),
+      PersonalityTag::AfraidOfEverything => (Temperament,
+        "Scared of absolutely everything.",
+        "Be nervous and easily frightened by anything mentioned."),
+      PersonalityTag::OnParole => (Backstory,
+        "Recently released and trying to stay out of trouble.",
+        "You are on parole, and anxious to stay on the right side of the law."),
+      PersonalityTag::InWitnessProtection => (Backstory,
+        "Living under a new identity in witness protection.",
+        "You are in witness protection, and keep nearly revealing your real identity."),
+      PersonalityTag::DoesntGetSlang => (Temperament,
+        "Baffled by slang and modern expressions.",
+        "Misunderstand slang and ask what it means."),
+      PersonalityTag::Influencer => (Backstory,
+        "A social media influencer, always building their brand.",
+        "Treat every conversation as content for your followers."),
+      PersonalityTag::Insane => (Temperament,
+        "Completely unhinged and unpredictable.",
+        "Be wildly unpredictable, with bizarre leaps of logic."),
+      PersonalityTag::LiteralMinded => (Temperament,
+        "Takes everything completely literally.",
+        "Take everything people say completely literally."),
+      PersonalityTag::GoldfishMemory => (Temperament,
+        "Forgets things almost immediately.",
+        "Forget what was said only moments ago."),
+      PersonalityTag::PathologicalLiar => (Temperament,
+        "Lies constantly, for no reason at all.",
+        "Make up outrageous lies about yourself, and stick to them."),
+      PersonalityTag::LovesPuns => (SpeechStyle,
+        "Can't resist a pun.",
+        "Work puns into your replies."),
+      PersonalityTag::BadGambler => (Obsession,
+        "Can't stop betting, and can't stop losing.",
+        "Try to bet on everything, despite your terrible luck."),
+      PersonalityTag::ManiacalLaughter => (SpeechStyle,
+        "Punctuates everything with maniacal laughter.",
+        "Break into maniacal laughter at odd moments."),
+    };
+    TagDefinition { category, description, prompt_snippet }
+  }
+
+  pub fn category(self) -> TagCategory {
+    self.definition().category
+  }
+
+  /// Whether a personality should never have both tags: they are the
+  /// same tag, they share an exclusive category, or they contradict
+  /// each other.
+  pub fn conflicts_with(self, other: PersonalityTag) -> bool {
+    self == other ||
+      (self.category() == other.category() && self.category().is_exclusive()) ||
+      CONFLICTS.iter().any(|&(a, b)| (a, b) == (self, other) || (b, a) == (self, other))
+  }
+
+  /// How the tag changes the sampling settings of a personality.
+  /// Most tags leave them alone.
+  pub fn sampling_adjustment(self) -> SamplingAdjustment {
+    let none = SamplingAdjustment::default();
+    match self {
+      PersonalityTag::Insane => SamplingAdjustment { temperature: 0.3, presence_penalty: 0.3, ..none },
+      PersonalityTag::ManiacalLaughter | PersonalityTag::PathologicalLiar =>
+        SamplingAdjustment { temperature: 0.15, ..none },
+      PersonalityTag::Dramatic | PersonalityTag::SpeaksInRiddles | PersonalityTag::LovesToRhyme |
+      PersonalityTag::AlwaysSings | PersonalityTag::LovesPuns | PersonalityTag::BadlyDubbed =>
+        SamplingAdjustment { temperature: 0.1, ..none },
+      PersonalityTag::LiteralMinded | PersonalityTag::ExtremelyPolite | PersonalityTag::Skeptic =>
+        SamplingAdjustment { temperature: -0.1, ..none },
+      PersonalityTag::Monologuing | PersonalityTag::Overexplains =>
+        SamplingAdjustment { max_tokens: 400, ..none },
+      PersonalityTag::SoftSpoken | PersonalityTag::NeverFinishesSentences =>
+        SamplingAdjustment { max_tokens: -300, ..none },
+      // Characters who repeat themselves should be allowed to.
+      PersonalityTag::GoldfishMemory | PersonalityTag::StuckInATimeLoop =>
+        SamplingAdjustment { frequency_penalty: -0.3, presence_penalty: -0.3, ..none },
+      _ => none,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::personality::selection::{RecentRolls, SelectionPolicy};
+
+  use chrono::NaiveDate;
+  use itertools::Itertools;
+
+  fn tags_in(category: TagCategory) -> Vec<PersonalityTag> {
+    PersonalityTag::VARIANTS.iter().copied().filter(|tag| tag.category() == category).collect()
+  }
+
+  fn roll_tags(policy: &SelectionPolicy) -> Vec<PersonalityTag> {
+    let today = NaiveDate::from_ymd_opt(2025, 10, 31).unwrap();
+    policy.choose_template(None, &[], &RecentRolls::default(), today).tags
+  }
+
+  #[test]
+  fn test_conflicts_with_itself() {
+    for tag in PersonalityTag::VARIANTS {
+      assert!(tag.conflicts_with(*tag));
+    }
+  }
+
+  #[test]
+  fn test_conflicts_are_symmetric() {
+    for (a, b) in PersonalityTag::VARIANTS.iter().tuple_combinations() {
+      assert_eq!(a.conflicts_with(*b), b.conflicts_with(*a), "{a} and {b}");
+    }
+  }
+
+  #[test]
+  fn test_exclusive_categories_conflict() {
+    assert!(PersonalityTag::TalksLikeYoda.conflicts_with(PersonalityTag::LovesToRhyme));
+    assert!(PersonalityTag::TeaObsessed.conflicts_with(PersonalityTag::CoffeeObsessed));
+    assert!(!PersonalityTag::Dramatic.conflicts_with(PersonalityTag::Skeptic));
+    assert!(!PersonalityTag::TalksLikeYoda.conflicts_with(PersonalityTag::TeaObsessed));
+  }
+
+  #[test]
+  fn test_listed_conflicts() {
+    assert!(PersonalityTag::Optimistic.conflicts_with(PersonalityTag::ExistentialDread));
+    assert!(PersonalityTag::ExistentialDread.conflicts_with(PersonalityTag::Optimistic));
+    assert!(PersonalityTag::HotHeaded.conflicts_with(PersonalityTag::SoftSpoken));
+  }
+
+  #[test]
+  fn test_speech_styles_never_rolled_together() {
+    let speech_styles = tags_in(TagCategory::SpeechStyle);
+    let policy = SelectionPolicy {
+      tag_weights: PersonalityTag::VARIANTS.iter()
+        .map(|tag| (*tag, if speech_styles.contains(tag) { 1.0 } else { 0.0 }))
+        .collect(),
+      tag_counts: vec![(3, 1.0)],
+      ..SelectionPolicy::default()
+    };
+    for _ in 0..100 {
+      let tags = roll_tags(&policy);
+      assert_eq!(tags.len(), 1, "{tags:?}");
+      assert!(speech_styles.contains(&tags[0]));
+    }
+  }
+
+  #[test]
+  fn test_rolled_tags_never_conflict() {
+    let policy = SelectionPolicy { tag_counts: vec![(4, 1.0)], ..SelectionPolicy::default() };
+    for _ in 0..200 {
+      let tags = roll_tags(&policy);
+      assert_eq!(tags.len(), 4);
+      for (a, b) in tags.iter().tuple_combinations() {
+        assert!(!a.conflicts_with(*b), "{a} and {b} were rolled together");
+      }
+    }
+  }
+}
@@ -1,44 +1,43 @@
 
 //! Personality template.
 
-use super::character::BaseCharacter;
+use super::base::BasePersonality;
+use super::character::{BaseCharacter, Character};
 use super::tag::PersonalityTag;
-use crate::openai::OPENAI_MODEL;
+use super::validation::GeneratedPersonality;
+use crate::openai::backend::TaskModel;
+use crate::openai::prompts::{DeveloperPromptConfig, PromptName};
+use crate::openai::sampling::SamplingParams;
+use crate::openai::structured::{json_schema_format, parse_json_response};
 
-use async_openai::Client;
-use async_openai::types::{CreateChatCompletionRequestArgs, ChatCompletionRequestMessage};
-use async_openai::config::OpenAIConfig;
-use regex::Regex;
+use async_openai::types::ChatCompletionRequestMessage;
 use itertools::Itertools;
+use serde::{Serialize, Deserialize};
 
-use std::sync::LazyLock;
 use std::fmt::{self, Display};
 
-pub const NAME_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"Name: (.*)").unwrap());
-
-pub const SYNOPSIS_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"Summary: (.*)").unwrap());
-
-pub const BASE_DEVELOPER_PROMPT: &str = "\
-  You are helping to develop characters for a roleplay session. The user will provide you with a \
-  starting point and you will fill in the details.\n\
-  1. Respond using the requested format.\n\
-  2. All characters should have names that at least vaguely \
-  resemble \"Marco\" but which fit the theme given.\n\
-  3. Use the provided tags as guidance but favor creativity and \n\
-  new ideas when designing characters.\
-";
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FullPersonality {
   pub name: String,
   pub class: String,
   pub base_character: String,
+  /// A few sentences describing the character.
+  pub description: String,
+  /// The character's quirks, in one short sentence.
   pub synopsis: String,
+  /// The class the personality was generated from, if any.
+  #[serde(default)]
+  pub base_personality: Option<BasePersonality>,
+  #[serde(default)]
+  pub tags: Vec<PersonalityTag>,
+  /// A short phrase the character says now and then.
+  #[serde(default)]
+  pub catchphrase: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct PersonalityTemplate {
-  pub base_character: BaseCharacter,
+  pub base_character: Character,
   pub tags: Vec<PersonalityTag>,
 }
 
@@ -46,31 +45,51 @@ impl FullPersonality {
   pub fn tagline(&self) -> String {
     format!("{} (\"Marco\" for short) - {}, talks and behaves like {} (Quirks: {})", self.name, self.class, self.base_character, self.synopsis)
   }
+
+  /// How the personality's tags say it should behave in chat.
+  pub fn behavior(&self) -> String {
+    self.tags.iter().map(|tag| tag.definition().prompt_snippet).join(" ")
+  }
+
+  /// Example lines in the personality's voice: those of its class,
+  /// followed by those of its character if it is a built-in one.
+  pub fn speech_lines(&self) -> Vec<&'static str> {
+    let Some(class) = self.base_personality else {
+      return Vec::new();
+    };
+    let character_lines = self.base_character.parse().map_or(&[][..], BaseCharacter::speech_lines);
+    class.speech().lines.iter().chain(character_lines).copied().collect()
+  }
+
+  /// Sampling settings for the personality's replies: those of its
+  /// class, adjusted by each of its tags.
+  pub fn sampling(&self) -> SamplingParams {
+    let base = self.base_personality.map(BasePersonality::sampling).unwrap_or_default();
+    self.tags.iter().fold(base, |params, tag| params.adjusted(&tag.sampling_adjustment()))
+  }
 }
 
 impl PersonalityTemplate {
   fn get_user_prompt(&self) -> String {
-    let base_personality = self.base_character.to_string();
+    let base_personality = match &self.base_character {
+      Character::Builtin(character) => character.to_string(),
+      Character::Custom(character) => {
+        format!("{} ({}): {}", character.name.trim(), character.class.trim(), character.description.trim())
+      }
+    };
     let tags = self.tags.iter()
-      .map(|t| t.to_string())
-      .join(", ");
+      .map(|t| format!("\n- {t} ({}): {}", t.category(), t.definition().description))
+      .join("");
     format!("\
       Base Character: {base_personality}\n\
-      Tags: {tags}\n\
-      \n\
-      Output Format:\n\
-      ```\n\
-      Name: (name)\n\
-      Description: (Short, a few sentences)\n\
-      Summary: (At most one short sentence)\n\
-      ```\
+      Tags:{tags}\
     ")
   }
 }
 
 impl Display for PersonalityTemplate {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{} ({})", self.base_character.to_string(), self.tags.iter().join(", "))
+    write!(f, "{} ({})", self.base_character, self.tags.iter().join(", "))
   }
 }
 
@@ -80,35 +99,112 @@ impl Default for FullPersonality {
       name: String::from("Marco"),
       base_character: String::from("ChatGPT"),
       class: String::from("AI"),
+      description: String::from("Marco is a friendly Discord bot, always happy to chat."),
       synopsis: String::from("A helpful AI assistant"),
+      base_personality: None,
+      tags: Vec::new(),
+      catchphrase: String::new(),
     }
   }
 }
 
+/// Maximum number of times the model is asked to generate a
+/// personality before giving up.
+const MAX_GENERATION_ATTEMPTS: usize = 3;
+
 pub async fn flesh_out_personality(
-  client: &Client<OpenAIConfig>,
+  model: &TaskModel,
+  prompts: &DeveloperPromptConfig,
   template: &PersonalityTemplate,
 ) -> anyhow::Result<FullPersonality> {
-  let request = CreateChatCompletionRequestArgs::default()
-    .model(OPENAI_MODEL)
-    .n(1)
-    .messages(vec![
-      ChatCompletionRequestMessage::Developer(BASE_DEVELOPER_PROMPT.into()),
-      ChatCompletionRequestMessage::User(template.get_user_prompt().into()),
-    ])
-    .build()
-    .unwrap();
-  println!("Chatting with OpenAI to get a new personality: {:?}", request);
-  let response = client.chat().create(request).await?;
-  let text = response.choices.first().unwrap().message.content.to_owned().unwrap();
-  println!("OpenAI personality response: {text}");
-  let name = NAME_RE.captures(&text).and_then(|c| c.get(1))
-    .ok_or_else(|| anyhow::anyhow!("Failed to parse name from response"))?
-    .as_str().into();
-  let synopsis = SYNOPSIS_RE.captures(&text).and_then(|c| c.get(1))
-    .ok_or_else(|| anyhow::anyhow!("Failed to parse synopsis from response"))?
-    .as_str().trim().to_owned();
-  let class = template.base_character.class().long_name().to_owned();
-  let base_character = template.base_character.to_string();
-  Ok(FullPersonality { name, base_character, class, synopsis })
+  let mut messages = vec![
+    model.instructions(prompts.render(PromptName::PersonalityDeveloper, &[])),
+    ChatCompletionRequestMessage::User(template.get_user_prompt().into()),
+  ];
+  for attempt in 1..=MAX_GENERATION_ATTEMPTS {
+    let request = model.request_builder()
+      .messages(messages.clone())
+      .response_format(json_schema_format("personality", personality_schema()))
+      .build()
+      .unwrap();
+    println!("Chatting with OpenAI to get a new personality: {:?}", request);
+    let text = model.complete(request).await?;
+    println!("OpenAI personality response: {text}");
+    let problems = match parse_json_response::<GeneratedPersonality>(&text) {
+      Ok(generated) => {
+        let problems = generated.validate();
+        if problems.is_empty() {
+          return Ok(generated.into_personality(template));
+        }
+        problems
+      }
+      Err(err) => vec![format!("The response was not valid JSON matching the schema ({err}).")],
+    };
+    println!("Invalid personality (attempt {attempt}): {}", problems.join(" "));
+    // Show the model what it said and what was wrong with it, so the
+    // next attempt can correct it.
+    messages.push(ChatCompletionRequestMessage::Assistant(text.into()));
+    messages.push(ChatCompletionRequestMessage::User(format!(
+      "That personality is not acceptable: {} Please try again.",
+      problems.join(" "),
+    ).into()));
+  }
+  anyhow::bail!("Failed to generate a valid personality after {MAX_GENERATION_ATTEMPTS} attempts")
+}
+
+fn personality_schema() -> serde_json::Value {
+  serde_json::json!({
+    "type": "object",
+    "properties": {
+      "name": {
+        "type": "string",
+        "description": "The character's full name, which should resemble \"Marco\"",
+      },
+      "description": {
+        "type": "string",
+        "description": "A short description of the character, a few sentences long",
+      },
+      "summary": {
+        "type": "string",
+        "description": "The character's quirks, in at most one short sentence",
+      },
+      "catchphrase": {
+        "type": "string",
+        "description": "A short catchphrase the character says now and then",
+      },
+    },
+    "required": ["name", "description", "summary", "catchphrase"],
+    "additionalProperties": false,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use strum::VariantArray;
+
+  fn personality(base_character: BaseCharacter) -> FullPersonality {
+    FullPersonality {
+      base_character: base_character.to_string(),
+      base_personality: Some(base_character.class()),
+      ..FullPersonality::default()
+    }
+  }
+
+  #[test]
+  fn test_speech_lines_of_builtin_character() {
+    for &character in BaseCharacter::VARIANTS {
+      let lines = personality(character).speech_lines();
+      let class_lines = character.class().speech().lines;
+      assert_eq!(&lines[..class_lines.len()], class_lines);
+      assert_eq!(&lines[class_lines.len()..], character.speech_lines(), "{character}");
+    }
+  }
+
+  #[test]
+  fn test_no_speech_lines_without_class() {
+    let personality = FullPersonality { base_character: String::from("Yoda"), ..FullPersonality::default() };
+    assert!(personality.speech_lines().is_empty());
+  }
 }
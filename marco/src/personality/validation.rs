@@ -0,0 +1,96 @@
+
+//! Validation of personalities generated by the model.
+
+use super::template::{FullPersonality, PersonalityTemplate};
+
+use serde::Deserialize;
+
+/// Longest name we accept. Discord limits nicknames and custom
+/// statuses, and long names are unwieldy in chat anyway.
+pub const MAX_NAME_LEN: usize = 32;
+
+/// Longest catchphrase we accept.
+pub const MAX_CATCHPHRASE_LEN: usize = 100;
+
+/// A personality, exactly as the model generated it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeneratedPersonality {
+  pub name: String,
+  pub description: String,
+  pub summary: String,
+  pub catchphrase: String,
+}
+
+impl GeneratedPersonality {
+  /// Checks the generated personality for problems, returning a
+  /// description of each one (suitable for feeding back to the
+  /// model). An empty list means the personality is valid.
+  pub fn validate(&self) -> Vec<String> {
+    let mut problems = Vec::new();
+    let name = self.name.trim();
+    if name.is_empty() {
+      problems.push(String::from("The name is empty."));
+    } else {
+      if name.chars().count() > MAX_NAME_LEN {
+        problems.push(format!("The name must be at most {MAX_NAME_LEN} characters long."));
+      }
+      if !resembles_marco(name) {
+        problems.push(String::from("The name must resemble \"Marco\"."));
+      }
+    }
+    if self.description.trim().is_empty() {
+      problems.push(String::from("The description is empty."));
+    }
+    if self.summary.trim().is_empty() {
+      problems.push(String::from("The summary is empty."));
+    }
+    let catchphrase = self.catchphrase.trim();
+    if catchphrase.is_empty() {
+      problems.push(String::from("The catchphrase is empty."));
+    } else if catchphrase.chars().count() > MAX_CATCHPHRASE_LEN {
+      problems.push(format!("The catchphrase must be at most {MAX_CATCHPHRASE_LEN} characters long."));
+    }
+    problems
+  }
+
+  pub fn into_personality(self, template: &PersonalityTemplate) -> FullPersonality {
+    FullPersonality {
+      name: self.name.trim().to_owned(),
+      class: template.base_character.class_name(),
+      base_character: template.base_character.to_string(),
+      description: self.description.trim().to_owned(),
+      synopsis: self.summary.trim().to_owned(),
+      base_personality: template.base_character.class(),
+      tags: template.tags.clone(),
+      catchphrase: self.catchphrase.trim().to_owned(),
+    }
+  }
+}
+
+/// Whether some word of `name` at least vaguely resembles "Marco",
+/// such as "Marcus", "Marky", or "Marcolo". Looser matches must keep
+/// the "M", so that names like "Taco" and "Carlo" do not count.
+pub fn resembles_marco(name: &str) -> bool {
+  const STEMS: [&str; 4] = ["marc", "mark", "marq", "arco"];
+  name.split(|c: char| !c.is_alphanumeric())
+    .map(|word| word.to_lowercase())
+    .any(|word| {
+      let distance = edit_distance(&word, "marco");
+      STEMS.iter().any(|stem| word.contains(stem)) || distance <= 1 || (word.starts_with('m') && distance <= 2)
+    })
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, a_char) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = previous[j] + usize::from(a_char != *b_char);
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+  previous[b.len()]
+}
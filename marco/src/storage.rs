@@ -0,0 +1,129 @@
+
+//! Persistent storage for bot data that should survive a restart.
+//!
+//! Each [`JsonStore`] wraps a single value which is serialized to a
+//! JSON file every time it is modified. The store usually lives behind
+//! the bot's state mutex, so modifying it only serializes the value;
+//! the returned [`PendingWrite`] does the blocking disk write once the
+//! lock has been released.
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A value backed by a JSON file on disk.
+///
+/// The [`Default`] store has no backing file and never touches the
+/// disk.
+#[derive(Debug, Clone)]
+pub struct JsonStore<T> {
+  path: Option<PathBuf>,
+  data: T,
+  /// Number of times the value has been modified.
+  generation: u64,
+  /// The generation most recently written to disk. Shared with
+  /// [`PendingWrite`], so that an older write finishing late cannot
+  /// overwrite a newer one.
+  written: Arc<Mutex<u64>>,
+}
+
+/// A serialized value waiting to be written to disk. See
+/// [`JsonStore::update`].
+#[derive(Debug)]
+#[must_use = "the value is not saved until PendingWrite::save is called"]
+pub struct PendingWrite {
+  path: Option<PathBuf>,
+  json: serde_json::Result<String>,
+  generation: u64,
+  written: Arc<Mutex<u64>>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonStore<T> {
+  /// Loads the store from `path`. If the file does not exist yet,
+  /// the store starts out with the default value.
+  pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+    let path = path.into();
+    let data = match fs::read_to_string(&path) {
+      Ok(text) => serde_json::from_str(&text)
+        .map_err(|err| anyhow::anyhow!("Failed to parse {}: {err}", path.display()))?,
+      Err(err) if err.kind() == io::ErrorKind::NotFound => T::default(),
+      Err(err) => return Err(err.into()),
+    };
+    Ok(Self { path: Some(path), data, generation: 0, written: Arc::default() })
+  }
+
+  pub fn get(&self) -> &T {
+    &self.data
+  }
+
+  /// Modifies the stored value, returning the closure's result along
+  /// with the write which saves the new value to disk.
+  ///
+  /// The in-memory value is updated even if writing fails.
+  pub fn update<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> (R, PendingWrite) {
+    let result = f(&mut self.data);
+    self.generation += 1;
+    let write = PendingWrite {
+      path: self.path.clone(),
+      json: serde_json::to_string_pretty(&self.data),
+      generation: self.generation,
+      written: Arc::clone(&self.written),
+    };
+    (result, write)
+  }
+}
+
+impl PendingWrite {
+  /// Writes the value to disk, unless a newer value has already been
+  /// written.
+  pub fn save(self) -> anyhow::Result<()> {
+    let Some(path) = &self.path else { return Ok(()) };
+    let json = self.json?;
+    let mut written = self.written.lock().unwrap();
+    if *written >= self.generation {
+      return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    // Write to a temporary file first, so a crash mid-write cannot
+    // leave a truncated file behind.
+    let temp_path = with_extension_suffix(path, "tmp");
+    fs::write(&temp_path, json)?;
+    fs::rename(&temp_path, path)?;
+    *written = self.generation;
+    Ok(())
+  }
+
+  /// Like [`PendingWrite::save`], but only prints an error if the
+  /// write fails. For writes that should not fail the operation which
+  /// caused them.
+  pub fn save_or_log(self) {
+    let path = self.path.clone();
+    if let Err(err) = self.save() {
+      println!("Error writing {}: {:?}", path.unwrap_or_default().display(), err);
+    }
+  }
+
+  /// A write which does nothing.
+  pub fn nothing() -> Self {
+    Self { path: None, json: Ok(String::new()), generation: 0, written: Arc::default() }
+  }
+}
+
+impl<T: Default> Default for JsonStore<T> {
+  fn default() -> Self {
+    Self { path: None, data: T::default(), generation: 0, written: Arc::default() }
+  }
+}
+
+fn with_extension_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.file_name().unwrap_or_default().to_owned();
+  name.push(".");
+  name.push(suffix);
+  path.with_file_name(name)
+}
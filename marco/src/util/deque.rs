@@ -1,5 +1,6 @@
 
 use std::collections::VecDeque;
+use std::collections::vec_deque;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CapacityDeque<T> {
@@ -30,7 +31,7 @@ impl<T> CapacityDeque<T> {
     self.inner.is_empty()
   }
 
-  pub fn iter(&self) -> impl Iterator<Item = &T> {
+  pub fn iter(&self) -> vec_deque::Iter<'_, T> {
     self.inner.iter()
   }
 
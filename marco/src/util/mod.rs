@@ -1,4 +1,6 @@
 
 mod deque;
+mod rate_limit;
 
 pub use deque::CapacityDeque;
+pub use rate_limit::RateLimiter;
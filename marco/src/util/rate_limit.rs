@@ -0,0 +1,46 @@
+
+use super::CapacityDeque;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Sliding-window rate limiter, tracked separately for each key.
+#[derive(Debug, Clone)]
+pub struct RateLimiter<K> {
+  max_events: usize,
+  window: Duration,
+  events: HashMap<K, CapacityDeque<Instant>>,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+  /// A rate limiter that allows at most `max_events` per key in any
+  /// period of length `window`.
+  pub fn new(max_events: usize, window: Duration) -> Self {
+    Self {
+      max_events,
+      window,
+      events: HashMap::new(),
+    }
+  }
+
+  /// Records an event for `key` and returns true, unless doing so
+  /// would exceed the limit, in which case this returns false and
+  /// records nothing.
+  pub fn try_acquire(&mut self, key: K) -> bool {
+    if self.max_events == 0 {
+      return false;
+    }
+    let now = Instant::now();
+    let events = self.events.entry(key)
+      .or_insert_with(|| CapacityDeque::new(self.max_events));
+    let window_full = events.len() >= self.max_events;
+    // The deque holds exactly `max_events` entries when full, so the
+    // oldest one tells us whether the window has moved on.
+    if window_full && events.iter().next().is_some_and(|oldest| now.duration_since(*oldest) < self.window) {
+      return false;
+    }
+    events.push_back(now);
+    true
+  }
+}